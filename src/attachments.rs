@@ -0,0 +1,161 @@
+//! Builds the single-prompt message from the `-p` argument, piped stdin and `--file`
+//! attachments.
+
+use std::io::{IsTerminal, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use crate::file_edit::{resolve_sandbox_path, MAX_FILE_SIZE};
+
+/// How long `-p "..."` waits for piped input to start. Parents such as Node's
+/// `child_process.spawn` or `ssh` hand over a pipe that never closes, and reading it
+/// to EOF would hang.
+const STDIN_WAIT: Duration = Duration::from_secs(1);
+
+/// Set once `single_prompt` has read piped stdin, leaving nothing to answer prompts with.
+static STDIN_CONSUMED: AtomicBool = AtomicBool::new(false);
+
+/// Whether standard input went into the prompt, so confirmations can't be read from it.
+pub(crate) fn stdin_consumed() -> bool {
+    STDIN_CONSUMED.load(Ordering::Relaxed)
+}
+
+/// Whether `fd` has data (or EOF) to read within `timeout`.
+#[cfg(unix)]
+fn fd_ready(fd: std::os::unix::io::RawFd, timeout: Duration) -> bool {
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    // SAFETY: poll only reads and writes the single pollfd passed to it.
+    unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) > 0 }
+}
+
+/// Whether non-terminal stdin has something to attach; without `poll`, assume it does.
+fn stdin_ready(timeout: Duration) -> bool {
+    #[cfg(unix)]
+    return fd_ready(libc::STDIN_FILENO, timeout);
+    #[cfg(not(unix))]
+    {
+        let _ = timeout;
+        true
+    }
+}
+
+/// Reads all of `reader`, refusing more than `MAX_FILE_SIZE` bytes.
+fn read_limited(reader: impl Read, source: &str) -> Result<String> {
+    let mut bytes = Vec::new();
+    reader.take(MAX_FILE_SIZE + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_FILE_SIZE {
+        return Err(anyhow!("{} is too large (max is {} bytes)", source, MAX_FILE_SIZE));
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow!("{} is not valid UTF-8 text", source))
+}
+
+/// Reads a sandbox file for `--file`, with the same path checks as `file_editor`.
+fn read_attachment(filename: &str) -> Result<String> {
+    let path = resolve_sandbox_path(filename).map_err(|e| anyhow!(e))?;
+    let metadata = std::fs::metadata(&path).map_err(|e| anyhow!("Failed to read '{}': {}", filename, e))?;
+    if !metadata.is_file() {
+        return Err(anyhow!("'{}' is not a file", filename));
+    }
+    let file = std::fs::File::open(&path).map_err(|e| anyhow!("Failed to read '{}': {}", filename, e))?;
+    read_limited(file, &format!("File '{}'", filename))
+}
+
+/// Wraps `content` in a code fence longer than any backtick run inside it.
+fn fenced(label: &str, content: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in content.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat((longest + 1).max(3));
+    format!("{}:\n{}\n{}\n{}", label, fence, content.trim_end_matches('\n'), fence)
+}
+
+/// Joins the prompt with piped stdin and attached files into the first user message.
+fn compose(prompt: &str, stdin: Option<&str>, files: &[(String, String)]) -> String {
+    let mut parts = vec![prompt.trim().to_string()];
+    if let Some(stdin) = stdin.filter(|s| !s.trim().is_empty()) {
+        parts.push(fenced("Piped input", stdin));
+    }
+    for (name, content) in files {
+        parts.push(fenced(&format!("File `{}`", name), content));
+    }
+    parts.join("\n\n")
+}
+
+/// The message for `-p`. `-p -` takes the whole prompt from stdin; otherwise piped
+/// stdin is attached after the prompt if it starts arriving within `STDIN_WAIT`,
+/// followed by each `--file`.
+pub(crate) fn single_prompt(prompt: &str, files: &[String]) -> Result<String> {
+    let stdin = std::io::stdin();
+    let piped = if stdin.is_terminal() || (prompt != "-" && !stdin_ready(STDIN_WAIT)) {
+        None
+    } else {
+        STDIN_CONSUMED.store(true, Ordering::Relaxed);
+        Some(read_limited(stdin.lock(), "Standard input")?)
+    };
+
+    let attachments = files
+        .iter()
+        .map(|name| Ok((name.clone(), read_attachment(name)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    if prompt == "-" {
+        let prompt = piped.filter(|p| !p.trim().is_empty()).ok_or_else(|| anyhow!("-p - expects the prompt on standard input"))?;
+        return Ok(compose(&prompt, None, &attachments));
+    }
+    Ok(compose(prompt, piped.as_deref(), &attachments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_attaches_stdin_and_files() {
+        let files = vec![("notes.md".to_string(), "# Notes\n".to_string())];
+        let message = compose("explain this failure\n", Some("error: boom\n"), &files);
+        assert_eq!(
+            message,
+            "explain this failure\n\nPiped input:\n```\nerror: boom\n```\n\nFile `notes.md`:\n```\n# Notes\n```"
+        );
+        assert_eq!(compose("hi", Some("  \n"), &[]), "hi");
+    }
+
+    #[test]
+    fn test_fence_is_longer_than_backticks_in_content() {
+        let block = fenced("Piped input", "```rust\nfn main() {}\n```");
+        assert!(block.starts_with("Piped input:\n````\n"));
+        assert!(block.ends_with("\n````"));
+    }
+
+    #[test]
+    fn test_attachment_checks_sandbox_and_size() {
+        assert!(read_attachment("../../etc/passwd").is_err());
+        assert!(read_attachment("src").unwrap_err().to_string().contains("not a file"));
+        assert!(read_attachment("Cargo.toml").unwrap().contains("[package]"));
+        let big = std::io::repeat(b'a').take(MAX_FILE_SIZE + 1);
+        assert!(read_limited(big, "Input").unwrap_err().to_string().contains("too large"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_open_empty_pipe_is_not_ready() {
+        let mut fds = [0; 2];
+        // SAFETY: pipe fills in two new descriptors, closed below.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [read_end, write_end] = fds;
+        assert!(!fd_ready(read_end, Duration::from_millis(50)));
+        // SAFETY: writes one byte from a valid buffer to the pipe's write end.
+        assert_eq!(unsafe { libc::write(write_end, b"x".as_ptr().cast(), 1) }, 1);
+        assert!(fd_ready(read_end, Duration::from_millis(50)));
+        // SAFETY: both descriptors came from pipe above and are not used afterwards.
+        unsafe {
+            libc::close(read_end);
+            libc::close(write_end);
+        }
+    }
+}
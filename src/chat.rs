@@ -2,32 +2,71 @@ use std::sync::Arc;
 use chrono::Local;
 use anyhow::{anyhow, Result};
 use serde_json::{json, Value};
-use crate::config::Config;
+use std::collections::HashSet;
+use crate::config::{Config, Profile, Sampling};
+use crate::session::{Session, SessionStore};
+use crate::usage::{Usage, UsageTotals};
 use spinners::{Spinner, Spinners};
-use async_openai::{Client, config::OpenAIConfig, error::OpenAIError, types::{CreateChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage, ChatCompletionRequestSystemMessageContent, ChatCompletionTool, ChatCompletionToolType, FunctionObject}};
+use crate::provider::{ApiError, LlmProvider};
+use crate::tools::ToolRegistry;
+use async_openai::types::ChatCompletionTool;
+use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
 
-/// Maximum number of messages to keep in conversation history.
-/// When exceeded, oldest messages are trimmed to stay within the limit.
-const MAX_HISTORY_SIZE: usize = 100;
+/// Automatic compaction starts once history uses this share (in percent) of its token budget.
+const COMPACTION_THRESHOLD_PERCENT: usize = 80;
+
+/// Each message is clipped to this many characters in the transcript sent for summarization.
+const COMPACTION_MESSAGE_CHARS: usize = 2_000;
+
+/// Prefix of the synthetic message that replaces summarized history.
+pub(crate) const SUMMARY_PREFIX: &str = "[Conversation summary]";
+
+const COMPACTION_INSTRUCTION: &str = "You summarize the earlier part of a conversation between a user and an AI assistant working in a terminal sandbox. Write a concise summary that lets the assistant continue the work without the original messages. Keep the user's goals and preferences, decisions made, file paths, commands run and their outcomes, errors and how they were resolved, and any open tasks. Use short bullet points. Do not address the user.";
 
 #[derive(Debug)]
 pub struct ChatManager {
+    /// Settings as loaded, before any profile is applied.
+    loaded_config: Config,
+    /// Settings in effect: `loaded_config` with the active profile applied.
     config: Config,
+    profile: Option<String>,
+    /// Request options from command-line flags; they win over the config and profile.
+    sampling_flags: Sampling,
     history: Vec<Value>,
     system_instruction: String,
-    client: Arc<Client<OpenAIConfig>>,
+    provider: Arc<dyn LlmProvider>,
+    /// Every registered tool; `tool_registry` holds those the active profile allows.
+    all_tools: ToolRegistry,
+    tool_registry: Arc<ToolRegistry>,
     tools: Vec<ChatCompletionTool>,
+    session: Session,
+    session_store: Option<SessionStore>,
+    /// The latest failed save, until a front end reports it
+    save_error: Option<String>,
+    /// Whether the user was already warned that saving fails
+    save_warned: bool,
+    /// Token counts since the conversation started; not persisted with the session
+    usage: UsageTotals,
 }
 
 /// Data needed for an LLM API call, extracted from ChatManager.
 /// This allows callers to release the mutex before the potentially long LLM call.
 pub(crate) struct LlmCallData {
-    pub client: Arc<Client<OpenAIConfig>>,
+    pub provider: Arc<dyn LlmProvider>,
     pub model: String,
     pub system_instruction: String,
     pub history: Vec<Value>,
     pub tools: Vec<ChatCompletionTool>,
+    pub sampling: Sampling,
+}
+
+/// A pending compaction: the history span to replace and the summarization request for it.
+pub(crate) struct CompactionPlan {
+    pub data: LlmCallData,
+    session_id: String,
+    start: usize,
+    end: usize,
 }
 
 /// Result from an LLM API call, to be applied back to ChatManager.
@@ -36,6 +75,15 @@ pub(crate) struct LlmCallResult {
     pub new_messages: Vec<Value>,
 }
 
+/// Whether a failed LLM request with this HTTP status is worth retrying.
+pub(crate) fn is_retryable(status_code: Option<u16>) -> bool {
+    match status_code {
+        Some(429) | Some(502) | Some(503) | Some(504) => true,
+        Some(code) if code >= 500 => true,
+        _ => false,
+    }
+}
+
 /// Performs an LLM API call with retry logic.
 /// This is a free function that doesn't require &mut self, allowing callers
 /// to avoid holding the Mutex across the await.
@@ -44,24 +92,6 @@ pub(crate) async fn call_llm_api(
     skip_spinner: bool,
     debug: bool,
 ) -> Result<LlmCallResult> {
-    let mut chat_messages: Vec<ChatCompletionRequestMessage> = Vec::new();
-    chat_messages.push(ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
-        content: ChatCompletionRequestSystemMessageContent::Text(data.system_instruction.clone()),
-        name: None,
-    }));
-    for msg in &data.history {
-        let message: ChatCompletionRequestMessage = serde_json::from_value(msg.clone())
-            .map_err(|e| anyhow!("Failed to parse message: {}", e))?;
-        chat_messages.push(message);
-    }
-
-    let request = CreateChatCompletionRequest {
-        model: data.model.clone(),
-        messages: chat_messages,
-        tools: Some(data.tools.clone()),
-        ..Default::default()
-    };
-
     let spinner = if skip_spinner {
         None
     } else {
@@ -74,11 +104,11 @@ pub(crate) async fn call_llm_api(
             crate::utils::log_to_file(debug, &format!("Retrying LLM API call in {}s (attempt {}/{})", delay.as_secs(), attempt + 1, 3));
             sleep(delay).await;
         }
-        crate::utils::log_to_file(debug, &format!("LLM API Call Attempt {}/{}", attempt + 1, 3));
+        crate::utils::log_to_file(debug, &format!("LLM API Call Attempt {}/{} ({})", attempt + 1, 3, data.provider.name()));
 
         let start_time = std::time::Instant::now();
-        match data.client.chat().create(request.clone()).await {
-            Ok(response) => {
+        match data.provider.chat(data).await {
+            Ok(response_json) => {
                 let elapsed = start_time.elapsed();
                 crate::utils::log_to_file(debug, &format!("LLM API Response ({}ms): success", elapsed.as_millis()));
 
@@ -87,37 +117,26 @@ pub(crate) async fn call_llm_api(
                     print!("\r\x1b[2K");
                 }
 
-                let response_json: Value = serde_json::to_value(&response)
-                    .map_err(|e| anyhow!("Failed to serialize response: {}", e))?;
                 crate::utils::log_to_file(debug, &format!("LLM Response: {}", crate::utils::truncate_str(&response_json.to_string(), 500)));
-
-                let mut new_messages: Vec<Value> = Vec::new();
-                for choice in &response.choices {
-                    match serde_json::to_value(&choice.message) {
-                        Ok(msg) => new_messages.push(msg),
-                        Err(e) => {
-                            crate::utils::log_to_file(debug, &format!("Warning: failed to serialize choice message: {}", e));
-                        }
-                    }
+                if let Some(timings) = crate::provider::timing_summary(&response_json) {
+                    crate::utils::log_to_file(debug, &format!("LLM Timings: {}", timings));
                 }
 
+                let new_messages: Vec<Value> = response_json
+                    .get("choices")
+                    .and_then(|c| c.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|choice| choice.get("message").cloned())
+                    .collect();
+
                 return Ok(LlmCallResult { response: response_json, new_messages });
             }
             Err(e) => {
                 let elapsed = start_time.elapsed();
-                let status_code = match &e {
-                    OpenAIError::Reqwest(reqwest_err) => {
-                        reqwest_err.status().map(|s| s.as_u16())
-                    }
-                    _ => None,
-                };
+                let status_code = e.downcast_ref::<ApiError>().and_then(|api_err| api_err.status);
                 crate::utils::log_to_file(debug, &format!("LLM API Error (attempt {}/{} in {}ms): {}", attempt + 1, 3, elapsed.as_millis(), e));
-                let should_retry = match status_code {
-                    Some(429) | Some(502) | Some(503) | Some(504) => true,
-                    Some(code) if code >= 500 => true,
-                    _ => false,
-                };
-                if should_retry && attempt < 2 {
+                if is_retryable(status_code) && attempt < 2 {
                     crate::utils::log_to_file(debug, &format!("Retryable error detected (status {:?}), will retry...", status_code));
                 } else {
                     if attempt == 2 {
@@ -140,23 +159,195 @@ pub(crate) async fn call_llm_api(
     unreachable!()
 }
 
-impl ChatManager {
-    pub fn get_tavily_api_key(&self) -> &str {
-        &self.config.tavily_api_key
+/// Summarizes older history with one extra LLM call and replaces it with a synthetic
+/// summary message. Without `force`, this only runs once history nears the token budget.
+/// Returns the number of messages that were summarized, or `None` if nothing was done.
+pub(crate) async fn compact_history(
+    chat_manager: &Arc<Mutex<ChatManager>>,
+    force: bool,
+    debug: bool,
+) -> Result<Option<usize>> {
+    let plan = {
+        let manager = chat_manager.lock().await;
+        if !force && !manager.needs_compaction() {
+            return Ok(None);
+        }
+        match manager.prepare_compaction(force) {
+            Some(plan) => plan,
+            None => return Ok(None),
+        }
+    };
+    crate::utils::log_to_file(debug, &format!("Compacting history messages {}..{}", plan.start, plan.end));
+    let result = call_llm_api(&plan.data, true, debug).await?;
+    let summary = result
+        .new_messages
+        .first()
+        .and_then(|m| m.get("content"))
+        .and_then(|c| c.as_str())
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    if summary.is_empty() {
+        return Err(anyhow!("Summarization returned an empty response"));
     }
+    let mut manager = chat_manager.lock().await;
+    manager.record_usage(&result.response);
+    Ok(manager.apply_compaction(&plan, &summary))
+}
 
-    pub fn get_config(&self) -> &Config {
-        &self.config
+fn transcript_line(msg: &Value) -> String {
+    let role = msg.get("role").and_then(|r| r.as_str()).unwrap_or("unknown");
+    let content = msg.get("content").and_then(|c| c.as_str()).unwrap_or("");
+    let mut line = match role {
+        "tool" => format!("Tool result: {}", content),
+        "assistant" => format!("Assistant: {}", content),
+        "user" => format!("User: {}", content),
+        other => format!("{}: {}", other, content),
+    };
+    line = crate::utils::truncate_str(&line, COMPACTION_MESSAGE_CHARS);
+    if let Some(tool_calls) = msg.get("tool_calls").and_then(|tc| tc.as_array()) {
+        for tc in tool_calls {
+            let func = tc.get("function");
+            let name = func.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or("");
+            let args = func.and_then(|f| f.get("arguments")).and_then(|a| a.as_str()).unwrap_or("");
+            line.push_str(&format!(
+                "\nAssistant called {} with {}",
+                name,
+                crate::utils::truncate_str(args, COMPACTION_MESSAGE_CHARS)
+            ));
+        }
     }
+    line
+}
 
-    pub fn get_alpha_vantage_api_key(&self) -> &str {
-        &self.config.alpha_vantage_api_key
+impl ChatManager {
+    pub fn get_config(&self) -> &Config {
+        &self.config
     }
 
     pub fn get_history(&self) -> &Vec<serde_json::Value> {
         &self.history
     }
 
+    /// The system prompt sent with every request, including instruction files.
+    pub fn system_instruction(&self) -> &str {
+        &self.system_instruction
+    }
+
+    /// Adds tools (e.g. from MCP servers) to the registry and the schemas sent to the model.
+    pub(crate) fn register_tools(&mut self, tools: Vec<Arc<dyn crate::tools::Tool>>) {
+        for tool in tools {
+            self.all_tools.register(tool);
+        }
+        self.refresh_tools();
+    }
+
+    /// Offers the model the registered tools the active profile allows.
+    fn refresh_tools(&mut self) {
+        let allowed = self.active_profile().and_then(|p| p.allowed_tools.as_deref());
+        let registry = match allowed {
+            Some(list) => self.all_tools.only(&crate::tools::parse_tool_list(list)),
+            None => self.all_tools.clone(),
+        };
+        self.tools = registry.schemas();
+        self.tool_registry = Arc::new(registry);
+    }
+
+    fn active_profile(&self) -> Option<&Profile> {
+        self.profile.as_deref().and_then(|name| self.loaded_config.profile.get(name))
+    }
+
+    /// The active profile's name, if any.
+    pub fn profile_name(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Names of the configured profiles.
+    pub fn profile_names(&self) -> Vec<String> {
+        self.loaded_config.profile.keys().cloned().collect()
+    }
+
+    /// Switches to a profile, or back to the top-level settings with `None`. The
+    /// conversation is kept; the next request uses the new prompt, model and tools.
+    pub fn set_profile(&mut self, name: Option<&str>) -> Result<()> {
+        let profile = name.map(|n| self.loaded_config.get_profile(n)).transpose()?;
+        let config = match profile {
+            Some(profile) => self.loaded_config.with_profile(profile),
+            None => self.loaded_config.clone(),
+        };
+        self.system_instruction = Self::build_system_instruction(profile.and_then(|p| p.system_instruction.as_deref()));
+        self.provider = crate::provider::create_provider(&config);
+        self.config = config;
+        self.profile = name.map(|n| n.to_string());
+        self.refresh_tools();
+        Ok(())
+    }
+
+    pub fn set_sampling_flags(&mut self, sampling: Sampling) {
+        self.sampling_flags = sampling;
+    }
+
+    /// Tools the active profile runs without confirmation, in addition to `--allow-tools`.
+    pub(crate) fn auto_approved_tools(&self) -> HashSet<String> {
+        self.active_profile()
+            .and_then(|p| p.auto_approve.as_deref())
+            .map(crate::tools::parse_tool_list)
+            .unwrap_or_default()
+    }
+
+    /// The tools offered to the model; both front ends dispatch tool calls through it.
+    pub(crate) fn tool_registry(&self) -> Arc<ToolRegistry> {
+        Arc::clone(&self.tool_registry)
+    }
+
+    pub(crate) fn provider(&self) -> Arc<dyn LlmProvider> {
+        Arc::clone(&self.provider)
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session.id
+    }
+
+    /// Enable persistence: history is saved to `store` after every LLM result and tool result.
+    pub fn set_session_store(&mut self, store: SessionStore) {
+        self.session_store = Some(store);
+    }
+
+    /// Replace the current conversation with a previously saved session.
+    pub fn restore_session(&mut self, session: Session) {
+        self.history = session.history.clone();
+        self.session = session;
+        self.usage = UsageTotals::default();
+    }
+
+    /// Write the current history to the session store, if one is attached.
+    fn save_session(&mut self) {
+        if self.session_store.is_none() || self.history.is_empty() {
+            return;
+        }
+        self.session.history = self.history.clone();
+        self.session.model = self.config.model.clone();
+        // A session resumed elsewhere belongs to the directory it now runs in.
+        self.session.sandbox_root = crate::sandbox::get_sandbox_root().clone();
+        self.session.updated_at = Local::now().to_rfc3339();
+        if let Some(store) = &self.session_store {
+            if let Err(e) = store.save(&self.session) {
+                self.save_error = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    /// Logs the latest failed save, if any, and returns a warning for the user the first
+    /// time saving fails.
+    pub(crate) fn take_save_warning(&mut self, debug: bool) -> Option<String> {
+        let error = self.save_error.take()?;
+        crate::utils::log_to_file(debug, &format!("Failed to save session {}: {}", self.session.id, error));
+        if std::mem::replace(&mut self.save_warned, true) {
+            return None;
+        }
+        Some(format!("Could not save session {}: {}. Later failures are only logged with --debug.", self.session.id, error))
+    }
+
     /// Push a user message to history.
     pub fn push_user_message(&mut self, message: &str, debug: bool) {
         let user_message = json!({
@@ -164,10 +355,11 @@ impl ChatManager {
             "content": message
         });
         self.history.push(user_message);
+        self.usage.start_turn();
         crate::utils::log_to_file(debug, &format!("LLM Query: {}", crate::utils::truncate_str(message, 200)));
     }
 
-    /// Push tool result messages to history and trim.
+    /// Push tool result messages to history.
     pub fn push_tool_results_to_history(&mut self, tool_results: &[(String, String)], debug: bool) {
         for (tool_call_id, result) in tool_results {
             let tool_message = json!({
@@ -178,30 +370,103 @@ impl ChatManager {
             self.history.push(tool_message);
             crate::utils::log_to_file(debug, &format!("Tool result for {}: {}", tool_call_id, crate::utils::truncate_str(result, 200)));
         }
-        self.trim_history();
+        self.save_session();
     }
 
     /// Extract data needed for an LLM call. The caller can release the lock
-    /// before calling `call_llm_api`.
-    pub fn prepare_llm_call(&self) -> LlmCallData {
+    /// before calling `call_llm_api`. Callers compact history first; whatever still
+    /// exceeds the budget (e.g. when compaction failed) is trimmed here.
+    pub fn prepare_llm_call(&mut self) -> LlmCallData {
+        self.trim_history();
         LlmCallData {
-            client: Arc::clone(&self.client),
+            provider: Arc::clone(&self.provider),
             model: self.config.model.clone(),
             system_instruction: self.system_instruction.clone(),
             history: self.history.clone(),
             tools: self.tools.clone(),
+            sampling: self.sampling_flags.clone().or(self.config.sampling()),
         }
     }
 
+    /// Whether history has grown close enough to the token budget to warrant compaction.
+    pub fn needs_compaction(&self) -> bool {
+        let used = crate::context::estimate_history_tokens(&self.history);
+        used * 100 > self.history_token_budget() * COMPACTION_THRESHOLD_PERCENT
+    }
+
+    /// Builds the summarization request for the oldest part of history. Automatic
+    /// compaction keeps the most recent quarter of the budget verbatim; a forced one
+    /// keeps only the latest exchange.
+    pub(crate) fn prepare_compaction(&self, force: bool) -> Option<CompactionPlan> {
+        let keep_recent = if force { 0 } else { self.history_token_budget() / 4 };
+        let (start, end) = crate::context::compaction_range(&self.history, keep_recent)?;
+        let transcript = self.history[start..end]
+            .iter()
+            .map(transcript_line)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let data = LlmCallData {
+            provider: Arc::clone(&self.provider),
+            model: self.config.model.clone(),
+            system_instruction: COMPACTION_INSTRUCTION.to_string(),
+            history: vec![json!({
+                "role": "user",
+                "content": format!("Summarize this conversation excerpt:\n\n{}", transcript)
+            })],
+            tools: Vec::new(),
+            sampling: Sampling::default(),
+        };
+        Some(CompactionPlan {
+            data,
+            session_id: self.session.id.clone(),
+            start,
+            end,
+        })
+    }
+
+    /// Replaces the planned span with a summary message. Returns the number of messages
+    /// replaced, or `None` if the conversation changed while the summary was generated.
+    pub(crate) fn apply_compaction(&mut self, plan: &CompactionPlan, summary: &str) -> Option<usize> {
+        if plan.session_id != self.session.id || plan.end > self.history.len() {
+            return None;
+        }
+        let summary_message = json!({
+            "role": "user",
+            "content": format!("{}\n{}", SUMMARY_PREFIX, summary)
+        });
+        self.history.splice(plan.start..plan.end, [summary_message]);
+        self.save_session();
+        Some(plan.end - plan.start)
+    }
+
     /// Apply LLM call results (new messages) back to history.
     pub fn apply_llm_result(&mut self, result: &LlmCallResult) {
+        self.record_usage(&result.response);
         for msg in &result.new_messages {
             self.history.push(msg.clone());
         }
-        self.trim_history();
+        self.save_session();
     }
 
-    fn build_system_instruction() -> String {
+    /// Adds a response's token counts to the turn and session totals, priced by the
+    /// current model's `[prices]` entry.
+    pub(crate) fn record_usage(&mut self, response: &Value) {
+        if let Some(usage) = Usage::from_response(response) {
+            let price = crate::usage::price_for(&self.config.prices, &self.config.model);
+            self.usage.record(usage, price.as_ref());
+        }
+    }
+
+    pub(crate) fn usage(&self) -> UsageTotals {
+        self.usage
+    }
+
+    /// The built-in prompt, or a profile's replacement for it, followed by any
+    /// instruction files found for the sandbox.
+    fn build_system_instruction(custom: Option<&str>) -> String {
+        if let Some(custom) = custom {
+            return crate::instructions::append_to(custom, &crate::instructions::load());
+        }
         let today = Local::now().format("%Y-%m-%d").to_string();
         let os_name = if cfg!(target_os = "windows") {
             "Windows"
@@ -214,158 +479,118 @@ impl ChatManager {
         };
 
         let shell_info = crate::shell::detect_shell_info();
+        let network = match crate::sandbox::get_sandbox_policy().network_commands.as_slice() {
+            [] => "network access is disabled for commands".to_string(),
+            programs => format!(
+                "network access is disabled for commands except a single {} invocation without pipes or chaining",
+                programs.join("/")
+            ),
+        };
 
-        format!(
-            "Today's date is {}. You are a proactive assistant running in a sandboxed {} terminal environment (network access is disabled for commands) with a full set of command line utilities. The default shell is {}. Your role is to assist with coding tasks, file operations, online searches, email sending, and shell commands efficiently and decisively. Assume the current directory (the sandbox root) is the target for all commands. Take initiative to provide solutions, execute commands, and analyze results immediately without asking for confirmation unless the action is explicitly ambiguous (e.g., multiple repos) or potentially destructive (e.g., deleting files). Use the `execute_command` tool to interact with the system but only when needed. Deliver concise, clear responses. After running a command, always summarize its output immediately and proceed with logical next steps, without waiting for the user to prompt you further. Stay within the sandbox directory. Users can run shell commands directly with `!`, and you'll receive the output to assist further. Act confidently and anticipate the user's needs to streamline their workflow. You may use md formatting to provide a more readable response. When using search tools, prioritize concise modes ('basic') to maintain efficiency unless the query requires depth.",
-            today, os_name, shell_info
-        )
+        let base = format!(
+            "Today's date is {}. You are a proactive assistant running in a sandboxed {} terminal environment ({}) with a full set of command line utilities. The default shell is {}. Your role is to assist with coding tasks, file operations, online searches, email sending, and shell commands efficiently and decisively. Assume the current directory (the sandbox root) is the target for all commands. Take initiative to provide solutions, execute commands, and analyze results immediately without asking for confirmation unless the action is explicitly ambiguous (e.g., multiple repos) or potentially destructive (e.g., deleting files). Use the `execute_command` tool to interact with the system but only when needed, and the `shell_session` tool when the working directory, environment variables or a virtualenv must persist between commands. Deliver concise, clear responses. After running a command, always summarize its output immediately and proceed with logical next steps, without waiting for the user to prompt you further. Stay within the sandbox directory. Users can run shell commands directly with `!`, and you'll receive the output to assist further. Act confidently and anticipate the user's needs to streamline their workflow. You may use md formatting to provide a more readable response. When using search tools, prioritize concise modes ('basic') to maintain efficiency unless the query requires depth.",
+            today, os_name, network, shell_info
+        );
+        crate::instructions::append_to(&base, &crate::instructions::load())
     }
 
     pub fn new(config: Config) -> Self {
-        let openai_config = OpenAIConfig::new()
-            .with_api_key(config.api_key.clone())
-            .with_api_base(format!("{}/{}", config.api_base_url, config.api_version));
-        let client = Arc::new(Client::with_config(openai_config));
-        let tools = Self::build_tools();
+        let provider = crate::provider::create_provider(&config);
+        let all_tools = ToolRegistry::builtin();
+        let tool_registry = Arc::new(all_tools.clone());
+        let tools = tool_registry.schemas();
+        let session = Session::new(&config.model);
         ChatManager {
+            loaded_config: config.clone(),
             config,
+            profile: None,
+            sampling_flags: Sampling::default(),
             history: Vec::new(),
-            system_instruction: Self::build_system_instruction(),
-            client,
+            system_instruction: Self::build_system_instruction(None),
+            provider,
+            all_tools,
+            tool_registry,
             tools,
+            session,
+            session_store: None,
+            save_error: None,
+            save_warned: false,
+            usage: UsageTotals::default(),
         }
     }
 
+    /// Start a fresh conversation. The previous session stays on disk and can be resumed.
     pub fn create_chat(&mut self) {
         self.history.clear();
+        self.session = Session::new(&self.config.model);
+        self.usage = UsageTotals::default();
+        crate::shell_session::close_shared_session();
+        crate::jobs::kill_all_jobs();
     }
 
-    fn create_tool(name: &str, description: &str, parameters: serde_json::Value) -> ChatCompletionTool {
-        ChatCompletionTool {
-            r#type: ChatCompletionToolType::Function,
-            function: FunctionObject {
-                name: name.to_string(),
-                description: Some(description.to_string()),
-                parameters: Some(parameters),
-                strict: Some(false),
-            },
-        }
-    }
-
-    fn build_tools() -> Vec<ChatCompletionTool> {
-        vec![
-
-            Self::create_tool("search_online", "Search the web for a query and return a synthesized answer. Use for factual lookups, current events, or research. Defaults to concise summaries for speed.", json!({
-                "type": "object",
-                "properties": {
-                    "query": {
-                        "type": "string",
-                        "description": "The search query"
-                    },
-                     "include_results": {
-                        "type": "boolean",
-                        "description": "Whether to include a list of search results (default: false). Set to true only if you need to review sources directly, e.g., for verification or multiple options.",
-                        "default": false
-                    },
-                    "answer_mode": {
-                        "type": "string",
-                        "enum": ["basic", "full"],
-                        "description": "Answer detail level. 'basic' (default): Quick summary in 3 sentences, ideal for straightforward queries. 'full': Comprehensive answer with all available details, best for in-depth research or ambiguous topics.",
-                        "default": "basic"
-                    }
-                },
-                "required": ["query"]
-            })),
-            Self::create_tool("execute_command", "Execute a system command. Use this for any shell task.", json!({
-                "type": "object",
-                "properties": {
-                    "command": {"type": "string"}
-                },
-                "required": ["command"]
-            })),
-            Self::create_tool("send_email", "Sends an email to a fixed address using SMTP.", json!({
-                "type": "object",
-                "properties": {
-                    "subject": {"type": "string", "description": "Email subject line"},
-                    "body": {"type": "string", "description": "Email message body"}
-                },
-                "required": ["subject", "body"]
-            })),
-            Self::create_tool("alpha_vantage_query", "Query the Alpha Vantage API for stock/financial data", json!({
-                "type": "object",
-                "properties": {
-                    "function": {
-                        "type": "string",
-                        "description": "The Alpha Vantage function (e.g., TIME_SERIES_DAILY)"
-                    },
-                    "symbol": {
-                        "type": "string",
-                        "description": "The stock symbol (e.g., IBM)"
-                    },
-                    "outputsize": {
-                        "type": "string",
-                        "enum": ["compact", "full"],
-                        "description": "The size of the output data. 'compact' returns the last 100 data points, 'full' returns all available data. Defaults to 'compact'."
-                    },
-                    "limit": {
-                        "type": "integer",
-                        "description": "Maximum number of most recent data points to return (default 5)",
-                        "default": 5
-                    }
-                },
-                "required": ["function", "symbol"]
-            })),
-            Self::create_tool("scrape_url", "Scrapes the content of a single URL", json!({
-                "type": "object",
-                "properties": {
-                    "url": {
-                        "type": "string",
-                        "description": "The URL to scrape"
-                    },
-                    "mode": {
-                        "type": "string",
-                        "enum": ["summarized", "full"],
-                        "default": "summarized",
-                        "description": "Mode: 'summarized' provides a concise summary (default), 'full' returns complete extracted text"
-                    }
-                },
-                "required": ["url"]
-            })),
-            Self::create_tool("file_editor", "Edit files in the sandbox with sub-commands: read, write, search, search_and_replace, apply_diff.", json!({
-                "type": "object",
-                "properties": {
-                    "subcommand": {
-                        "type": "string",
-                        "description": "The sub-command to execute: read, write, search, search_and_replace, apply_diff",
-                        "enum": ["read", "write", "search", "search_and_replace", "apply_diff"]
-                    },
-                    "filename": {
-                        "type": "string",
-                        "description": "The name of the file in the sandbox to operate on"
-                    },
-                    "data": {
-                        "type": "string",
-                        "description": "Content to write (for write), regex pattern (for search/search_and_replace), or diff content (for apply_diff)"
-                    },
-                    "replacement": {
-                        "type": "string",
-                        "description": "Replacement text for search_and_replace"
-                    }
-                },
-                "required": ["subcommand", "filename"]
-            })),
-        ]
+    /// Tokens available for history after the system prompt, tool schemas and reply reserve.
+    fn history_token_budget(&self) -> usize {
+        let window = crate::context::context_window_for_model(&self.config.model, self.config.context_window);
+        let tools_json = serde_json::to_string(&self.tools).unwrap_or_default();
+        let overhead = crate::context::estimate_text_tokens(&self.system_instruction)
+            + crate::context::estimate_text_tokens(&tools_json);
+        crate::context::history_budget(window, overhead)
     }
 
-    /// Trims history to MAX_HISTORY_SIZE by removing the oldest entries.
+    /// Trims history to the model's token budget, dropping the oldest message groups first.
+    /// Tool calls and their results are removed together and the first user message is kept.
     fn trim_history(&mut self) {
-        if self.history.len() > MAX_HISTORY_SIZE {
-            let excess = self.history.len() - MAX_HISTORY_SIZE;
-            self.history.drain(..excess);
-        }
+        let budget = self.history_token_budget();
+        crate::context::trim_to_budget(&mut self.history, budget);
     }
 
     pub fn cleanup(&mut self, _is_signal: bool) {
         self.history.clear();
+        crate::shell_session::close_shared_session();
+        crate::jobs::kill_all_jobs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_leaves_history_for_compaction_and_prepare_trims() {
+        let mut manager = ChatManager::new(Config { context_window: 20_000, ..Config::default() });
+        let budget = manager.history_token_budget();
+        manager.push_user_message("first question", false);
+        manager.apply_llm_result(&LlmCallResult {
+            response: json!({}),
+            new_messages: vec![json!({"role": "assistant", "content": "first answer"})],
+        });
+        manager.push_user_message(&"word ".repeat(budget), false);
+
+        assert_eq!(manager.get_history().len(), 3);
+        assert!(manager.needs_compaction());
+
+        let data = manager.prepare_llm_call();
+        assert_eq!(data.history.len(), 2);
+        assert_eq!(data.history[0]["content"], "first question");
+    }
+
+    #[test]
+    fn test_save_failures_warn_once() {
+        let blocker = std::env::temp_dir().join(format!("ai-cli-save-blocker-{}", std::process::id()));
+        std::fs::write(&blocker, "").unwrap();
+        let mut manager = ChatManager::new(Config::default());
+        manager.set_session_store(SessionStore::new(blocker.join("sessions")));
+        let answer = |text: &str| LlmCallResult {
+            response: json!({}),
+            new_messages: vec![json!({"role": "assistant", "content": text})],
+        };
+
+        manager.push_user_message("hello", false);
+        manager.apply_llm_result(&answer("hi"));
+        assert!(manager.take_save_warning(false).is_some_and(|w| w.contains("Could not save session")));
+        assert_eq!(manager.take_save_warning(false), None);
+        manager.apply_llm_result(&answer("again"));
+        assert_eq!(manager.take_save_warning(false), None);
+        let _ = std::fs::remove_file(&blocker);
     }
 }
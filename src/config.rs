@@ -1,17 +1,189 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use config::{Config as ConfigLoader, Environment, File, FileFormat};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
 
+use crate::command_rules::CommandRules;
+use crate::usage::Price;
+
+const OPENAI_API_BASE_URL: &str = "https://api.openai.com";
+const ANTHROPIC_API_BASE_URL: &str = "https://api.anthropic.com";
+const OLLAMA_API_BASE_URL: &str = "http://localhost:11434";
+
+/// The config file, relative to the home directory
+pub const CONFIG_FILE_NAME: &str = ".aicli.conf";
+
+/// Which LLM API the client speaks, selected with the PROVIDER key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// Any OpenAI-compatible `/chat/completions` endpoint (OpenAI, Gemini, local servers)
+    #[default]
+    OpenAi,
+    /// Native Anthropic `/messages` API
+    Anthropic,
+    /// Native Ollama `/api/chat` API
+    Ollama,
+}
+
+/// One MCP server from a `[mcp.servers.<name>]` section
+#[derive(Debug, Clone, Deserialize)]
+pub struct McpServerConfig {
+    /// Command line that starts the server, split like a shell would
+    pub command: String,
+    /// Seconds to wait for a response before giving up on a request
+    #[serde(default = "default_mcp_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_mcp_timeout_secs() -> u64 {
+    120
+}
+
+/// Model Context Protocol settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct McpConfig {
+    pub servers: BTreeMap<String, McpServerConfig>,
+}
+
+/// Command sandbox preset, selected with `--sandbox-profile` or `profile` in `[sandbox]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxProfile {
+    /// Only the sandbox root is writable; no network; private `/tmp`
+    #[default]
+    Strict,
+    /// Also writable tool caches and network access for package managers and git
+    Dev,
+}
+
+/// The `[sandbox]` section. Lists are comma-separated and extend the profile's preset.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub profile: Option<SandboxProfile>,
+    /// Extra writable paths
+    pub rw_paths: Option<String>,
+    /// Paths replaced with an empty directory or file
+    pub hide_paths: Option<String>,
+    /// Programs that run with network access
+    pub network_commands: Option<String>,
+    /// Environment variables passed to commands besides the defaults (`*` matches any prefix or suffix)
+    pub env_allow: Option<String>,
+    /// Environment variables removed before commands run
+    pub env_deny: Option<String>,
+    /// Give commands a private, empty `/tmp`
+    pub tmpfs_tmp: Option<bool>,
+}
+
+/// The `[command_rules]` section: comma-separated glob patterns (`*`, `?`), or regexes
+/// prefixed with `re:`, checked against commands before they run. `\,` is a literal comma.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CommandRulesConfig {
+    /// Commands that run without confirmation
+    pub allow: Option<String>,
+    /// Commands that are always refused
+    pub deny: Option<String>,
+    /// Commands that always need confirmation, even when commands are auto-approved
+    pub ask: Option<String>,
+}
+
+/// How much a reasoning model thinks before answering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Minimal,
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReasoningEffort::Minimal => "minimal",
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+}
+
+/// Request options sent to the model. Each is passed through only when set, so
+/// unset options keep the provider's defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sampling {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub seed: Option<i64>,
+    pub stop: Vec<String>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+    pub parallel_tool_calls: Option<bool>,
+}
+
+impl Sampling {
+    /// Fills the options not set here from `fallback`.
+    pub fn or(self, fallback: Sampling) -> Sampling {
+        Sampling {
+            temperature: self.temperature.or(fallback.temperature),
+            top_p: self.top_p.or(fallback.top_p),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            seed: self.seed.or(fallback.seed),
+            stop: if self.stop.is_empty() { fallback.stop } else { self.stop },
+            reasoning_effort: self.reasoning_effort.or(fallback.reasoning_effort),
+            parallel_tool_calls: self.parallel_tool_calls.or(fallback.parallel_tool_calls),
+        }
+    }
+}
+
+/// A named `[profile.<name>]` section, selected with `--profile` or `/profile`.
+/// Unset keys keep the top-level settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Replaces the built-in system prompt; instruction files are still appended
+    pub system_instruction: Option<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    /// Comma-separated tools offered to the model; unset offers all of them
+    pub allowed_tools: Option<String>,
+    /// Comma-separated tools that run without confirmation, like `--allow-tools`
+    pub auto_approve: Option<String>,
+}
+
 /// Configuration structure holding all settings for the AI CLI
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct Config {
     // AI Provider Configuration
+    pub provider: ProviderKind,
     pub api_base_url: String,
     pub api_version: String,
     pub model: String,
     pub api_key: String,
+    /// Context window in tokens; 0 means infer it from the model name
+    pub context_window: usize,
+    /// Sampling temperature; unset leaves the provider default
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// Most tokens generated per response
+    pub max_tokens: Option<u32>,
+    pub seed: Option<i64>,
+    /// Comma-separated stop sequences
+    pub stop: Option<String>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Whether the model may request several tool calls in one response
+    pub parallel_tool_calls: Option<bool>,
+
+    // Command limits
+    /// Seconds before a command is killed, unless the call sets `timeout_secs`
+    pub command_timeout_secs: u64,
+    /// Command output kept for the model; longer output loses its middle
+    pub command_max_output_bytes: usize,
 
     // SMTP Configuration
     pub smtp_server: String,
@@ -23,6 +195,22 @@ pub struct Config {
     // Optional: Search APIs
     pub tavily_api_key: String,
     pub alpha_vantage_api_key: String,
+
+    // Optional: external MCP tool servers
+    pub mcp: McpConfig,
+
+    // Optional: named profiles
+    pub profile: BTreeMap<String, Profile>,
+
+    // Optional: command sandbox policy
+    pub sandbox: SandboxConfig,
+
+    // Optional: allow/deny/ask rules for commands, compiled when the config is loaded
+    pub command_rules: CommandRules,
+
+    /// Optional: `[prices]` entries of `model = input, output[, cached_input]` in USD
+    /// per million tokens, used for cost estimates
+    pub prices: BTreeMap<String, String>,
 }
 
 impl Config {
@@ -30,7 +218,7 @@ impl Config {
     pub fn load() -> Result<Self> {
         let home_dir = dirs::home_dir().context("Could not determine home directory")?;
 
-        let config_path = home_dir.join(".aicli.conf");
+        let config_path = home_dir.join(CONFIG_FILE_NAME);
 
         let loader = ConfigLoader::builder()
             .add_source(
@@ -38,30 +226,91 @@ impl Config {
                     .format(FileFormat::Ini)
                     .required(false),
             )
-            .add_source(Environment::with_prefix("AICLI"))
-            .build()
-            .context("Failed to build config")?;
+            .add_source(Environment::with_prefix("AICLI"));
 
-        let mut config: Config = loader
-            .try_deserialize()
-            .context("Failed to deserialize config")?;
+        let mut config = Self::build(loader)?;
 
         // Override SMTP_SERVER_IP with SMTP_SERVER for backwards compatibility
         if let Ok(smtp_ip) = env::var("SMTP_SERVER_IP") {
             config.smtp_server = smtp_ip;
         }
 
+        // Don't send requests (or an Anthropic key) to the OpenAI default when API_BASE_URL is left unset
+        if config.api_base_url == OPENAI_API_BASE_URL {
+            match config.provider {
+                ProviderKind::OpenAi => {}
+                ProviderKind::Anthropic => config.api_base_url = ANTHROPIC_API_BASE_URL.to_string(),
+                ProviderKind::Ollama => config.api_base_url = OLLAMA_API_BASE_URL.to_string(),
+            }
+        }
+
         Ok(config)
     }
 
-    /// Construct the API endpoint URL - always use OpenAI-compatible format
+    fn build(loader: config::ConfigBuilder<config::builder::DefaultState>) -> Result<Self> {
+        let config: Config = loader
+            .build()
+            .context("Failed to build config")?
+            .try_deserialize()
+            .context("Failed to deserialize config")?;
+        for (model, price) in &config.prices {
+            Price::parse(price).with_context(|| format!("Invalid [prices] entry for {}", model))?;
+        }
+        Ok(config)
+    }
+
+    /// Looks up a profile by name.
+    pub fn get_profile(&self, name: &str) -> Result<&Profile> {
+        self.profile.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profile.keys().map(|k| k.as_str()).collect();
+            if known.is_empty() {
+                anyhow!("Unknown profile '{}': no [profile.<name>] sections are configured", name)
+            } else {
+                anyhow!("Unknown profile '{}' (available: {})", name, known.join(", "))
+            }
+        })
+    }
+
+    /// The settings with a profile's model and temperature applied.
+    pub fn with_profile(&self, profile: &Profile) -> Config {
+        let mut config = self.clone();
+        if let Some(model) = &profile.model {
+            config.model = model.clone();
+        }
+        if profile.temperature.is_some() {
+            config.temperature = profile.temperature;
+        }
+        config
+    }
+
+    /// The request options set in the config file or environment.
+    pub fn sampling(&self) -> Sampling {
+        Sampling {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            seed: self.seed,
+            stop: self
+                .stop
+                .iter()
+                .flat_map(|s| s.split(','))
+                .map(|s| s.replace("\\n", "\n").replace("\\t", "\t"))
+                .filter(|s| !s.is_empty())
+                .collect(),
+            reasoning_effort: self.reasoning_effort,
+            parallel_tool_calls: self.parallel_tool_calls,
+        }
+    }
+
+    /// Construct the API endpoint URL for the configured provider
     pub fn get_api_endpoint(&self) -> String {
-        // Always use OpenAI-compatible chat/completions endpoint
-        // Google Gemini also supports OpenAI-compatible endpoints
-        format!(
-            "{}/{}/chat/completions",
-            self.api_base_url, self.api_version
-        )
+        match self.provider {
+            // Google Gemini also supports OpenAI-compatible endpoints
+            ProviderKind::OpenAi => format!("{}/{}/chat/completions", self.api_base_url, self.api_version),
+            ProviderKind::Anthropic => format!("{}/{}/messages", self.api_base_url, self.api_version),
+            // The native Ollama API is unversioned
+            ProviderKind::Ollama => format!("{}/api/chat", self.api_base_url),
+        }
     }
 }
 
@@ -82,10 +331,21 @@ pub fn mask_value(value: &str, mask_empty: bool) -> String {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            api_base_url: "https://api.openai.com".to_string(),
+            provider: ProviderKind::OpenAi,
+            api_base_url: OPENAI_API_BASE_URL.to_string(),
             api_version: "v1".to_string(),
             model: "gpt-4o-mini".to_string(),
             api_key: "".to_string(),
+            context_window: 0,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            seed: None,
+            stop: None,
+            reasoning_effort: None,
+            parallel_tool_calls: None,
+            command_timeout_secs: 120,
+            command_max_output_bytes: 32 * 1024,
             smtp_server: "localhost".to_string(),
             smtp_username: "".to_string(),
             smtp_password: "".to_string(),
@@ -93,6 +353,11 @@ impl Default for Config {
             sender_email: "".to_string(),
             tavily_api_key: "".to_string(),
             alpha_vantage_api_key: "".to_string(),
+            mcp: McpConfig::default(),
+            profile: BTreeMap::new(),
+            sandbox: SandboxConfig::default(),
+            command_rules: CommandRules::default(),
+            prices: BTreeMap::new(),
         }
     }
 }
@@ -100,14 +365,17 @@ impl Default for Config {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::command_rules::RuleDecision;
 
     #[test]
     fn test_config_default_values() {
         let config = Config::default();
+        assert_eq!(config.provider, ProviderKind::OpenAi);
         assert_eq!(config.api_base_url, "https://api.openai.com");
         assert_eq!(config.api_version, "v1");
         assert_eq!(config.model, "gpt-4o-mini");
         assert_eq!(config.api_key, "");
+        assert_eq!(config.context_window, 0);
         assert_eq!(config.smtp_server, "localhost");
         assert!(config.smtp_username.is_empty());
         assert!(config.smtp_password.is_empty());
@@ -126,6 +394,116 @@ mod tests {
         assert!(endpoint.contains("chat/completions"));
     }
 
+    #[test]
+    fn test_get_api_endpoint_anthropic() {
+        let config = Config {
+            provider: ProviderKind::Anthropic,
+            api_base_url: ANTHROPIC_API_BASE_URL.to_string(),
+            ..Config::default()
+        };
+        assert_eq!(config.get_api_endpoint(), "https://api.anthropic.com/v1/messages");
+    }
+
+    #[test]
+    fn test_get_api_endpoint_ollama() {
+        let config = Config {
+            provider: ProviderKind::Ollama,
+            api_base_url: OLLAMA_API_BASE_URL.to_string(),
+            ..Config::default()
+        };
+        assert_eq!(config.get_api_endpoint(), "http://localhost:11434/api/chat");
+    }
+
+    #[test]
+    fn test_mcp_servers_from_ini_sections() {
+        let ini = "MODEL=test\n[mcp.servers.files]\ncommand=npx -y server-filesystem /tmp\n[mcp.servers.git]\ncommand=mcp-git\ntimeout_secs=5\n";
+        let loader = ConfigLoader::builder().add_source(File::from_str(ini, FileFormat::Ini));
+        let config = Config::build(loader).unwrap();
+        assert_eq!(config.model, "test");
+        assert_eq!(config.mcp.servers.len(), 2);
+        assert_eq!(config.mcp.servers["files"].command, "npx -y server-filesystem /tmp");
+        assert_eq!(config.mcp.servers["files"].timeout_secs, 120);
+        assert_eq!(config.mcp.servers["git"].timeout_secs, 5);
+    }
+
+    #[test]
+    fn test_profiles_from_ini_sections() {
+        let ini = "MODEL=base\n[profile.reviewer]\nmodel=big-model\ntemperature=0.2\nsystem_instruction=You review code.\nallowed_tools=file_editor,search_online\n";
+        let loader = ConfigLoader::builder().add_source(File::from_str(ini, FileFormat::Ini));
+        let config = Config::build(loader).unwrap();
+        let reviewer = config.get_profile("reviewer").unwrap();
+        assert_eq!(reviewer.system_instruction.as_deref(), Some("You review code."));
+        assert_eq!(reviewer.allowed_tools.as_deref(), Some("file_editor,search_online"));
+        assert!(reviewer.auto_approve.is_none());
+
+        let applied = config.with_profile(reviewer);
+        assert_eq!(applied.model, "big-model");
+        assert_eq!(applied.temperature, Some(0.2));
+        assert_eq!(config.model, "base");
+
+        let err = config.get_profile("shell").unwrap_err().to_string();
+        assert!(err.contains("available: reviewer"), "{}", err);
+    }
+
+    #[test]
+    fn test_sampling_options_from_ini() {
+        let ini = "TOP_P=0.9\nMAX_TOKENS=512\nSEED=7\nSTOP=</answer>,\\n\\nUser:\nREASONING_EFFORT=high\nPARALLEL_TOOL_CALLS=false\n";
+        let loader = ConfigLoader::builder().add_source(File::from_str(ini, FileFormat::Ini));
+        let sampling = Config::build(loader).unwrap().sampling();
+        assert_eq!(sampling.temperature, None);
+        assert_eq!(sampling.top_p, Some(0.9));
+        assert_eq!(sampling.max_tokens, Some(512));
+        assert_eq!(sampling.seed, Some(7));
+        assert_eq!(sampling.stop, ["</answer>", "\n\nUser:"]);
+        assert_eq!(sampling.reasoning_effort, Some(ReasoningEffort::High));
+        assert_eq!(sampling.parallel_tool_calls, Some(false));
+
+        let flags = Sampling { temperature: Some(0.1), seed: Some(1), ..Sampling::default() };
+        let merged = flags.or(sampling);
+        assert_eq!(merged.temperature, Some(0.1));
+        assert_eq!(merged.seed, Some(1));
+        assert_eq!(merged.max_tokens, Some(512));
+    }
+
+    #[test]
+    fn test_prices_from_ini_section() {
+        let ini = "MODEL=gpt-4.1\n[prices]\ngpt-4.1 = 2, 8, 0.5\nclaude-sonnet-4 = 3, 15\n";
+        let loader = ConfigLoader::builder().add_source(File::from_str(ini, FileFormat::Ini));
+        let config = Config::build(loader).unwrap();
+        assert_eq!(config.prices.len(), 2);
+        let price = crate::usage::price_for(&config.prices, &config.model).unwrap();
+        assert_eq!(price, Price { input: 2.0, output: 8.0, cached_input: 0.5 });
+
+        let loader = ConfigLoader::builder().add_source(File::from_str("[prices]\ngpt-4o = cheap\n", FileFormat::Ini));
+        let err = format!("{:#}", Config::build(loader).unwrap_err());
+        assert!(err.contains("Invalid [prices] entry for gpt-4o"), "{}", err);
+    }
+
+    #[test]
+    fn test_sandbox_section() {
+        let ini = "MODEL=test\n[sandbox]\nprofile=dev\nrw_paths=~/.m2\ntmpfs_tmp=false\n";
+        let loader = ConfigLoader::builder().add_source(File::from_str(ini, FileFormat::Ini));
+        let sandbox = Config::build(loader).unwrap().sandbox;
+        assert_eq!(sandbox.profile, Some(SandboxProfile::Dev));
+        assert_eq!(sandbox.rw_paths.as_deref(), Some("~/.m2"));
+        assert_eq!(sandbox.tmpfs_tmp, Some(false));
+        assert!(sandbox.env_deny.is_none());
+    }
+
+    #[test]
+    fn test_command_rules_section() {
+        let ini = "MODEL=test\n[command_rules]\nallow=git status*, ls *\ndeny=re:^rm -rf\n";
+        let loader = ConfigLoader::builder().add_source(File::from_str(ini, FileFormat::Ini));
+        let rules = Config::build(loader).unwrap().command_rules;
+        assert_eq!(rules.check("git status --short"), RuleDecision::Allow);
+        assert_eq!(rules.check("ls src"), RuleDecision::Allow);
+        assert_eq!(rules.check("rm -rf src"), RuleDecision::Deny("re:^rm -rf".into()));
+
+        let loader = ConfigLoader::builder().add_source(File::from_str("[command_rules]\nask=re:(\n", FileFormat::Ini));
+        let err = format!("{:#}", Config::build(loader).unwrap_err());
+        assert!(err.contains("Invalid [command_rules]: Invalid command rule 're:('"), "{}", err);
+    }
+
     #[test]
     fn test_mask_value_mask_empty_false() {
         assert_eq!(mask_value("hello", false), "hello");
@@ -0,0 +1,269 @@
+use serde_json::Value;
+
+/// Fixed per-message overhead (role markers, separators) added by chat templates.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Context window used when the model is unknown and CONTEXT_WINDOW is not set.
+const DEFAULT_CONTEXT_WINDOW: usize = 32_000;
+
+/// Upper bound on the share of the window reserved for the model's reply.
+const MAX_RESPONSE_RESERVE: usize = 8_192;
+
+/// Estimates the token count of a piece of text without a model-specific tokenizer.
+/// BPE tokenizers average roughly four characters per token for English and code,
+/// so this errs slightly on the high side for prose, which is the safe direction.
+pub fn estimate_text_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimates the tokens a single history message (OpenAI chat format) will consume,
+/// including tool call names and arguments.
+pub fn estimate_message_tokens(msg: &Value) -> usize {
+    let mut tokens = MESSAGE_OVERHEAD_TOKENS;
+    match msg.get("content") {
+        Some(Value::String(s)) => tokens += estimate_text_tokens(s),
+        Some(Value::Null) | None => {}
+        Some(other) => tokens += estimate_text_tokens(&other.to_string()),
+    }
+    if let Some(tool_calls) = msg.get("tool_calls").and_then(|tc| tc.as_array()) {
+        for tc in tool_calls {
+            let func = tc.get("function");
+            let name = func.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or("");
+            let args = func.and_then(|f| f.get("arguments")).and_then(|a| a.as_str()).unwrap_or("");
+            tokens += MESSAGE_OVERHEAD_TOKENS + estimate_text_tokens(name) + estimate_text_tokens(args);
+        }
+    }
+    tokens
+}
+
+pub fn estimate_history_tokens(history: &[Value]) -> usize {
+    history.iter().map(estimate_message_tokens).sum()
+}
+
+/// Returns the context window for a model, preferring an explicit override (non-zero).
+pub fn context_window_for_model(model: &str, configured: usize) -> usize {
+    if configured > 0 {
+        return configured;
+    }
+    let model = model.to_lowercase();
+    if model.contains("gemini") {
+        1_000_000
+    } else if model.contains("claude") {
+        200_000
+    } else if model.starts_with("gpt-4.1") {
+        1_000_000
+    } else if model.starts_with("gpt-4o") || model.starts_with("gpt-4-turbo") || model.starts_with("gpt-5") {
+        128_000
+    } else if model.starts_with('o') && model.chars().nth(1).is_some_and(|c| c.is_ascii_digit()) {
+        200_000
+    } else if model.starts_with("gpt-4") {
+        8_192
+    } else if model.starts_with("gpt-3.5") {
+        16_385
+    } else if model.contains("llama3") || model.contains("llama-3") {
+        8_192
+    } else {
+        DEFAULT_CONTEXT_WINDOW
+    }
+}
+
+/// Tokens left for conversation history once the system prompt, tool schemas and
+/// a reply reserve have been accounted for.
+pub fn history_budget(context_window: usize, fixed_overhead: usize) -> usize {
+    let reserve = (context_window / 4).min(MAX_RESPONSE_RESERVE);
+    context_window.saturating_sub(reserve).saturating_sub(fixed_overhead)
+}
+
+/// Splits history into groups that must be kept or dropped together: every message
+/// starts a group, except `tool` results which stay with the assistant message
+/// whose `tool_calls` they answer. Returns `(start, end)` index ranges.
+pub fn message_groups(history: &[Value]) -> Vec<(usize, usize)> {
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for (i, msg) in history.iter().enumerate() {
+        let is_tool = msg.get("role").and_then(|r| r.as_str()) == Some("tool");
+        match groups.last_mut() {
+            Some(last) if is_tool => last.1 = i + 1,
+            _ => groups.push((i, i + 1)),
+        }
+    }
+    groups
+}
+
+/// Drops the oldest message groups until the history fits in `budget` tokens.
+/// The first user message and the most recent group are never dropped, and
+/// tool-call/tool-result groups are removed as a unit. Returns the number of
+/// messages removed.
+pub fn trim_to_budget(history: &mut Vec<Value>, budget: usize) -> usize {
+    let mut total = estimate_history_tokens(history);
+    if total <= budget {
+        return 0;
+    }
+
+    let first_user = history
+        .iter()
+        .position(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"));
+    let groups = message_groups(history);
+    let last_group = groups.len().saturating_sub(1);
+
+    let mut remove = vec![false; history.len()];
+    for (idx, &(start, end)) in groups.iter().enumerate() {
+        if total <= budget || idx == last_group {
+            break;
+        }
+        if first_user.is_some_and(|f| f >= start && f < end) {
+            continue;
+        }
+        total -= estimate_history_tokens(&history[start..end]);
+        remove[start..end].iter_mut().for_each(|r| *r = true);
+    }
+
+    let removed = remove.iter().filter(|r| **r).count();
+    let mut flags = remove.into_iter();
+    history.retain(|_| !flags.next().unwrap_or(false));
+    removed
+}
+
+/// Picks the span of history to replace with a summary: everything after the first
+/// user message, except the most recent groups totalling up to `keep_recent_tokens`
+/// (the latest group is always kept). Returns `None` when there is nothing to summarize.
+pub fn compaction_range(history: &[Value], keep_recent_tokens: usize) -> Option<(usize, usize)> {
+    let groups = message_groups(history);
+    let first_user = history
+        .iter()
+        .position(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"));
+    let start = first_user
+        .and_then(|f| groups.iter().find(|&&(s, e)| f >= s && f < e))
+        .map(|&(_, e)| e)
+        .unwrap_or(0);
+
+    let mut end = history.len();
+    let mut kept = 0;
+    for &(s, e) in groups.iter().rev() {
+        if s < start {
+            break;
+        }
+        let tokens = estimate_history_tokens(&history[s..e]);
+        if end != history.len() && kept + tokens > keep_recent_tokens {
+            break;
+        }
+        kept += tokens;
+        end = s;
+    }
+
+    if end > start {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text(role: &str, len: usize) -> Value {
+        json!({"role": role, "content": "x".repeat(len)})
+    }
+
+    #[test]
+    fn test_estimate_text_tokens() {
+        assert_eq!(estimate_text_tokens(""), 0);
+        assert_eq!(estimate_text_tokens("abcd"), 1);
+        assert_eq!(estimate_text_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn test_context_window_override_and_defaults() {
+        assert_eq!(context_window_for_model("gpt-4o-mini", 0), 128_000);
+        assert_eq!(context_window_for_model("gemini-2.5-flash", 0), 1_000_000);
+        assert_eq!(context_window_for_model("unknown-model", 0), DEFAULT_CONTEXT_WINDOW);
+        assert_eq!(context_window_for_model("gpt-4o-mini", 4096), 4096);
+    }
+
+    #[test]
+    fn test_message_groups_keep_tool_results_with_call() {
+        let history = vec![
+            text("user", 4),
+            json!({"role": "assistant", "content": null, "tool_calls": [
+                {"id": "a", "type": "function", "function": {"name": "x", "arguments": "{}"}},
+                {"id": "b", "type": "function", "function": {"name": "y", "arguments": "{}"}}
+            ]}),
+            json!({"role": "tool", "tool_call_id": "a", "content": "ok"}),
+            json!({"role": "tool", "tool_call_id": "b", "content": "ok"}),
+            text("assistant", 4),
+        ];
+        assert_eq!(message_groups(&history), vec![(0, 1), (1, 4), (4, 5)]);
+    }
+
+    #[test]
+    fn test_trim_under_budget_is_noop() {
+        let mut history = vec![text("user", 40), text("assistant", 40)];
+        assert_eq!(trim_to_budget(&mut history, 1_000), 0);
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn test_trim_keeps_first_user_message_and_latest() {
+        let mut history = vec![
+            text("user", 400),
+            text("assistant", 400),
+            text("user", 400),
+            text("assistant", 400),
+            text("user", 400),
+        ];
+        let removed = trim_to_budget(&mut history, 250);
+        assert_eq!(removed, 3);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["role"], "user");
+        assert_eq!(history[0]["content"].as_str().unwrap().len(), 400);
+        assert_eq!(history[1]["role"], "user");
+    }
+
+    #[test]
+    fn test_trim_never_splits_tool_group() {
+        let mut history = vec![
+            text("user", 40),
+            json!({"role": "assistant", "content": null, "tool_calls": [
+                {"id": "a", "type": "function", "function": {"name": "x", "arguments": "{}"}}
+            ]}),
+            json!({"role": "tool", "tool_call_id": "a", "content": "y".repeat(4000)}),
+            text("assistant", 40),
+            text("user", 40),
+        ];
+        trim_to_budget(&mut history, 100);
+        assert!(history.iter().all(|m| m["role"] != "tool"));
+        assert!(history.iter().all(|m| m.get("tool_calls").is_none()));
+        assert_eq!(history.first().unwrap()["role"], "user");
+    }
+
+    #[test]
+    fn test_compaction_range_skips_first_user_and_latest() {
+        let history = vec![
+            text("user", 40),
+            text("assistant", 40),
+            text("user", 40),
+            text("assistant", 40),
+        ];
+        assert_eq!(compaction_range(&history, 0), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_compaction_range_keeps_recent_budget() {
+        let history = vec![
+            text("user", 40),
+            text("assistant", 40),
+            text("user", 40),
+            text("assistant", 40),
+        ];
+        // Each message is 14 tokens; 28 keeps the last two messages.
+        assert_eq!(compaction_range(&history, 28), Some((1, 2)));
+    }
+
+    #[test]
+    fn test_compaction_range_nothing_to_summarize() {
+        let history = vec![text("user", 40), text("assistant", 40)];
+        assert_eq!(compaction_range(&history, 0), None);
+        assert_eq!(compaction_range(&[], 0), None);
+    }
+}
@@ -10,9 +10,9 @@ use anyhow::Result;
 use crate::patch::apply_patch;
 
 /// Maximum allowed file size for read/write operations (10 MB)
-const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+pub(crate) const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
 
-const CANCELLATION_MESSAGE: &str = "User has cancelled this operation because it is against their wishes. Do not attempt any alternative approaches or modifications. Wait for further instructions.";
+pub(crate) const CANCELLATION_MESSAGE: &str = "User has cancelled this operation because it is against their wishes. Do not attempt any alternative approaches or modifications. Wait for further instructions.";
 
 pub(crate) fn resolve_sandbox_path(filename: &str) -> Result<PathBuf, String> {
     if std::path::Path::new(filename)
@@ -100,40 +100,6 @@ fn handle_read(file_path: &PathBuf, filename: &str) -> (String, bool) {
     }
 }
 
-fn handle_write(
-    file_path: &PathBuf,
-    filename: &str,
-    data: Option<&str>,
-    skip_confirmation: bool,
-) -> (String, bool) {
-    let new_content = data.unwrap_or("");
-    let content_size = new_content.len() as u64;
-    if content_size > MAX_FILE_SIZE {
-        return (
-            format!(
-                "Error: Content too large ({} bytes, max is {} bytes)",
-                content_size, MAX_FILE_SIZE
-            ),
-            false,
-        );
-    }
-    let current_content = fs::read_to_string(file_path).unwrap_or_default();
-    if let Err(msg) = confirm_and_apply_change(
-        &current_content,
-        new_content,
-        filename,
-        "writing to",
-        skip_confirmation,
-    ) {
-        let is_cancel = msg == CANCELLATION_MESSAGE;
-        return (msg, is_cancel);
-    }
-    match fs::write(file_path, new_content) {
-        Ok(()) => (format!("Successfully wrote to '{}'", filename), false),
-        Err(e) => (format!("Error writing to '{}': {}", filename, e), false),
-    }
-}
-
 fn handle_search(file_path: &PathBuf, filename: &str, data: Option<&str>) -> (String, bool) {
     let pattern = match data {
         Some(p) => p,
@@ -204,149 +170,164 @@ fn handle_search(file_path: &PathBuf, filename: &str, data: Option<&str>) -> (St
     }
 }
 
-fn handle_search_and_replace(
+/// Computes the result of a mutating operation (`write`, `search_and_replace`,
+/// `apply_diff`) without touching the file. Returns the original content, the new
+/// content and the success message, or a user-facing error.
+fn planned_change(
     file_path: &PathBuf,
     filename: &str,
+    subcommand: &str,
     data: Option<&str>,
     replacement: Option<&str>,
-    skip_confirmation: bool,
-) -> (String, bool) {
-    let pattern = match data {
-        Some(p) => p,
-        None => {
-            return (
-                "Error: 'data' parameter with regex pattern is required for search_and_replace"
-                    .to_string(),
-                false,
-            )
-        }
-    };
-    let replace_with = match replacement {
-        Some(r) => r,
-        None => {
-            return (
-                "Error: 'replacement' parameter is required for search_and_replace".to_string(),
-                false,
-            );
+) -> Result<(String, String, String), String> {
+    match subcommand {
+        "write" => {
+            let new_content = data.unwrap_or("");
+            let content_size = new_content.len() as u64;
+            if content_size > MAX_FILE_SIZE {
+                return Err(format!(
+                    "Error: Content too large ({} bytes, max is {} bytes)",
+                    content_size, MAX_FILE_SIZE
+                ));
+            }
+            let current_content = fs::read_to_string(file_path).unwrap_or_default();
+            Ok((
+                current_content,
+                new_content.to_string(),
+                format!("Successfully wrote to '{}'", filename),
+            ))
         }
-    };
-    let replace_size = replace_with.len() as u64;
-    if replace_size > MAX_FILE_SIZE {
-        return (
-            format!(
-                "Error: Replacement text too large ({} bytes, max is {} bytes)",
-                replace_size, MAX_FILE_SIZE
-            ),
-            false,
-        );
-    }
-    match Regex::new(pattern) {
-        Ok(re) => {
+        "search_and_replace" => {
+            let pattern = data.ok_or_else(|| {
+                "Error: 'data' parameter with regex pattern is required for search_and_replace"
+                    .to_string()
+            })?;
+            let replace_with = replacement.ok_or_else(|| {
+                "Error: 'replacement' parameter is required for search_and_replace".to_string()
+            })?;
+            let replace_size = replace_with.len() as u64;
+            if replace_size > MAX_FILE_SIZE {
+                return Err(format!(
+                    "Error: Replacement text too large ({} bytes, max is {} bytes)",
+                    replace_size, MAX_FILE_SIZE
+                ));
+            }
+            let re = Regex::new(pattern)
+                .map_err(|e| format!("Error compiling regex pattern '{}': {}", pattern, e))?;
             match fs::metadata(file_path) {
                 Ok(metadata) if metadata.len() > MAX_FILE_SIZE => {
-                    return (
-                        format!(
-                            "Error: File '{}' is too large ({} bytes, max is {} bytes)",
-                            filename,
-                            metadata.len(),
-                            MAX_FILE_SIZE
-                        ),
-                        false,
-                    );
+                    return Err(format!(
+                        "Error: File '{}' is too large ({} bytes, max is {} bytes)",
+                        filename,
+                        metadata.len(),
+                        MAX_FILE_SIZE
+                    ));
                 }
                 Ok(_) => {}
-                Err(e) => {
-                    return (
-                        format!("Error reading file metadata '{}': {}", filename, e),
-                        false,
-                    )
-                }
+                Err(e) => return Err(format!("Error reading file metadata '{}': {}", filename, e)),
             }
-            match fs::read_to_string(file_path) {
-                Ok(content) => {
-                    let new_content = re.replace_all(&content, replace_with);
-                    if let Err(msg) = confirm_and_apply_change(
-                        &content,
-                        &new_content,
-                        filename,
-                        "search and replace in",
-                        skip_confirmation,
-                    ) {
-                        let is_cancel = msg == CANCELLATION_MESSAGE;
-                        return (msg, is_cancel);
-                    }
-                    let result_size = new_content.len() as u64;
-                    if result_size > MAX_FILE_SIZE {
-                        return (
-                            format!(
-                                "Error: Resulting content too large ({} bytes, max is {} bytes)",
-                                result_size, MAX_FILE_SIZE
-                            ),
-                            false,
-                        );
-                    }
-                    match fs::write(file_path, new_content.as_ref()) {
-                        Ok(()) => (
-                            format!(
-                                "Successfully replaced pattern '{}' with '{}' in '{}'",
-                                pattern, replace_with, filename
-                            ),
-                            false,
-                        ),
-                        Err(e) => (format!("Error writing to '{}': {}", filename, e), false),
-                    }
-                }
-                Err(e) => (format!("Error reading file '{}': {}", filename, e), false),
+            let content = fs::read_to_string(file_path)
+                .map_err(|e| format!("Error reading file '{}': {}", filename, e))?;
+            let new_content = re.replace_all(&content, replace_with).into_owned();
+            let result_size = new_content.len() as u64;
+            if result_size > MAX_FILE_SIZE {
+                return Err(format!(
+                    "Error: Resulting content too large ({} bytes, max is {} bytes)",
+                    result_size, MAX_FILE_SIZE
+                ));
             }
+            Ok((
+                content,
+                new_content,
+                format!(
+                    "Successfully replaced pattern '{}' with '{}' in '{}'",
+                    pattern, replace_with, filename
+                ),
+            ))
         }
-        Err(e) => (
-            format!("Error compiling regex pattern '{}': {}", pattern, e),
-            false,
-        ),
+        "apply_diff" => {
+            let diff_content = data.ok_or_else(|| {
+                "Error: 'data' parameter with diff content is required for apply_diff".to_string()
+            })?;
+            let original_content = fs::read_to_string(file_path)
+                .map_err(|e| format!("Error reading file '{}': {}", filename, e))?;
+            let new_content = apply_patch(&original_content, diff_content)
+                .map_err(|e| format!("Error parsing or applying diff: {}", e))?;
+            Ok((
+                original_content,
+                new_content,
+                format!("Successfully applied diff to '{}'", filename),
+            ))
+        }
+        _ => Err(format!("Error: Unknown subcommand '{}'", subcommand)),
     }
 }
 
-fn handle_apply_diff(
+fn handle_change(
     file_path: &PathBuf,
     filename: &str,
+    subcommand: &str,
     data: Option<&str>,
+    replacement: Option<&str>,
     skip_confirmation: bool,
 ) -> (String, bool) {
-    let diff_content = match data {
-        Some(d) => d,
-        None => {
-            return (
-                "Error: 'data' parameter with diff content is required for apply_diff".to_string(),
-                false,
-            );
-        }
+    let (old_content, new_content, success) =
+        match planned_change(file_path, filename, subcommand, data, replacement) {
+            Ok(change) => change,
+            Err(e) => return (e, false),
+        };
+    let operation_desc = match subcommand {
+        "write" => "writing to",
+        "search_and_replace" => "search and replace in",
+        _ => "applying diff to",
     };
+    if let Err(msg) = confirm_and_apply_change(
+        &old_content,
+        &new_content,
+        filename,
+        operation_desc,
+        skip_confirmation,
+    ) {
+        let is_cancel = msg == CANCELLATION_MESSAGE;
+        return (msg, is_cancel);
+    }
+    match fs::write(file_path, &new_content) {
+        Ok(()) => (success, false),
+        Err(e) => (format!("Error writing to '{}': {}", filename, e), false),
+    }
+}
 
-    match fs::read_to_string(file_path) {
-        Ok(original_content) => match apply_patch(&original_content, diff_content) {
-            Ok(new_content) => {
-                if let Err(msg) = confirm_and_apply_change(
-                    &original_content,
-                    &new_content,
-                    filename,
-                    "applying diff to",
-                    skip_confirmation,
-                ) {
-                    let is_cancel = msg == CANCELLATION_MESSAGE;
-                    return (msg, is_cancel);
-                }
-                match fs::write(file_path, &new_content) {
-                    Ok(()) => (
-                        format!("Successfully applied diff to '{}'", filename),
-                        false,
-                    ),
-                    Err(e) => (format!("Error writing to '{}': {}", filename, e), false),
-                }
-            }
-            Err(e) => (format!("Error parsing or applying diff: {}", e), false),
-        },
-        Err(e) => (format!("Error reading file '{}': {}", filename, e), false),
+/// Whether a `file_editor` subcommand only reads (and so never needs confirmation).
+pub(crate) fn is_read_only(subcommand: &str) -> bool {
+    matches!(subcommand, "read" | "search")
+}
+
+/// Renders the diff a mutating operation would apply, one line per change prefixed
+/// with ` `, `-` or `+`. Returns `None` for read-only or invalid operations; the
+/// error is reported when the operation actually runs.
+pub(crate) fn preview_change(
+    subcommand: &str,
+    filename: &str,
+    data: Option<&str>,
+    replacement: Option<&str>,
+) -> Option<String> {
+    if is_read_only(subcommand) {
+        return None;
+    }
+    let file_path = resolve_sandbox_path(filename).ok()?;
+    let (old_content, new_content, _) =
+        planned_change(&file_path, filename, subcommand, data, replacement).ok()?;
+    let changeset = Changeset::new(&old_content, &new_content, "\n");
+    let mut lines = Vec::new();
+    for diff in &changeset.diffs {
+        let (prefix, text) = match diff {
+            Difference::Same(s) => (' ', s),
+            Difference::Rem(s) => ('-', s),
+            Difference::Add(s) => ('+', s),
+        };
+        lines.extend(text.split('\n').map(|line| format!("{}{}", prefix, line)));
     }
+    Some(lines.join("\n"))
 }
 
 pub fn file_editor(
@@ -372,12 +353,15 @@ pub fn file_editor(
 
     let (result, rejected) = match subcommand {
         "read" => handle_read(&file_path, filename),
-        "write" => handle_write(&file_path, filename, data, skip_confirmation),
         "search" => handle_search(&file_path, filename, data),
-        "search_and_replace" => {
-            handle_search_and_replace(&file_path, filename, data, replacement, skip_confirmation)
-        }
-        "apply_diff" => handle_apply_diff(&file_path, filename, data, skip_confirmation),
+        "write" | "search_and_replace" | "apply_diff" => handle_change(
+            &file_path,
+            filename,
+            subcommand,
+            data,
+            replacement,
+            skip_confirmation,
+        ),
         _ => (format!("Error: Unknown subcommand '{}'", subcommand), false),
     };
 
@@ -556,4 +540,18 @@ mod tests {
         let _ = fs::remove_file(target_file);
         assert!(result.contains("symlink target content"));
     }
+
+    #[test]
+    fn test_preview_change_marks_replaced_lines() {
+        let test_file = "test_preview_change.tmp";
+        fs::write(test_file, "alpha\nbeta\n").unwrap();
+        let preview = preview_change("search_and_replace", test_file, Some("beta"), Some("gamma"));
+        let unchanged = fs::read_to_string(test_file).unwrap();
+        let _ = fs::remove_file(test_file);
+        let preview = preview.unwrap();
+        assert!(preview.contains("-beta"));
+        assert!(preview.contains("+gamma"));
+        assert_eq!(unchanged, "alpha\nbeta\n");
+        assert!(preview_change("read", test_file, None, None).is_none());
+    }
 }
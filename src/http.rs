@@ -2,6 +2,7 @@ use reqwest::Client;
 use std::sync::OnceLock;
 
 static ASYNC_HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
+static LLM_HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
 
 pub fn create_async_http_client() -> Client {
     ASYNC_HTTP_CLIENT.get_or_init(|| {
@@ -12,4 +13,17 @@ pub fn create_async_http_client() -> Client {
             .build()
             .unwrap_or_else(|_| Client::new())
     }).clone()
-}
\ No newline at end of file
+}
+
+/// Client for LLM APIs. Responses can stream for minutes, so instead of a total
+/// timeout it only fails when the connection stalls.
+pub fn create_llm_http_client() -> Client {
+    LLM_HTTP_CLIENT.get_or_init(|| {
+        Client::builder()
+            .connect_timeout(std::time::Duration::from_secs(30))
+            .read_timeout(std::time::Duration::from_secs(300))
+            .user_agent("ai-cli/1.0")
+            .build()
+            .unwrap_or_else(|_| Client::new())
+    }).clone()
+}
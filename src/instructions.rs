@@ -0,0 +1,141 @@
+//! Instruction files appended to the system prompt: a user-global one in the home
+//! directory and per-project ones from the sandbox root up to the git root.
+
+use std::path::{Path, PathBuf};
+
+/// Names looked up in each directory; the first one found wins.
+const FILE_NAMES: [&str; 2] = ["AICLI.md", ".aicli/instructions.md"];
+
+/// Instruction files are truncated past this size so one stray file cannot fill the context.
+const MAX_INSTRUCTIONS_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InstructionFile {
+    pub(crate) path: PathBuf,
+    pub(crate) content: String,
+}
+
+fn find_in(dir: &Path) -> Option<PathBuf> {
+    FILE_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file())
+}
+
+/// The sandbox root and its parents up to the enclosing git root, outermost first.
+/// Outside a git repository only the sandbox root itself is searched.
+fn project_dirs(root: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for dir in root.ancestors() {
+        dirs.push(dir.to_path_buf());
+        if dir.join(".git").exists() {
+            dirs.reverse();
+            return dirs;
+        }
+    }
+    vec![root.to_path_buf()]
+}
+
+/// Instruction file paths in the order they are appended: the global file first, then
+/// project files from the git root down, so the most specific comes last.
+fn discover(root: &Path, home: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = home.and_then(find_in).into_iter().collect();
+    for dir in project_dirs(root) {
+        if let Some(path) = find_in(&dir).filter(|p| !paths.contains(p)) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+fn read(path: PathBuf) -> Option<InstructionFile> {
+    let mut content = std::fs::read_to_string(&path).ok()?;
+    if content.trim().is_empty() {
+        return None;
+    }
+    if content.len() > MAX_INSTRUCTIONS_SIZE {
+        let end = (0..=MAX_INSTRUCTIONS_SIZE).rev().find(|&i| content.is_char_boundary(i)).unwrap_or(0);
+        content.truncate(end);
+        content.push_str("\n[truncated]");
+    }
+    Some(InstructionFile { path, content })
+}
+
+/// Loads the instruction files for the current sandbox root. Unreadable files are skipped.
+pub(crate) fn load() -> Vec<InstructionFile> {
+    let root = Path::new(crate::sandbox::get_sandbox_root());
+    discover(root, dirs::home_dir().as_deref())
+        .into_iter()
+        .filter_map(read)
+        .collect()
+}
+
+/// Appends the instruction files to the base system prompt.
+pub(crate) fn append_to(base: &str, files: &[InstructionFile]) -> String {
+    let mut prompt = base.to_string();
+    for file in files {
+        prompt.push_str(&format!(
+            "\n\nInstructions from {} (follow them unless the user says otherwise):\n{}",
+            file.path.display(),
+            file.content.trim_end()
+        ));
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aicli_instructions_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_discover_walks_up_to_git_root() {
+        let base = temp_dir("walk");
+        let repo = base.join("repo");
+        let sub = repo.join("crates/app");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(sub.join(".aicli")).unwrap();
+        std::fs::create_dir_all(base.join("home/.aicli")).unwrap();
+        // Above the git root: ignored.
+        std::fs::write(base.join("AICLI.md"), "outside").unwrap();
+        std::fs::write(repo.join("AICLI.md"), "repo").unwrap();
+        std::fs::write(sub.join(".aicli/instructions.md"), "crate").unwrap();
+        std::fs::write(base.join("home/.aicli/instructions.md"), "global").unwrap();
+
+        let paths = discover(&sub, Some(&base.join("home")));
+        assert_eq!(
+            paths,
+            [base.join("home/.aicli/instructions.md"), repo.join("AICLI.md"), sub.join(".aicli/instructions.md")]
+        );
+
+        // Outside a repository only the sandbox root is searched.
+        std::fs::remove_dir_all(repo.join(".git")).unwrap();
+        assert_eq!(discover(&sub, None), [sub.join(".aicli/instructions.md")]);
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_append_to_names_each_file() {
+        let files = [InstructionFile { path: PathBuf::from("/repo/AICLI.md"), content: "Use tabs.\n".into() }];
+        let prompt = append_to("Base prompt.", &files);
+        assert_eq!(
+            prompt,
+            "Base prompt.\n\nInstructions from /repo/AICLI.md (follow them unless the user says otherwise):\nUse tabs."
+        );
+        assert_eq!(append_to("Base prompt.", &[]), "Base prompt.");
+    }
+
+    #[test]
+    fn test_large_files_are_truncated() {
+        let dir = temp_dir("large");
+        let path = dir.join("AICLI.md");
+        std::fs::write(&path, "é".repeat(MAX_INSTRUCTIONS_SIZE)).unwrap();
+        let file = read(path).unwrap();
+        assert!(file.content.ends_with("\n[truncated]"));
+        assert!(file.content.len() <= MAX_INSTRUCTIONS_SIZE + "\n[truncated]".len());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,230 @@
+//! Background jobs: commands started with `execute_command` and `background: true`
+//! keep running in the sandbox while the conversation goes on. Their output is
+//! logged so the model can poll it incrementally by byte offset.
+
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::time::Duration;
+
+use crate::command::{execute_command, CommandObserver, CommandOptions};
+
+/// Most jobs running at the same time.
+pub const MAX_RUNNING_JOBS: usize = 8;
+
+/// Output kept per job; older output is dropped from the front.
+const JOB_LOG_BYTES: usize = 1024 * 1024;
+
+/// How long `kill` waits for the job to report that it ended.
+const KILL_WAIT: Duration = Duration::from_secs(3);
+
+static JOBS: Mutex<Vec<Arc<Job>>> = Mutex::new(Vec::new());
+static NEXT_JOB_ID: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Debug, Default)]
+struct JobLog {
+    text: String,
+    /// Bytes dropped from the front of `text`, so offsets stay stable
+    dropped: usize,
+    /// Final status such as `exit code 0`; `None` while running
+    status: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: u32,
+    pub command: String,
+    started: Instant,
+    cancel: Arc<AtomicBool>,
+    log: Mutex<JobLog>,
+}
+
+impl CommandObserver for Job {
+    fn started(&self, _command: &str) {}
+
+    fn line(&self, line: &str) {
+        let mut log = self.log.lock().unwrap();
+        log.text.push_str(line);
+        log.text.push('\n');
+        if log.text.len() > JOB_LOG_BYTES {
+            let mut cut = log.text.len() - JOB_LOG_BYTES;
+            while !log.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            log.text.drain(..cut);
+            log.dropped += cut;
+        }
+    }
+
+    fn finished(&self, status: &str, _success: bool) {
+        self.log.lock().unwrap().status = Some(status.to_string());
+    }
+}
+
+/// A point-in-time view of a job, for status reports and the TUI sidebar.
+#[derive(Debug, Clone)]
+pub struct JobSummary {
+    pub id: u32,
+    pub command: String,
+    pub elapsed: Duration,
+    pub status: Option<String>,
+    /// Total bytes of output so far, i.e. the offset after the last byte
+    pub output_bytes: usize,
+}
+
+impl JobSummary {
+    pub fn is_running(&self) -> bool {
+        self.status.is_none()
+    }
+
+    /// e.g. `job 2: running for 35s, 1204 bytes of output: npm run dev`
+    pub fn describe(&self) -> String {
+        let state = match &self.status {
+            None => format!("running for {}s", self.elapsed.as_secs()),
+            Some(status) => format!("ended ({})", status),
+        };
+        format!("job {}: {}, {} bytes of output: {}", self.id, state, self.output_bytes, self.command)
+    }
+}
+
+impl Job {
+    pub fn summary(&self) -> JobSummary {
+        let log = self.log.lock().unwrap();
+        JobSummary {
+            id: self.id,
+            command: self.command.clone(),
+            elapsed: self.started.elapsed(),
+            status: log.status.clone(),
+            output_bytes: log.dropped + log.text.len(),
+        }
+    }
+
+    /// Output from byte `offset` on, at most `max_bytes` of it, and the offset to
+    /// continue from.
+    pub fn output_since(&self, offset: usize, max_bytes: usize) -> (String, usize) {
+        let log = self.log.lock().unwrap();
+        let mut note = String::new();
+        let mut start = offset.saturating_sub(log.dropped).min(log.text.len());
+        if offset < log.dropped {
+            note = format!("[... output before offset {} was dropped ...]\n", log.dropped);
+        }
+        while !log.text.is_char_boundary(start) {
+            start += 1;
+        }
+        let mut end = (start + max_bytes).min(log.text.len());
+        while !log.text.is_char_boundary(end) {
+            end -= 1;
+        }
+        (format!("{}{}", note, &log.text[start..end]), log.dropped + end)
+    }
+
+    pub fn kill(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Starts `command` in the background and returns its job. `options` supply the
+/// timeout and output limits; cancellation and output capture are the job's own.
+pub fn start_job(command: &str, options: CommandOptions, debug: bool) -> Result<Arc<Job>> {
+    let mut jobs = JOBS.lock().unwrap();
+    let running = jobs.iter().filter(|j| j.summary().is_running()).count();
+    if running >= MAX_RUNNING_JOBS {
+        return Err(anyhow!(
+            "Too many background jobs ({} running); kill one with job_kill first",
+            running
+        ));
+    }
+    let job = Arc::new(Job {
+        id: NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed),
+        command: command.to_string(),
+        started: Instant::now(),
+        cancel: Arc::new(AtomicBool::new(false)),
+        log: Mutex::new(JobLog::default()),
+    });
+    jobs.push(job.clone());
+
+    let options = options.with_cancel(Some(job.cancel.clone())).with_observer(Some(job.clone()));
+    let running = job.clone();
+    tokio::spawn(async move {
+        let result = execute_command(&running.command, &options, debug).await;
+        let mut log = running.log.lock().unwrap();
+        if log.status.is_none() {
+            log.status = Some(match result {
+                Ok(message) => message,
+                Err(e) => format!("failed: {}", e),
+            });
+        }
+    });
+    Ok(job)
+}
+
+pub fn find_job(id: u32) -> Option<Arc<Job>> {
+    JOBS.lock().unwrap().iter().find(|j| j.id == id).cloned()
+}
+
+pub fn list_jobs() -> Vec<JobSummary> {
+    JOBS.lock().unwrap().iter().map(|j| j.summary()).collect()
+}
+
+/// Kills a job and waits briefly for it to end.
+pub async fn kill_job(job: &Job) -> JobSummary {
+    job.kill();
+    let deadline = Instant::now() + KILL_WAIT;
+    while job.summary().is_running() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    job.summary()
+}
+
+/// Kills every job and forgets them, e.g. when the conversation is cleared.
+pub fn kill_all_jobs() {
+    for job in JOBS.lock().unwrap().drain(..) {
+        job.kill();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> Job {
+        Job {
+            id: 1,
+            command: "test".to_string(),
+            started: Instant::now(),
+            cancel: Arc::new(AtomicBool::new(false)),
+            log: Mutex::new(JobLog::default()),
+        }
+    }
+
+    #[test]
+    fn test_output_since_offset() {
+        let job = job();
+        job.line("first");
+        job.line("second");
+        let (text, next) = job.output_since(0, 100);
+        assert_eq!((text.as_str(), next), ("first\nsecond\n", 13));
+        let (text, next) = job.output_since(6, 3);
+        assert_eq!((text.as_str(), next), ("sec", 9));
+        let (text, next) = job.output_since(next, 100);
+        assert_eq!((text.as_str(), next), ("ond\n", 13));
+        assert_eq!(job.output_since(13, 100), (String::new(), 13));
+    }
+
+    #[test]
+    fn test_log_drops_oldest_output_but_keeps_offsets() {
+        let job = job();
+        let line = "x".repeat(1023);
+        for _ in 0..1100 {
+            job.line(&line);
+        }
+        let summary = job.summary();
+        assert_eq!(summary.output_bytes, 1100 * 1024);
+        let (text, next) = job.output_since(0, 10);
+        assert!(text.starts_with("[... output before offset 77824 was dropped ...]\n"), "{}", text);
+        assert_eq!(next, 77824 + 10);
+        job.finished("exit code 0", true);
+        assert!(job.summary().describe().contains("ended (exit code 0)"));
+    }
+}
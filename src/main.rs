@@ -1,9 +1,8 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use anyhow::Result;
 use colored::{Color, Colorize};
 use std::collections::HashSet;
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
 use tokio::sync::Mutex;
 use rustyline::DefaultEditor;
 use rustyline::error::ReadlineError;
@@ -11,11 +10,15 @@ use build_time::build_time_local;
 use tokio::process::Command;
 
 mod config;
-use config::Config;
+use config::{Config, ReasoningEffort, SandboxProfile, Sampling};
 use config::mask_value;
 
+mod attachments;
 mod chat;
+mod context;
+mod provider;
 mod shell;
+mod shell_session;
 mod tools;
 mod search;
 mod scrape;
@@ -23,18 +26,27 @@ mod scrape;
 mod tui;
 mod patch;
 mod command;
+mod command_rules;
 mod email;
 mod alpha_vantage;
 mod file_edit;
+mod instructions;
+mod jobs;
 mod sandbox;
+mod session;
+mod usage;
 mod http;
+mod mcp;
+mod output;
 mod utils;
 
 use crate::chat::ChatManager;
-use crate::tools::{display_response, process_tool_calls};
+use crate::tools::{print_cancelled, process_tool_calls, stream_llm_response, ApprovalMemory, ToolPolicy};
+use crate::output::{JsonReporter, OutputFormat};
 use crate::utils::{log_to_file, print_error, clear_debug_file};
 use crate::shell::interactive_shell;
-use crate::command::execute_command;
+use crate::command::{execute_command, CommandOptions};
+use crate::session::SessionStore;
 use sandbox::get_sandbox_root;
 
 const COMPILE_TIME: &str = build_time_local!("%Y-%m-%d %H:%M:%S");
@@ -44,35 +56,35 @@ async fn handle_llm_response(
     chat_manager: Arc<Mutex<ChatManager>>,
     debug: bool,
     quiet: bool,
-    allow_commands: bool,
     process_tools: bool,
-    always_approve: &Arc<AtomicBool>,
-    allowed_tools: &HashSet<String>,
+    policy: &ToolPolicy,
+    reporter: Option<&JsonReporter>,
 ) -> Result<()> {
-    display_response(response);
     if !quiet {
+        if let Some(timings) = crate::provider::timing_summary(response) {
+            println!("{}", timings.dimmed());
+        }
         crate::tools::add_block_spacing();
     }
     if process_tools {
-        process_tool_calls(response, &chat_manager, debug, quiet, allow_commands, always_approve, allowed_tools).await?;
+        process_tool_calls(response, &chat_manager, debug, quiet, policy, reporter).await?;
     }
     Ok(())
 }
 
-async fn send_llm_input(chat_manager: Arc<Mutex<ChatManager>>, llm_input: String, args: &Args, always_approve: &Arc<AtomicBool>) -> Result<()> {
+async fn send_llm_input(chat_manager: Arc<Mutex<ChatManager>>, llm_input: String, args: &Args, policy: &ToolPolicy) -> Result<()> {
     // Lock, push user message, extract data, release lock
-    let llm_data = {
-        let mut manager = chat_manager.lock().await;
-        manager.push_user_message(&llm_input, args.debug);
-        manager.prepare_llm_call()
-    };
+    chat_manager.lock().await.push_user_message(&llm_input, args.debug);
+    crate::tools::compact_and_report(&chat_manager, false, args.debug, false).await;
+    let llm_data = chat_manager.lock().await.prepare_llm_call();
     // LLM call without holding the lock
-    match crate::chat::call_llm_api(&llm_data, false, args.debug).await {
-        Ok(llm_result) => {
+    match stream_llm_response(&llm_data, false, args.debug, None).await {
+        Ok(Some(llm_result)) => {
             // Re-acquire lock to update history
             chat_manager.lock().await.apply_llm_result(&llm_result);
-            handle_llm_response(&llm_result.response, chat_manager.clone(), args.debug, false, false, true, always_approve, &HashSet::new()).await?;
+            handle_llm_response(&llm_result.response, chat_manager.clone(), args.debug, false, true, policy, None).await?;
         },
+        Ok(None) => print_cancelled(),
         Err(e) => print_error(&format!("Error: {}", e)),
     }
     Ok(())
@@ -83,7 +95,7 @@ async fn handle_user_input(
     rl: &mut DefaultEditor,
     chat_manager: Arc<Mutex<ChatManager>>,
     args: &Args,
-    always_approve: &Arc<AtomicBool>,
+    policy: &ToolPolicy,
 ) -> Result<bool> {
     // Add to history (skip empty lines and special commands)
     let input_lower = user_input.to_lowercase();
@@ -105,6 +117,34 @@ async fn handle_user_input(
             println!();
             return Ok(true);
         }
+        "compact" => {
+            crate::tools::compact_and_report(&chat_manager, true, args.debug, false).await;
+            return Ok(true);
+        }
+        "models" => {
+            crate::tools::show_models(&chat_manager, None).await;
+            return Ok(true);
+        }
+        "instructions" => {
+            println!("{}", chat_manager.lock().await.system_instruction());
+            println!();
+            return Ok(true);
+        }
+        "usage" => {
+            println!("{}", chat_manager.lock().await.usage().report().color(Color::Cyan));
+            println!();
+            return Ok(true);
+        }
+        _ if input_lower == "permissions" || input_lower.starts_with("permissions ") => {
+            println!("{}", policy.approvals().command(&user_input["permissions".len()..]).color(Color::Cyan));
+            println!();
+            return Ok(true);
+        }
+        _ if input_lower.starts_with("models pull ") => {
+            let name = user_input["models pull ".len()..].trim();
+            crate::tools::show_models(&chat_manager, Some(name)).await;
+            return Ok(true);
+        }
         "" => {
             println!("{}", "Please enter a command or message.".color(Color::Red));
             println!();
@@ -115,26 +155,30 @@ async fn handle_user_input(
 
     if let Some(command) = user_input.strip_prefix('!') {
         let command: &str = command.trim();
+         let options = CommandOptions::from_config(chat_manager.lock().await.get_config());
          if command.is_empty() {
-             let output = interactive_shell(args.debug).await?;
+             let output = interactive_shell(&options, args.debug).await?;
          let llm_input = format!("User ran interactive shell session with output:\n{}", output);
-         send_llm_input(chat_manager.clone(), llm_input, args, always_approve).await?;
+         send_llm_input(chat_manager.clone(), llm_input, args, policy).await?;
          } else {
-            let output = execute_command(command, args.debug).await.unwrap_or_else(|e| e.to_string());
+            let output = execute_command(command, &options, args.debug).await.unwrap_or_else(|e| e.to_string());
             let llm_input = format!("User ran command '!{}' with output: {}", command, output);
             println!("{}", output);
-            send_llm_input(chat_manager.clone(), llm_input, args, always_approve).await?;
+            send_llm_input(chat_manager.clone(), llm_input, args, policy).await?;
          }
      } else {
             // Lock, push user message, extract data, release lock
-            let llm_data = {
-                let mut manager = chat_manager.lock().await;
-                manager.push_user_message(user_input, args.debug);
-                manager.prepare_llm_call()
-            };
+            chat_manager.lock().await.push_user_message(user_input, args.debug);
+            crate::tools::compact_and_report(&chat_manager, false, args.debug, false).await;
+            let llm_data = chat_manager.lock().await.prepare_llm_call();
+            println!(); // Add blank line before response
             // LLM call without holding the lock
-            let llm_result = match crate::chat::call_llm_api(&llm_data, false, args.debug).await {
-                Ok(result) => result,
+            let llm_result = match stream_llm_response(&llm_data, false, args.debug, None).await {
+                Ok(Some(result)) => result,
+                Ok(None) => {
+                    print_cancelled();
+                    return Ok(true);
+                }
                 Err(e) => {
                     println!(
                         "{}",
@@ -146,8 +190,7 @@ async fn handle_user_input(
             // Re-acquire lock to update history
             chat_manager.lock().await.apply_llm_result(&llm_result);
 
-            println!(); // Add blank line before response
-            if let Err(e) = handle_llm_response(&llm_result.response, chat_manager.clone(), args.debug, false, false, true, always_approve, &HashSet::new()).await {
+            if let Err(e) = handle_llm_response(&llm_result.response, chat_manager.clone(), args.debug, false, true, policy, None).await {
                 print_error(&format!("Error processing tool calls: {}", e));
             }
     }
@@ -159,12 +202,17 @@ async fn load_and_display_config(debug: bool) -> Result<Config> {
 
     if debug {
         log_to_file(debug, "=== AI Provider Configuration ===");
+        log_to_file(debug, &format!("Provider: {:?}", config.provider));
         log_to_file(debug, &format!("API Base URL: {}", config.api_base_url));
         log_to_file(debug, &format!("API Version: {}", config.api_version));
         log_to_file(debug, &format!("Model: {}", config.model));
         log_to_file(debug, &format!("API Key: {}***", &config.api_key.chars().take(4).collect::<String>()));
         log_to_file(debug, &format!("Endpoint: {}", config.get_api_endpoint()));
-        log_to_file(debug, "Auth Method: Header (Bearer)");
+        log_to_file(debug, match config.provider {
+            config::ProviderKind::OpenAi => "Auth Method: Header (Bearer)",
+            config::ProviderKind::Anthropic => "Auth Method: Header (x-api-key)",
+            config::ProviderKind::Ollama => "Auth Method: None",
+        });
         log_to_file(debug, "================================");
         log_to_file(debug, "=== SMTP Configuration ===");
         log_to_file(debug, &format!("SMTP_SERVER_IP: {}", config.smtp_server));
@@ -173,21 +221,38 @@ async fn load_and_display_config(debug: bool) -> Result<Config> {
         log_to_file(debug, &format!("DESTINATION_EMAIL: {}", mask_value(&config.destination_email, false)));
         log_to_file(debug, &format!("SENDER_EMAIL: {}", mask_value(&config.sender_email, false)));
         log_to_file(debug, "==========================");
+        for (name, server) in &config.mcp.servers {
+            log_to_file(debug, &format!("MCP server {}: {}", name, server.command));
+        }
     }
     Ok(config)
 }
 
-async fn handle_single_prompt_mode(chat_manager: Arc<Mutex<ChatManager>>, args: &Args, always_approve: &Arc<AtomicBool>, allowed_tools: &HashSet<String>) -> Result<()> {
-    let prompt = args.prompt.as_ref().unwrap();
-    // Lock, push user message, extract data, release lock
-    let llm_data = {
-        let mut manager = chat_manager.lock().await;
-        manager.push_user_message(prompt, args.debug);
-        manager.prepare_llm_call()
+async fn handle_single_prompt_mode(chat_manager: Arc<Mutex<ChatManager>>, args: &Args, policy: &ToolPolicy) -> Result<()> {
+    let model = chat_manager.lock().await.get_config().model.clone();
+    let reporter = JsonReporter::new(args.output, &model);
+    let reporter = reporter.as_ref();
+    let prompt = match crate::attachments::single_prompt(args.prompt.as_deref().unwrap(), &args.files) {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            match reporter {
+                Some(reporter) => {
+                    reporter.error(&e.to_string());
+                    reporter.finish();
+                }
+                None => print_error(&format!("Error: {}", e)),
+            }
+            chat_manager.lock().await.cleanup(false);
+            return Err(e);
+        }
     };
+    // Lock, push user message, extract data, release lock
+    chat_manager.lock().await.push_user_message(&prompt, args.debug);
+    crate::tools::compact_and_report(&chat_manager, false, args.debug, true).await;
+    let llm_data = chat_manager.lock().await.prepare_llm_call();
     // LLM call without holding the lock
-    let llm_result = match crate::chat::call_llm_api(&llm_data, true, args.debug).await {
-        Ok(result) => {
+    let llm_result = match stream_llm_response(&llm_data, true, args.debug, reporter).await {
+        Ok(Some(result)) => {
             if args.debug {
                 log_to_file(args.debug, "=== Raw Response ===");
                 log_to_file(args.debug, &format!("{:?}", result.response));
@@ -195,25 +260,137 @@ async fn handle_single_prompt_mode(chat_manager: Arc<Mutex<ChatManager>>, args:
             }
             result
         },
+        Ok(None) => {
+            match reporter {
+                Some(reporter) => {
+                    reporter.error("Cancelled by user");
+                    reporter.finish();
+                }
+                None => print_cancelled(),
+            }
+            chat_manager.lock().await.cleanup(false);
+            std::process::exit(130);
+        }
         Err(e) => {
-            print_error(&format!("Error: {}", e));
+            match reporter {
+                Some(reporter) => {
+                    reporter.error(&e.to_string());
+                    reporter.finish();
+                }
+                None => print_error(&format!("Error: {}", e)),
+            }
             chat_manager.lock().await.cleanup(false);
             return Err(e);
         }
     };
     // Re-acquire lock to update history
     chat_manager.lock().await.apply_llm_result(&llm_result);
-    if let Err(e) = handle_llm_response(&llm_result.response, chat_manager.clone(), args.debug, true, args.allow_commands, true, always_approve, allowed_tools).await {
-        print_error(&format!("Error processing tool calls: {}", e));
+    let outcome = handle_llm_response(&llm_result.response, chat_manager.clone(), args.debug, true, true, policy, reporter).await;
+    let mut manager = chat_manager.lock().await;
+    log_to_file(args.debug, &format!("Token usage:\n{}", manager.usage().report()));
+    if let Some(warning) = manager.take_save_warning(args.debug) {
+        eprintln!("{}", format!("Warning: {}", warning).color(Color::Yellow));
+    }
+    manager.cleanup(false);
+    drop(manager);
+    match (outcome, reporter) {
+        (Ok(()), Some(reporter)) => reporter.finish(),
+        (Err(e), Some(reporter)) => {
+            reporter.error(&format!("Error processing tool calls: {}", e));
+            reporter.finish();
+            return Err(e);
+        }
+        (Err(e), None) => print_error(&format!("Error processing tool calls: {}", e)),
+        (Ok(()), None) => {}
     }
-    chat_manager.lock().await.cleanup(false);
     Ok(())
 }
 
-async fn run_interactive_loop(chat_manager: Arc<Mutex<ChatManager>>, args: &Args, always_approve: &Arc<AtomicBool>) -> Result<()> {
+/// Prints a resumed conversation so the REPL user can see where they left off.
+async fn replay_history(chat_manager: &Arc<Mutex<ChatManager>>) {
+    let manager = chat_manager.lock().await;
+    let history = manager.get_history();
+    if history.is_empty() {
+        return;
+    }
     println!(
         "{}",
-        "Welcome to AI CLI! Chat with me (type 'exit' to quit, 'clear' to reset conversation)."
+        format!("Resumed session {} ({} messages)", manager.session_id(), history.len()).color(Color::Cyan)
+    );
+    println!();
+    for msg in history {
+        let content = msg.get("content").and_then(|c| c.as_str()).unwrap_or("");
+        match msg.get("role").and_then(|r| r.as_str()) {
+            Some("user") if !content.is_empty() => {
+                println!("{}", format!("> {}", content).color(Color::Green).bold());
+                println!();
+            }
+            Some("assistant") if !content.is_empty() => {
+                crate::tools::display_llm_output(content);
+                crate::tools::add_block_spacing();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn list_sessions(store: &SessionStore) -> Result<()> {
+    let sessions = store.list()?;
+    if sessions.is_empty() {
+        println!("No saved sessions.");
+        return Ok(());
+    }
+    for session in sessions {
+        let updated = chrono::DateTime::parse_from_rfc3339(&session.updated_at)
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or(session.updated_at.clone());
+        println!(
+            "{}  {}  {:>4} msgs  {}  {}",
+            session.id.color(Color::Cyan),
+            updated,
+            session.history.len(),
+            session.sandbox_root.color(Color::BrightBlack),
+            crate::utils::truncate_str(&session.title(), 60)
+        );
+    }
+    Ok(())
+}
+
+/// Attaches the session store to the chat manager and restores a session if `--resume` or `--continue` was given.
+/// `--continue` only picks up sessions started in the current sandbox root. Scripted `-p` runs are only
+/// saved when they carry on a restored session, so `--continue` never picks them up on its own.
+fn setup_session(manager: &mut ChatManager, store: Option<SessionStore>, args: &Args) -> Result<()> {
+    // Without a store the session flags already failed in `main`.
+    let Some(store) = store else {
+        return Ok(());
+    };
+    let root = get_sandbox_root().clone();
+    if let Some(id) = &args.resume {
+        let session = store.load(id)?;
+        if session.sandbox_root != root {
+            println!(
+                "{}",
+                format!("Session {} was started in {}; commands now run in {}.", session.id, session.sandbox_root, root).color(Color::Yellow)
+            );
+        }
+        manager.restore_session(session);
+    } else if args.continue_session {
+        match store.latest(&root)? {
+            Some(session) => manager.restore_session(session),
+            None => println!("{}", format!("No saved sessions in {} to continue; starting a new one.", root).color(Color::Yellow)),
+        }
+    }
+    if args.prompt.is_some() && manager.get_history().is_empty() {
+        return Ok(());
+    }
+    manager.set_session_store(store);
+    Ok(())
+}
+
+async fn run_interactive_loop(chat_manager: Arc<Mutex<ChatManager>>, args: &Args, policy: &ToolPolicy) -> Result<()> {
+    println!(
+        "{}",
+        "Welcome to AI CLI! Chat with me (type 'exit' to quit, 'clear' to reset conversation, 'compact' to summarize older messages, 'models' to list models, 'instructions' to show the system prompt, 'usage' to show token usage, 'permissions' to list or revoke remembered approvals)."
             .color(Color::Cyan)
             .bold()
     );
@@ -230,6 +407,7 @@ async fn run_interactive_loop(chat_manager: Arc<Mutex<ChatManager>>, args: &Args
         "Use !command to run shell commands directly (e.g., !ls or !dir). Use ! alone to enter interactive shell mode.".color(Color::Cyan)
     );
     println!();
+    replay_history(&chat_manager).await;
 
     // Initialize rustyline editor
     let mut rl = DefaultEditor::new().map_err(|e| anyhow::anyhow!("Failed to create readline editor: {}", e))?;
@@ -240,6 +418,9 @@ async fn run_interactive_loop(chat_manager: Arc<Mutex<ChatManager>>, args: &Args
     // Main input loop with rustyline
     loop {
         prompt_count += 1;
+        if let Some(warning) = chat_manager.lock().await.take_save_warning(args.debug) {
+            eprintln!("{}", format!("Warning: {}", warning).color(Color::Yellow));
+        }
         let conv_length: usize = chat_manager.lock().await
             .get_history()
             .iter()
@@ -249,7 +430,7 @@ async fn run_interactive_loop(chat_manager: Arc<Mutex<ChatManager>>, args: &Args
 
         let current_dir = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from(".")).display().to_string();
         // Refresh git branch cache every 10 iterations to avoid subprocess overhead
-        if cached_git_branch.is_none() || prompt_count % 10 == 0 {
+        if cached_git_branch.is_none() || prompt_count.is_multiple_of(10) {
             cached_git_branch = Command::new("git")
                 .args(["branch", "--show-current"])
                 .output()
@@ -279,7 +460,7 @@ async fn run_interactive_loop(chat_manager: Arc<Mutex<ChatManager>>, args: &Args
             Ok(line) => {
                 let user_input: &str = line.trim();
 
-                match handle_user_input(user_input, &mut rl, chat_manager.clone(), args, always_approve).await {
+                match handle_user_input(user_input, &mut rl, chat_manager.clone(), args, policy).await {
                     Ok(true) => continue,
                     Ok(false) => break,
                     Err(e) => {
@@ -314,25 +495,118 @@ async fn run_interactive_loop(chat_manager: Arc<Mutex<ChatManager>>, args: &Args
 #[command(name = "ai-cli")]
 #[command(about = "A provider-agnostic AI assistant for coding tasks")]
 struct Args {
-    /// Single prompt to send to the LLM and exit
+    /// Single prompt to send to the LLM and exit; `-` reads it from stdin, otherwise piped stdin is attached
     #[arg(short, long)]
     prompt: Option<String>,
 
+    /// Attach a sandbox file to the single prompt (repeatable)
+    #[arg(long = "file", value_name = "PATH", requires = "prompt")]
+    files: Vec<String>,
+
     /// Enable debug output for troubleshooting
-    #[arg(long)]
+    #[arg(long, global = true)]
     debug: bool,
 
-    /// Allow LLM to execute commands without user confirmation in single prompt mode
-    #[arg(long)]
+    /// Allow LLM to execute commands without user confirmation
+    #[arg(long, global = true)]
     allow_commands: bool,
 
     /// Comma-separated list of tools to auto-approve without confirmation (e.g., email,file_editor,commands)
-    #[arg(long)]
+    #[arg(long, global = true)]
     allow_tools: Option<String>,
 
+    /// Command sandbox preset (overrides `profile` in the `[sandbox]` section)
+    #[arg(long, value_enum, global = true)]
+    sandbox_profile: Option<SandboxProfile>,
+
+    /// Output format for single prompt mode
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, requires = "prompt")]
+    output: OutputFormat,
+
+    /// Use a `[profile.<name>]` section from the config file
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Sampling temperature (overrides TEMPERATURE and the profile)
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    /// Nucleus sampling probability mass
+    #[arg(long)]
+    top_p: Option<f32>,
+
+    /// Most tokens generated per response
+    #[arg(long)]
+    max_tokens: Option<u32>,
+
+    /// Seed for reproducible sampling, where the provider supports it
+    #[arg(long)]
+    seed: Option<i64>,
+
+    /// Stop sequence (repeatable)
+    #[arg(long = "stop", value_name = "SEQUENCE")]
+    stop: Vec<String>,
+
+    /// Reasoning effort for reasoning models
+    #[arg(long, value_enum)]
+    reasoning_effort: Option<ReasoningEffort>,
+
+    /// Whether the model may request several tool calls in one response
+    #[arg(long, value_name = "BOOL")]
+    parallel_tool_calls: Option<bool>,
+
     /// Use the old REPL interface instead of the TUI
     #[arg(long)]
     no_tui: bool,
+
+    /// Resume a saved session by id
+    #[arg(long, value_name = "ID", conflicts_with = "continue_session")]
+    resume: Option<String>,
+
+    /// Continue the most recent saved session started in the current directory
+    #[arg(long = "continue")]
+    continue_session: bool,
+
+    /// List saved sessions and exit
+    #[arg(long)]
+    list_sessions: bool,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Serve execute_command (with its job tools), file_editor, scrape_url and search_online over MCP on stdio.
+    /// Calls needing confirmation are refused unless listed in --allow-tools.
+    McpServe,
+}
+
+/// The approval policy from the command line: `--allow-tools`, plus `execute_command`
+/// and `shell_session` when `--allow-commands` is given. Every mode starts from it.
+fn command_line_policy(args: &Args, allowed_tools: &HashSet<String>) -> ToolPolicy {
+    let mut allowed = allowed_tools.clone();
+    if args.allow_commands {
+        allowed.insert("execute_command".to_string());
+        allowed.insert("shell_session".to_string());
+    }
+    ToolPolicy::new(ApprovalMemory::default(), allowed)
+}
+
+/// Builds the command sandbox policy from `[sandbox]` and `--sandbox-profile`.
+fn install_sandbox_policy(config: &Config, args: &Args) {
+    let policy = crate::sandbox::SandboxPolicy::from_config(&config.sandbox, args.sandbox_profile, dirs::home_dir().as_deref());
+    log_to_file(args.debug, &format!("Sandbox policy: {:?}", policy));
+    crate::sandbox::set_sandbox_policy(policy);
+}
+
+/// Runs `ai-cli mcp-serve`. Stdout carries the protocol, so nothing else may print to it.
+async fn run_mcp_server(args: &Args, allowed_tools: HashSet<String>) -> Result<()> {
+    let config = load_and_display_config(args.debug).await?;
+    install_sandbox_policy(&config, args);
+    let policy = command_line_policy(args, &allowed_tools);
+    let ctx = crate::tools::ToolContext { config, debug: args.debug, cancel: None, command_observer: None };
+    crate::mcp::serve_stdio(policy, ctx).await
 }
 
 #[tokio::main]
@@ -341,26 +615,68 @@ async fn main() -> Result<(), anyhow::Error> {
 
     clear_debug_file(args.debug);
 
-    let config = load_and_display_config(args.debug).await?;
+    let allowed_tools: HashSet<String> = args.allow_tools.as_deref()
+        .map(crate::tools::parse_tool_list)
+        .unwrap_or_default();
 
-    let chat_manager = Arc::new(Mutex::new(ChatManager::new(config)));
-    
-    // Create atomic bool for "always approve" mode (session-scoped)
-    let always_approve = Arc::new(AtomicBool::new(false));
+    if let Some(CliCommand::McpServe) = args.command {
+        return run_mcp_server(&args, allowed_tools).await;
+    }
 
-    let allowed_tools: HashSet<String> = args.allow_tools.as_ref()
-        .map(|s| s.split(',').map(|t| t.trim().to_lowercase()).collect())
-        .unwrap_or_default();
+    // Persistence is optional: only the session flags need the store (e.g. HOME may be read-only in CI).
+    let session_store = match SessionStore::open_default() {
+        Ok(store) => Some(store),
+        Err(e) if args.list_sessions || args.resume.is_some() || args.continue_session => return Err(e),
+        Err(e) => {
+            log_to_file(args.debug, &format!("Sessions disabled: {:#}", e));
+            if args.prompt.is_none() {
+                eprintln!("{}", format!("Warning: {:#}; this conversation will not be saved.", e).color(Color::Yellow));
+            }
+            None
+        }
+    };
+    if let (true, Some(store)) = (args.list_sessions, &session_store) {
+        return list_sessions(store);
+    }
+
+    let config = load_and_display_config(args.debug).await?;
+    install_sandbox_policy(&config, &args);
+
+    let mcp_tools = crate::mcp::connect_servers(&config.mcp, args.debug).await;
+    let mut manager = ChatManager::new(config);
+    manager.register_tools(mcp_tools);
+    manager.set_sampling_flags(Sampling {
+        temperature: args.temperature,
+        top_p: args.top_p,
+        max_tokens: args.max_tokens,
+        seed: args.seed,
+        stop: args.stop.clone(),
+        reasoning_effort: args.reasoning_effort,
+        parallel_tool_calls: args.parallel_tool_calls,
+    });
+    if let Some(profile) = &args.profile {
+        manager.set_profile(Some(profile))?;
+        log_to_file(args.debug, &format!("Profile: {} (model {})", profile, manager.get_config().model));
+    }
+    let cli_policy = command_line_policy(&args, &allowed_tools);
+    // Profile auto-approvals apply like --allow-tools; the TUI re-reads them when /profile switches.
+    let policy = cli_policy.with_allowed(&manager.auto_approved_tools());
+    setup_session(&mut manager, session_store, &args)?;
+    let chat_manager = Arc::new(Mutex::new(manager));
+
+    if args.prompt.is_some() || args.no_tui {
+        crate::tools::install_interrupt_handler();
+    }
 
     if args.prompt.is_some() {
-        handle_single_prompt_mode(chat_manager.clone(), &args, &always_approve, &allowed_tools).await?;
+        handle_single_prompt_mode(chat_manager.clone(), &args, &policy).await?;
         return Ok(());
     }
 
     if args.no_tui {
-        run_interactive_loop(chat_manager, &args, &always_approve).await?;
+        run_interactive_loop(chat_manager, &args, &policy).await?;
     } else {
-        crate::tui::run_tui(chat_manager.clone(), args.debug, always_approve).await?;
+        crate::tui::run_tui(chat_manager.clone(), args.debug, cli_policy).await?;
     }
 
     Ok(())
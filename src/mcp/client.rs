@@ -0,0 +1,230 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use crate::config::McpServerConfig;
+use crate::utils::log_to_file;
+
+/// Protocol revision sent in `initialize`; servers answer with the one they speak.
+pub(crate) const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// How long the `initialize` / `tools/list` handshake may take before a server is skipped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Connection {
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+}
+
+/// A running MCP server speaking newline-delimited JSON-RPC over stdio. Requests are
+/// serialized: each one writes a message and reads until the matching response.
+pub(crate) struct McpClient {
+    name: String,
+    timeout: Duration,
+    debug: bool,
+    connection: Mutex<Connection>,
+    // Held so the server is killed when the last tool referencing it is dropped.
+    _child: Child,
+}
+
+impl std::fmt::Debug for McpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpClient").field("name", &self.name).finish()
+    }
+}
+
+impl McpClient {
+    /// Starts the server process and performs the `initialize` handshake.
+    pub(crate) async fn start(name: &str, server: &McpServerConfig, debug: bool) -> Result<Self> {
+        let argv = shell_words::split(&server.command)
+            .with_context(|| format!("Invalid command for MCP server '{}'", name))?;
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| anyhow!("Empty command for MCP server '{}'", name))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start MCP server '{}' ({})", name, program))?;
+
+        let stdin = child.stdin.take().context("MCP server stdin unavailable")?;
+        let stdout = child.stdout.take().context("MCP server stdout unavailable")?;
+        if let Some(stderr) = child.stderr.take() {
+            // Server logs go to the debug log so they never garble the terminal.
+            let label = name.to_string();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log_to_file(debug, &format!("MCP {} stderr: {}", label, line));
+                }
+            });
+        }
+
+        let client = McpClient {
+            name: name.to_string(),
+            timeout: Duration::from_secs(server.timeout_secs),
+            debug,
+            connection: Mutex::new(Connection {
+                stdin,
+                stdout: BufReader::new(stdout).lines(),
+                next_id: 1,
+            }),
+            _child: child,
+        };
+
+        let init = client
+            .request_with_timeout(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {"name": "ai-cli", "version": env!("CARGO_PKG_VERSION")}
+                }),
+                HANDSHAKE_TIMEOUT,
+            )
+            .await?;
+        log_to_file(debug, &format!("MCP {} initialized: {}", name, init));
+        client.notify("notifications/initialized", json!({})).await?;
+        Ok(client)
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Fetches every tool definition the server offers, following pagination cursors.
+    pub(crate) async fn list_tools(&self) -> Result<Vec<Value>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(c) => json!({"cursor": c}),
+                None => json!({}),
+            };
+            let result = self.request_with_timeout("tools/list", params, HANDSHAKE_TIMEOUT).await?;
+            if let Some(page) = result.get("tools").and_then(|t| t.as_array()) {
+                tools.extend(page.iter().cloned());
+            }
+            cursor = result.get("nextCursor").and_then(|c| c.as_str()).map(|c| c.to_string());
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// Calls a tool and returns its text content. Results flagged `isError` become errors.
+    pub(crate) async fn call_tool(&self, tool: &str, arguments: &Value) -> Result<String> {
+        let result = self
+            .request_with_timeout("tools/call", json!({"name": tool, "arguments": arguments}), self.timeout)
+            .await?;
+        let text = content_text(&result);
+        if result.get("isError").and_then(|e| e.as_bool()).unwrap_or(false) {
+            bail!(text);
+        }
+        Ok(text)
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let mut connection = self.connection.lock().await;
+        write_message(&mut connection.stdin, &json!({"jsonrpc": "2.0", "method": method, "params": params})).await
+    }
+
+    async fn request_with_timeout(&self, method: &str, params: Value, timeout: Duration) -> Result<Value> {
+        tokio::time::timeout(timeout, self.request(method, params))
+            .await
+            .map_err(|_| anyhow!("MCP server '{}' did not answer {} within {}s", self.name, method, timeout.as_secs()))?
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let mut connection = self.connection.lock().await;
+        let id = connection.next_id;
+        connection.next_id += 1;
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        log_to_file(self.debug, &format!("MCP {} -> {}", self.name, message));
+        write_message(&mut connection.stdin, &message).await?;
+
+        loop {
+            let line = connection
+                .stdout
+                .next_line()
+                .await?
+                .ok_or_else(|| anyhow!("MCP server '{}' exited", self.name))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Ok(incoming) = serde_json::from_str::<Value>(&line) else {
+                log_to_file(self.debug, &format!("MCP {} sent non-JSON output: {}", self.name, line));
+                continue;
+            };
+
+            // Requests from the server (e.g. ping) need an answer; we offer no client features.
+            if let (Some(method), Some(request_id)) = (incoming.get("method").and_then(|m| m.as_str()), incoming.get("id")) {
+                let reply = if method == "ping" {
+                    json!({"jsonrpc": "2.0", "id": request_id, "result": {}})
+                } else {
+                    json!({"jsonrpc": "2.0", "id": request_id,
+                        "error": {"code": -32601, "message": format!("Method not found: {}", method)}})
+                };
+                write_message(&mut connection.stdin, &reply).await?;
+                continue;
+            }
+            if incoming.get("id").and_then(|i| i.as_u64()) != Some(id) {
+                // Notifications and stale responses from timed-out requests.
+                continue;
+            }
+
+            log_to_file(self.debug, &format!("MCP {} <- {}", self.name, line));
+            if let Some(error) = incoming.get("error") {
+                let message = error.get("message").and_then(|m| m.as_str()).unwrap_or("unknown error");
+                bail!("MCP server '{}' returned an error for {}: {}", self.name, method, message);
+            }
+            return Ok(incoming.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+}
+
+async fn write_message(stdin: &mut ChildStdin, message: &Value) -> Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+/// Flattens a `tools/call` result's content blocks into text for the model.
+pub(crate) fn content_text(result: &Value) -> String {
+    let blocks = result.get("content").and_then(|c| c.as_array()).cloned().unwrap_or_default();
+    blocks
+        .iter()
+        .map(|block| match block.get("type").and_then(|t| t.as_str()) {
+            Some("text") => block.get("text").and_then(|t| t.as_str()).unwrap_or("").to_string(),
+            Some("resource") => {
+                let resource = block.get("resource");
+                resource
+                    .and_then(|r| r.get("text"))
+                    .and_then(|t| t.as_str())
+                    .map(|t| t.to_string())
+                    .unwrap_or_else(|| {
+                        format!("[resource: {}]", resource.and_then(|r| r.get("uri")).and_then(|u| u.as_str()).unwrap_or("?"))
+                    })
+            }
+            Some(other) => format!(
+                "[{} content: {}]",
+                other,
+                block.get("mimeType").and_then(|m| m.as_str()).unwrap_or("unknown type")
+            ),
+            None => block.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
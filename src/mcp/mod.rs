@@ -0,0 +1,221 @@
+//! Model Context Protocol support: external tool servers launched over stdio and
+//! exposed to the model next to the built-in tools, and `ai-cli mcp-serve`, which
+//! publishes the sandboxed built-in tools to other MCP clients.
+
+mod client;
+mod server;
+
+pub(crate) use server::serve_stdio;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use colored::{Color, Colorize};
+use serde_json::{json, Value};
+
+use crate::config::McpConfig;
+use crate::tools::{ConfirmRequest, Tool, ToolContext};
+use client::McpClient;
+
+/// Longest tool name accepted by the chat completion APIs.
+const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// A tool offered by an MCP server. The model sees it as `<server>__<tool>`.
+struct McpTool {
+    client: Arc<McpClient>,
+    /// Name exposed to the model.
+    name: String,
+    /// Name the server knows the tool by.
+    remote_name: String,
+    description: String,
+    input_schema: Value,
+}
+
+impl McpTool {
+    fn new(client: Arc<McpClient>, definition: &Value) -> Option<Self> {
+        let remote_name = definition.get("name")?.as_str()?.to_string();
+        let name = exposed_name(client.name(), &remote_name);
+        let description = definition
+            .get("description")
+            .and_then(|d| d.as_str())
+            .map(|d| d.to_string())
+            .unwrap_or_else(|| format!("{} (from MCP server {})", remote_name, client.name()));
+        let input_schema = definition
+            .get("inputSchema")
+            .filter(|s| s.is_object())
+            .cloned()
+            .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+        Some(McpTool {
+            client,
+            name,
+            remote_name,
+            description,
+            input_schema,
+        })
+    }
+}
+
+/// Builds a model-facing tool name: only `[A-Za-z0-9_-]`, at most 64 characters.
+fn exposed_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(MAX_TOOL_NAME_LEN)
+        .collect()
+}
+
+#[async_trait]
+impl Tool for McpTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.input_schema.clone()
+    }
+
+    // External servers can do anything, so every call is confirmed unless allowed by name.
+    fn approval(&self, args: &Value) -> Option<ConfirmRequest> {
+        Some(ConfirmRequest {
+            prompt: format!(
+                "Run {} on MCP server {} with {}?",
+                self.remote_name,
+                self.client.name(),
+                crate::utils::truncate_str(&args.to_string(), 200)
+            ),
+            preview: None,
+            scope: None,
+        })
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        crate::utils::truncate_str(&args.to_string(), 100)
+    }
+
+    async fn execute(&self, args: &Value, _ctx: &ToolContext) -> Result<String> {
+        self.client.call_tool(&self.remote_name, args).await
+    }
+}
+
+async fn connect_server(name: &str, server: &crate::config::McpServerConfig, debug: bool) -> Result<Vec<Arc<dyn Tool>>> {
+    let client = Arc::new(McpClient::start(name, server, debug).await?);
+    let definitions = client.list_tools().await?;
+    crate::utils::log_to_file(debug, &format!("MCP {} offers {} tools", name, definitions.len()));
+    Ok(definitions
+        .iter()
+        .filter_map(|definition| McpTool::new(Arc::clone(&client), definition))
+        .map(|tool| Arc::new(tool) as Arc<dyn Tool>)
+        .collect())
+}
+
+/// Starts every configured server and returns the tools they offer. Servers that fail
+/// to start or answer the handshake are reported and skipped.
+pub(crate) async fn connect_servers(config: &McpConfig, debug: bool) -> Vec<Arc<dyn Tool>> {
+    let results = futures::future::join_all(
+        config
+            .servers
+            .iter()
+            .map(|(name, server)| async move { (name, connect_server(name, server, debug).await) }),
+    )
+    .await;
+
+    let mut tools = Vec::new();
+    for (name, result) in results {
+        match result {
+            Ok(server_tools) => tools.extend(server_tools),
+            // Stderr, so -p --output json and mcp-serve keep stdout parseable.
+            Err(e) => eprintln!("{}", format!("MCP server '{}' unavailable: {:#}", name, e).color(Color::Red)),
+        }
+    }
+    tools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::McpServerConfig;
+    use std::collections::BTreeMap;
+
+    /// A stub MCP server in POSIX sh: answers the handshake, lists `echo` and `fail`,
+    /// and sends a notification before each `tools/call` response.
+    const STUB_SERVER: &str = r#"
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":"2024-11-05","capabilities":{"tools":{}},"serverInfo":{"name":"stub","version":"1"}}}\n' "$id" ;;
+    *'"method":"tools/list"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"echo","description":"Echo text","inputSchema":{"type":"object","properties":{"text":{"type":"string"}}}},{"name":"fail.now"}]}}\n' "$id" ;;
+    *'"method":"tools/call"'*)
+      printf '{"jsonrpc":"2.0","method":"notifications/message","params":{}}\n'
+      case "$line" in
+        *'"name":"echo"'*)
+          text=$(printf '%s' "$line" | sed -n 's/.*"text":"\([^"]*\)".*/\1/p')
+          printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"echo: %s"}]}}\n' "$id" "$text" ;;
+        *)
+          printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"it broke"}],"isError":true}}\n' "$id" ;;
+      esac ;;
+  esac
+done
+"#;
+
+    fn stub_config() -> McpConfig {
+        let command = shell_words::join(["sh", "-c", STUB_SERVER]);
+        McpConfig {
+            servers: BTreeMap::from([(
+                "stub".to_string(),
+                McpServerConfig { command, timeout_secs: 10 },
+            )]),
+        }
+    }
+
+    fn ctx() -> ToolContext {
+        ToolContext {
+            config: crate::config::Config::default(),
+            debug: false,
+            cancel: None,
+            command_observer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_lists_tools_and_calls_them() {
+        let tools = connect_servers(&stub_config(), false).await;
+        let names: Vec<&str> = tools.iter().map(|t| t.name()).collect();
+        assert_eq!(names, ["stub__echo", "stub__fail_now"]);
+        assert_eq!(tools[0].description(), "Echo text");
+        assert_eq!(tools[0].parameters()["properties"]["text"]["type"], "string");
+        assert!(tools[0].approval(&json!({})).is_some());
+
+        let echoed = tools[0].execute(&json!({"text": "hi"}), &ctx()).await.unwrap();
+        assert_eq!(echoed, "echo: hi");
+        let failed = tools[1].execute(&json!({}), &ctx()).await.unwrap_err();
+        assert_eq!(failed.to_string(), "it broke");
+    }
+
+    #[tokio::test]
+    async fn test_unstartable_server_is_skipped() {
+        let config = McpConfig {
+            servers: BTreeMap::from([(
+                "missing".to_string(),
+                McpServerConfig { command: "/nonexistent/mcp-server".into(), timeout_secs: 1 },
+            )]),
+        };
+        assert!(connect_servers(&config, false).await.is_empty());
+    }
+
+    #[test]
+    fn test_content_text_flattens_blocks() {
+        let result = json!({"content": [
+            {"type": "text", "text": "line one"},
+            {"type": "image", "data": "...", "mimeType": "image/png"},
+            {"type": "resource", "resource": {"uri": "file:///a", "text": "body"}}
+        ]});
+        assert_eq!(client::content_text(&result), "line one\n[image content: image/png]\nbody");
+    }
+}
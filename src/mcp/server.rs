@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use super::client::PROTOCOL_VERSION;
+use crate::tools::{Gate, ToolContext, ToolPolicy, ToolRegistry};
+use crate::utils::log_to_file;
+
+/// Built-in tools published by `ai-cli mcp-serve`. The job tools come along with
+/// `execute_command` so clients can poll and stop the background jobs it starts.
+pub(crate) const SERVED_TOOLS: [&str; 7] = [
+    "execute_command",
+    "job_status",
+    "job_output",
+    "job_kill",
+    "file_editor",
+    "scrape_url",
+    "search_online",
+];
+
+/// Serves the sandboxed built-in tools over MCP on stdin/stdout until stdin closes.
+/// Calls that would need confirmation in the REPL are refused unless allowed by the
+/// policy, since stdin carries the protocol and there is nobody to ask.
+pub(crate) async fn serve_stdio(policy: ToolPolicy, ctx: ToolContext) -> Result<()> {
+    let builtin = ToolRegistry::builtin();
+    let mut registry = ToolRegistry::default();
+    for name in SERVED_TOOLS {
+        if let Some(tool) = builtin.get(name) {
+            registry.register(tool);
+        }
+    }
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    let result = serve(Arc::new(registry), policy, ctx, stdin, tokio::io::stdout()).await;
+    crate::jobs::kill_all_jobs();
+    result
+}
+
+/// The MCP request loop over any line-oriented reader and writer. Tool calls run as
+/// separate tasks so a slow command does not block pings or other calls.
+pub(crate) async fn serve<R, W>(registry: Arc<ToolRegistry>, policy: ToolPolicy, ctx: ToolContext, reader: R, mut writer: W) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+    let debug = ctx.debug;
+    let writer_task = tokio::spawn(async move {
+        while let Some(message) = out_rx.recv().await {
+            log_to_file(debug, &format!("MCP serve <- {}", message));
+            let mut line = message.to_string();
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        log_to_file(debug, &format!("MCP serve -> {}", line));
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let _ = out_tx.send(error_response(&Value::Null, -32700, &format!("Parse error: {}", e)));
+                continue;
+            }
+        };
+        let method = request.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        // Notifications (no id) need no answer.
+        let Some(id) = request.get("id").cloned() else {
+            continue;
+        };
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+
+        match method {
+            "initialize" => {
+                let _ = out_tx.send(json!({"jsonrpc": "2.0", "id": id, "result": {
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {"tools": {}},
+                    "serverInfo": {"name": "ai-cli", "version": env!("CARGO_PKG_VERSION")}
+                }}));
+            }
+            "ping" => {
+                let _ = out_tx.send(json!({"jsonrpc": "2.0", "id": id, "result": {}}));
+            }
+            "tools/list" => {
+                let tools: Vec<Value> = registry
+                    .schemas()
+                    .into_iter()
+                    .map(|schema| json!({
+                        "name": schema.function.name,
+                        "description": schema.function.description,
+                        "inputSchema": schema.function.parameters,
+                    }))
+                    .collect();
+                let _ = out_tx.send(json!({"jsonrpc": "2.0", "id": id, "result": {"tools": tools}}));
+            }
+            "tools/call" => {
+                let registry = Arc::clone(&registry);
+                let policy = policy.clone();
+                let ctx = ctx.clone();
+                let out_tx = out_tx.clone();
+                tokio::spawn(async move {
+                    let result = call_tool(&registry, &policy, &ctx, &params).await;
+                    let _ = out_tx.send(json!({"jsonrpc": "2.0", "id": id, "result": result}));
+                });
+            }
+            _ => {
+                let _ = out_tx.send(error_response(&id, -32601, &format!("Method not found: {}", method)));
+            }
+        }
+    }
+
+    drop(out_tx);
+    let _ = writer_task.await;
+    Ok(())
+}
+
+/// Runs one `tools/call` and builds its result; failures are tool errors, not protocol errors.
+async fn call_tool(registry: &ToolRegistry, policy: &ToolPolicy, ctx: &ToolContext, params: &Value) -> Value {
+    let name = params.get("name").and_then(|n| n.as_str()).unwrap_or("");
+    let args = params.get("arguments").cloned().unwrap_or(json!({}));
+    let outcome = match registry.get(name) {
+        None => Err(format!("Unknown tool: {}", name)),
+        Some(tool) => match policy.gate(tool.as_ref(), &args, &ctx.config) {
+            Gate::Run => tool.execute(&args, ctx).await.map_err(|e| e.to_string()),
+            Gate::Confirm(_) => Err(format!(
+                "Tool '{}' needs approval for this call; start ai-cli mcp-serve with --allow-tools {} to allow it",
+                name, name
+            )),
+            Gate::Deny(error) => Err(error),
+        },
+    };
+    match outcome {
+        Ok(text) => json!({"content": [{"type": "text", "text": text}], "isError": false}),
+        Err(text) => json!({"content": [{"type": "text", "text": text}], "isError": true}),
+    }
+}
+
+fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ApprovalMemory;
+    use std::collections::HashSet;
+    use tokio::io::AsyncReadExt;
+
+    async fn exchange(requests: &[Value], allowed: &[&str]) -> Vec<Value> {
+        let builtin = ToolRegistry::builtin();
+        let mut registry = ToolRegistry::default();
+        for name in SERVED_TOOLS {
+            registry.register(builtin.get(name).unwrap());
+        }
+        let policy = ToolPolicy::new(
+            ApprovalMemory::default(),
+            allowed.iter().map(|s| s.to_string()).collect::<HashSet<_>>(),
+        );
+        let ctx = ToolContext {
+            config: crate::config::Config::default(),
+            debug: false,
+            cancel: None,
+            command_observer: None,
+        };
+        let input: String = requests.iter().map(|r| format!("{}\n", r)).collect();
+        let (writer, mut output) = tokio::io::duplex(64 * 1024);
+        serve(Arc::new(registry), policy, ctx, input.as_bytes(), writer).await.unwrap();
+        let mut raw = String::new();
+        output.read_to_string(&mut raw).await.unwrap();
+        let mut responses: Vec<Value> = raw.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        responses.sort_by_key(|r| r["id"].as_i64());
+        responses
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_tool_list() {
+        let responses = exchange(
+            &[
+                json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+                json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+                json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"}),
+                json!({"jsonrpc": "2.0", "id": 3, "method": "resources/list"}),
+            ],
+            &[],
+        )
+        .await;
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["result"]["serverInfo"]["name"], "ai-cli");
+        let names: Vec<&str> = responses[1]["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, SERVED_TOOLS);
+        assert_eq!(responses[2]["error"]["code"], -32601);
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_respect_allow_list() {
+        let test_file = "test_mcp_serve_read.tmp";
+        std::fs::write(test_file, "served content").unwrap();
+        let calls = [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call",
+                "params": {"name": "file_editor", "arguments": {"subcommand": "read", "filename": test_file}}}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "tools/call",
+                "params": {"name": "execute_command", "arguments": {"command": "echo hi"}}}),
+        ];
+        let responses = exchange(&calls, &[]).await;
+        let _ = std::fs::remove_file(test_file);
+
+        assert_eq!(responses[0]["result"]["isError"], false);
+        assert!(responses[0]["result"]["content"][0]["text"].as_str().unwrap().contains("served content"));
+        assert_eq!(responses[1]["result"]["isError"], true);
+        assert!(responses[1]["result"]["content"][0]["text"].as_str().unwrap().contains("--allow-tools"));
+    }
+
+    fn call(id: i64, name: &str, arguments: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": name, "arguments": arguments}})
+    }
+
+    fn text(response: &Value) -> &str {
+        response["result"]["content"][0]["text"].as_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_background_jobs_can_be_polled_and_killed() {
+        let started = exchange(
+            &[call(1, "execute_command", json!({"command": "echo served-job; sleep 30", "background": true}))],
+            &["execute_command"],
+        )
+        .await;
+        let started = text(&started[0]);
+        let id: u32 = started
+            .strip_prefix("Started background job ")
+            .and_then(|rest| rest.split(':').next())
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_else(|| panic!("{}", started));
+
+        // The command itself may fail where bubblewrap is missing; the job is there either way.
+        let output = exchange(&[call(1, "job_output", json!({"job_id": id}))], &[]).await;
+        assert_eq!(output[0]["result"]["isError"], false, "{}", text(&output[0]));
+        assert!(text(&output[0]).contains("next offset"), "{}", text(&output[0]));
+
+        let killed = exchange(&[call(1, "job_kill", json!({"job_id": id}))], &[]).await;
+        assert_eq!(killed[0]["result"]["isError"], false, "{}", text(&killed[0]));
+        let status = exchange(&[call(1, "job_status", json!({"job_id": id}))], &[]).await;
+        assert!(text(&status[0]).starts_with(&format!("job {}: ended", id)), "{}", text(&status[0]));
+    }
+}
@@ -0,0 +1,149 @@
+//! Machine-readable output for single-prompt mode (`--output json|stream-json`).
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+/// How single-prompt mode prints its result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Markdown rendered for the terminal
+    #[default]
+    Text,
+    /// One JSON object once the run has finished
+    Json,
+    /// Newline-delimited JSON events as they happen
+    StreamJson,
+}
+
+#[derive(Default)]
+struct ReportState {
+    answer: String,
+    tool_calls: Vec<Value>,
+    error: Option<String>,
+}
+
+/// Collects (`json`) or emits (`stream-json`) the events of a single-prompt run. Only
+/// this reporter writes to stdout in those modes, so the output stays parseable.
+pub(crate) struct JsonReporter {
+    format: OutputFormat,
+    model: String,
+    started: Instant,
+    state: Mutex<ReportState>,
+}
+
+impl JsonReporter {
+    /// Returns `None` for text output.
+    pub(crate) fn new(format: OutputFormat, model: &str) -> Option<Self> {
+        (format != OutputFormat::Text).then(|| JsonReporter {
+            format,
+            model: model.to_string(),
+            started: Instant::now(),
+            state: Mutex::new(ReportState::default()),
+        })
+    }
+
+    fn emit(&self, event: Value) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", event);
+        let _ = stdout.flush();
+    }
+
+    pub(crate) fn token(&self, text: &str) {
+        if self.format == OutputFormat::StreamJson {
+            self.emit(json!({"type": "token", "text": text}));
+        }
+    }
+
+    /// Records the text of a completed response; the last one is the final answer.
+    pub(crate) fn answer(&self, text: &str) {
+        self.state.lock().unwrap().answer = text.to_string();
+    }
+
+    pub(crate) fn tool_call(&self, id: &str, name: &str, arguments: &Value) {
+        if self.format == OutputFormat::StreamJson {
+            self.emit(json!({"type": "tool_call", "id": id, "name": name, "arguments": arguments}));
+        }
+        self.state.lock().unwrap().tool_calls.push(json!({"id": id, "name": name, "arguments": arguments}));
+    }
+
+    /// Records the text sent back to the model for a call.
+    pub(crate) fn tool_result(&self, id: &str, result: &str, rejected: bool) {
+        let is_error = result.starts_with("[Tool error]");
+        if self.format == OutputFormat::StreamJson {
+            self.emit(json!({"type": "tool_result", "id": id, "result": result, "is_error": is_error, "rejected": rejected}));
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(call) = state.tool_calls.iter_mut().rev().find(|c| c["id"] == id) {
+            call["result"] = json!(result);
+            call["is_error"] = json!(is_error);
+            call["rejected"] = json!(rejected);
+        }
+    }
+
+    pub(crate) fn error(&self, message: &str) {
+        if self.format == OutputFormat::StreamJson {
+            self.emit(json!({"type": "error", "message": message}));
+        }
+        self.state.lock().unwrap().error = Some(message.to_string());
+    }
+
+    /// The summary printed by `json` mode; `stream-json` ends with the same fields in `done`.
+    fn report(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        let mut report = json!({
+            "model": self.model,
+            "answer": state.answer,
+            "tool_calls": state.tool_calls,
+            "duration_ms": self.started.elapsed().as_millis() as u64,
+        });
+        if let Some(error) = &state.error {
+            report["error"] = json!(error);
+        }
+        report
+    }
+
+    /// Prints the final object (`json`) or the `done` event (`stream-json`).
+    pub(crate) fn finish(&self) {
+        let mut report = self.report();
+        if self.format == OutputFormat::StreamJson {
+            // Calls were already streamed as events.
+            report.as_object_mut().unwrap().remove("tool_calls");
+            report["type"] = json!("done");
+        }
+        self.emit(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_output_has_no_reporter() {
+        assert!(JsonReporter::new(OutputFormat::Text, "m").is_none());
+    }
+
+    #[test]
+    fn test_report_collects_tool_calls_and_answer() {
+        let reporter = JsonReporter::new(OutputFormat::Json, "test-model").unwrap();
+        reporter.tool_call("call_1", "execute_command", &json!({"command": "ls"}));
+        reporter.tool_result("call_1", "[Tool result] execute_command: a.txt", false);
+        reporter.tool_call("call_2", "file_editor", &json!({}));
+        reporter.tool_result("call_2", "[Tool error] file_editor: Missing parameters", false);
+        reporter.answer("Found a.txt");
+
+        let report = reporter.report();
+        assert_eq!(report["model"], "test-model");
+        assert_eq!(report["answer"], "Found a.txt");
+        assert_eq!(report["tool_calls"][0]["arguments"]["command"], "ls");
+        assert_eq!(report["tool_calls"][0]["is_error"], false);
+        assert_eq!(report["tool_calls"][1]["is_error"], true);
+        assert!(report.get("error").is_none());
+
+        reporter.error("boom");
+        assert_eq!(reporter.report()["error"], "boom");
+    }
+}
@@ -0,0 +1,463 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde_json::{json, Value};
+
+use super::{error_from_response, line_stream, sse_data, tool_functions, transport_error, LlmProvider, StreamEvent};
+use crate::chat::LlmCallData;
+use crate::config::Config;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// The Messages API requires an explicit output limit.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Native Anthropic `/v1/messages` backend. History stays in OpenAI chat format and
+/// is converted on every request: the system prompt moves to a top-level field,
+/// tool calls become `tool_use` blocks and tool results become `tool_result` blocks.
+#[derive(Debug)]
+pub(crate) struct AnthropicProvider {
+    endpoint: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl AnthropicProvider {
+    pub(crate) fn new(config: &Config) -> Self {
+        AnthropicProvider {
+            endpoint: config.get_api_endpoint(),
+            api_key: config.api_key.clone(),
+            client: crate::http::create_llm_http_client(),
+        }
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response> {
+        let resp = self
+            .client
+            .post(&self.endpoint)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+        if !resp.status().is_success() {
+            return Err(error_from_response(resp).await);
+        }
+        Ok(resp)
+    }
+}
+
+/// Appends content blocks to `messages`, merging into the previous message when the
+/// role repeats (the API requires alternating user/assistant turns).
+fn push_blocks(messages: &mut Vec<Value>, role: &str, blocks: Vec<Value>) {
+    if blocks.is_empty() {
+        return;
+    }
+    if let Some(last) = messages.last_mut() {
+        if last["role"] == role {
+            if let Some(content) = last["content"].as_array_mut() {
+                content.extend(blocks);
+                return;
+            }
+        }
+    }
+    messages.push(json!({"role": role, "content": blocks}));
+}
+
+fn text_block(content: &Value) -> Option<Value> {
+    let text = match content {
+        Value::String(s) => s.clone(),
+        Value::Null => return None,
+        other => other.to_string(),
+    };
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(json!({"type": "text", "text": text}))
+    }
+}
+
+/// Converts OpenAI-format history to Anthropic `messages`.
+fn convert_history(history: &[Value]) -> Vec<Value> {
+    let mut messages: Vec<Value> = Vec::new();
+    for msg in history {
+        let content = msg.get("content").unwrap_or(&Value::Null);
+        match msg.get("role").and_then(|r| r.as_str()).unwrap_or("") {
+            "assistant" => {
+                let mut blocks: Vec<Value> = text_block(content).into_iter().collect();
+                for tc in msg.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+                    let func = tc.get("function");
+                    let input = func
+                        .and_then(|f| f.get("arguments"))
+                        .and_then(|a| a.as_str())
+                        .and_then(|a| serde_json::from_str::<Value>(a).ok())
+                        .filter(|v| v.is_object())
+                        .unwrap_or_else(|| json!({}));
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": tc.get("id").and_then(|i| i.as_str()).unwrap_or(""),
+                        "name": func.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or(""),
+                        "input": input
+                    }));
+                }
+                push_blocks(&mut messages, "assistant", blocks);
+            }
+            "tool" => {
+                let text = content.as_str().map(|s| s.to_string()).unwrap_or_else(|| content.to_string());
+                push_blocks(&mut messages, "user", vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": msg.get("tool_call_id").and_then(|i| i.as_str()).unwrap_or(""),
+                    "content": text
+                })]);
+            }
+            _ => push_blocks(&mut messages, "user", text_block(content).into_iter().collect()),
+        }
+    }
+    if messages.first().is_some_and(|m| m["role"] != "user") {
+        messages.insert(0, json!({"role": "user", "content": [{"type": "text", "text": "(continued conversation)"}]}));
+    }
+    messages
+}
+
+fn build_request_body(data: &LlmCallData, stream: bool) -> Value {
+    let sampling = &data.sampling;
+    let mut body = json!({
+        "model": data.model,
+        "max_tokens": sampling.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        "system": data.system_instruction,
+        "messages": convert_history(&data.history),
+    });
+    let tools: Vec<Value> = tool_functions(data)
+        .into_iter()
+        .map(|f| json!({"name": f["name"], "description": f["description"], "input_schema": f["parameters"]}))
+        .collect();
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+        if let Some(parallel) = sampling.parallel_tool_calls {
+            body["tool_choice"] = json!({"type": "auto", "disable_parallel_tool_use": !parallel});
+        }
+    }
+    // There is no seed, and extended thinking would need its blocks replayed with tool
+    // results, which history does not keep, so `seed` and `reasoning_effort` are not sent.
+    if let Some(temperature) = sampling.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = sampling.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if !sampling.stop.is_empty() {
+        body["stop_sequences"] = json!(sampling.stop);
+    }
+    if stream {
+        body["stream"] = json!(true);
+    }
+    body
+}
+
+fn map_stop_reason(reason: &str) -> &'static str {
+    match reason {
+        "tool_use" => "tool_calls",
+        "max_tokens" => "length",
+        _ => "stop",
+    }
+}
+
+/// Converts a Messages API response into an OpenAI-shaped `chat.completion`.
+fn convert_response(resp: &Value) -> Value {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for block in resp.get("content").and_then(|c| c.as_array()).into_iter().flatten() {
+        match block.get("type").and_then(|t| t.as_str()) {
+            Some("text") => text.push_str(block.get("text").and_then(|t| t.as_str()).unwrap_or("")),
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block["id"],
+                "type": "function",
+                "function": {
+                    "name": block["name"],
+                    "arguments": block.get("input").cloned().unwrap_or_else(|| json!({})).to_string()
+                }
+            })),
+            _ => {}
+        }
+    }
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": if text.is_empty() { Value::Null } else { json!(text) }
+    });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = json!(tool_calls);
+    }
+
+    json!({
+        "id": resp.get("id").cloned().unwrap_or_default(),
+        "object": "chat.completion",
+        "model": resp.get("model").cloned().unwrap_or_default(),
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": map_stop_reason(resp.get("stop_reason").and_then(|s| s.as_str()).unwrap_or(""))
+        }],
+        "usage": convert_usage(resp.get("usage").unwrap_or(&Value::Null))
+    })
+}
+
+/// Maps Anthropic token counts to the OpenAI `usage` shape. Cache reads and writes
+/// count as prompt tokens.
+fn convert_usage(usage: &Value) -> Value {
+    let count = |key: &str| usage.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    let cached = count("cache_read_input_tokens");
+    let prompt = count("input_tokens") + cached + count("cache_creation_input_tokens");
+    let completion = count("output_tokens");
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": prompt + completion,
+        "prompt_tokens_details": {"cached_tokens": cached}
+    })
+}
+
+/// Maps one server-sent event payload to stream events. Content block indexes are
+/// used as tool call indexes; text blocks never produce tool call deltas. Token counts
+/// arrive split across `message_start` and `message_delta`, so they are merged into
+/// `usage` and re-sent whole each time.
+fn convert_stream_event(event: &Value, usage: &mut Value) -> Result<Vec<StreamEvent>> {
+    let index = event.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as u32;
+    let mut events = Vec::new();
+    if let Some(counts) = event["message"]["usage"].as_object().or(event["usage"].as_object()) {
+        if !usage.is_object() {
+            *usage = json!({});
+        }
+        for (key, value) in counts {
+            usage[key] = value.clone();
+        }
+        events.push(StreamEvent::Usage(convert_usage(usage)));
+    }
+    let event = match event.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+        "content_block_start" => {
+            let block = &event["content_block"];
+            if block["type"] == "tool_use" {
+                Some(StreamEvent::ToolCallDelta {
+                    index,
+                    id: block["id"].as_str().map(|s| s.to_string()),
+                    name: block["name"].as_str().map(|s| s.to_string()),
+                    arguments: None,
+                })
+            } else {
+                None
+            }
+        }
+        "content_block_delta" => {
+            let delta = &event["delta"];
+            match delta["type"].as_str() {
+                Some("text_delta") => delta["text"].as_str().map(|t| StreamEvent::Token(t.to_string())),
+                Some("input_json_delta") => Some(StreamEvent::ToolCallDelta {
+                    index,
+                    id: None,
+                    name: None,
+                    arguments: delta["partial_json"].as_str().map(|s| s.to_string()),
+                }),
+                _ => None,
+            }
+        }
+        "message_delta" => event["delta"]["stop_reason"]
+            .as_str()
+            .map(|r| StreamEvent::Finish(map_stop_reason(r).to_string())),
+        "error" => {
+            let message = event["error"]["message"].as_str().unwrap_or("unknown error");
+            return Err(anyhow!("Stream error: {}", message));
+        }
+        _ => None,
+    };
+    events.extend(event);
+    Ok(events)
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    async fn chat(&self, data: &LlmCallData) -> Result<Value> {
+        let resp = self.send(&build_request_body(data, false)).await?;
+        let body: Value = resp
+            .json()
+            .await
+            .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
+        Ok(convert_response(&body))
+    }
+
+    async fn chat_stream(&self, data: &LlmCallData) -> Result<BoxStream<'static, Result<StreamEvent>>> {
+        let resp = self.send(&build_request_body(data, true)).await?;
+        Ok(line_stream(resp)
+            .scan(Value::Null, |usage, line| {
+                let events = match line {
+                    Ok(line) => match sse_data(&line).and_then(|data| serde_json::from_str::<Value>(data).ok()) {
+                        Some(event) => match convert_stream_event(&event, usage) {
+                            Ok(events) => events.into_iter().map(Ok).collect(),
+                            Err(e) => vec![Err(e)],
+                        },
+                        None => Vec::new(),
+                    },
+                    Err(e) => vec![Err(e)],
+                };
+                futures::future::ready(Some(stream::iter(events)))
+            })
+            .flatten()
+            .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::mock_server::MockServer;
+    use crate::provider::{ApiError, StreamAccumulator};
+    use std::sync::Arc;
+
+    fn provider_for(server: &MockServer) -> AnthropicProvider {
+        AnthropicProvider::new(&Config {
+            provider: crate::config::ProviderKind::Anthropic,
+            api_base_url: server.url(),
+            api_key: "test-key".into(),
+            ..Config::default()
+        })
+    }
+
+    fn call_data(history: Vec<Value>) -> LlmCallData {
+        LlmCallData {
+            provider: Arc::new(AnthropicProvider::new(&Config::default())),
+            model: "claude-test".into(),
+            system_instruction: "be brief".into(),
+            history,
+            tools: Vec::new(),
+            sampling: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_convert_history_maps_tool_calls_and_results() {
+        let history = vec![
+            json!({"role": "user", "content": "list files"}),
+            json!({"role": "assistant", "content": null, "tool_calls": [
+                {"id": "t1", "type": "function", "function": {"name": "execute_command", "arguments": "{\"command\":\"ls\"}"}},
+                {"id": "t2", "type": "function", "function": {"name": "execute_command", "arguments": "{\"command\":\"pwd\"}"}}
+            ]}),
+            json!({"role": "tool", "tool_call_id": "t1", "content": "a.txt"}),
+            json!({"role": "tool", "tool_call_id": "t2", "content": "/sandbox"}),
+            json!({"role": "assistant", "content": "Done."}),
+        ];
+        let messages = convert_history(&history);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][0]["input"]["command"], "ls");
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(messages[2]["content"][1]["tool_use_id"], "t2");
+        assert_eq!(messages[3]["content"][0]["text"], "Done.");
+    }
+
+    #[test]
+    fn test_request_body_sampling_options() {
+        let mut data = call_data(vec![json!({"role": "user", "content": "hi"})]);
+        assert_eq!(build_request_body(&data, false)["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert!(build_request_body(&data, false).get("temperature").is_none());
+
+        data.sampling = crate::config::Sampling {
+            temperature: Some(0.5),
+            max_tokens: Some(100),
+            stop: vec!["END".into()],
+            parallel_tool_calls: Some(false),
+            ..Default::default()
+        };
+        let body = build_request_body(&data, false);
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["max_tokens"], 100);
+        assert_eq!(body["stop_sequences"], json!(["END"]));
+        // No tools, so no tool_choice.
+        assert!(body.get("tool_choice").is_none());
+    }
+
+    #[test]
+    fn test_convert_history_starts_with_user() {
+        let messages = convert_history(&[json!({"role": "assistant", "content": "hi"})]);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[1]["role"], "assistant");
+    }
+
+    #[tokio::test]
+    async fn test_chat_converts_request_and_response() {
+        let server = MockServer::start(vec![(200, "application/json", json!({
+            "id": "msg_1", "model": "claude-test", "stop_reason": "tool_use",
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_1", "name": "execute_command", "input": {"command": "ls"}}
+            ],
+            "usage": {"input_tokens": 10, "output_tokens": 5, "cache_read_input_tokens": 4}
+        }).to_string())]);
+        let provider = provider_for(&server);
+
+        let response = provider.chat(&call_data(vec![json!({"role": "user", "content": "hi"})])).await.unwrap();
+        let choice = &response["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["message"]["content"], "Let me check.");
+        assert_eq!(choice["message"]["tool_calls"][0]["id"], "toolu_1");
+        assert_eq!(choice["message"]["tool_calls"][0]["function"]["arguments"], "{\"command\":\"ls\"}");
+        assert_eq!(response["usage"]["prompt_tokens"], 14);
+        assert_eq!(response["usage"]["prompt_tokens_details"]["cached_tokens"], 4);
+
+        let request = server.requests().remove(0);
+        assert_eq!(request.path, "/v1/messages");
+        assert_eq!(request.header("x-api-key").as_deref(), Some("test-key"));
+        assert_eq!(request.header("anthropic-version").as_deref(), Some(ANTHROPIC_VERSION));
+        assert_eq!(request.body["system"], "be brief");
+        assert_eq!(request.body["messages"][0]["content"][0]["text"], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_chat_error_keeps_status() {
+        let server = MockServer::start(vec![(429, "application/json",
+            json!({"type": "error", "error": {"type": "rate_limit_error", "message": "slow down"}}).to_string())]);
+        let err = provider_for(&server).chat(&call_data(Vec::new())).await.unwrap_err();
+        let api_err = err.downcast_ref::<ApiError>().unwrap();
+        assert_eq!(api_err.status, Some(429));
+        assert!(api_err.message.contains("slow down"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_assembles_text_and_tool_use() {
+        let events = [
+            json!({"type": "message_start", "message": {"id": "msg_1",
+                "usage": {"input_tokens": 20, "cache_read_input_tokens": 100, "output_tokens": 1}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Checking"}}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "execute_command", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"command\":"}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"ls\"}"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 15}}),
+            json!({"type": "message_stop"}),
+        ];
+        let body: String = events
+            .iter()
+            .map(|e| format!("event: {}\ndata: {}\n\n", e["type"].as_str().unwrap(), e))
+            .collect();
+        let server = MockServer::start(vec![(200, "text/event-stream", body)]);
+
+        let mut stream = provider_for(&server).chat_stream(&call_data(Vec::new())).await.unwrap();
+        let mut acc = StreamAccumulator::default();
+        while let Some(event) = stream.next().await {
+            acc.push(&event.unwrap());
+        }
+        assert_eq!(acc.content, "Checking");
+        let (message, response) = acc.finish();
+        assert_eq!(message["tool_calls"][0]["id"], "toolu_1");
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], "{\"command\":\"ls\"}");
+        assert_eq!(response["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(response["usage"]["prompt_tokens"], 120);
+        assert_eq!(response["usage"]["prompt_tokens_details"]["cached_tokens"], 100);
+        assert_eq!(response["usage"]["completion_tokens"], 15);
+        assert_eq!(server.requests()[0].body["stream"], true);
+    }
+}
@@ -0,0 +1,350 @@
+mod anthropic;
+mod ollama;
+mod openai;
+
+#[cfg(test)]
+pub(crate) mod mock_server;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde_json::{json, Value};
+
+use crate::chat::LlmCallData;
+use crate::config::{Config, ProviderKind};
+
+pub(crate) use anthropic::AnthropicProvider;
+pub(crate) use ollama::OllamaProvider;
+pub(crate) use openai::OpenAiProvider;
+
+/// An LLM backend. Requests and responses use the OpenAI chat format as the common
+/// representation: history is a list of OpenAI-style messages and every backend
+/// returns an OpenAI-shaped `chat.completion` JSON value, so callers never need to
+/// know which provider is in use.
+#[async_trait]
+pub(crate) trait LlmProvider: Send + Sync + fmt::Debug {
+    /// Short provider name, as used by the `PROVIDER` config key.
+    fn name(&self) -> &'static str;
+
+    /// Performs a non-streaming chat completion, including tool calls.
+    async fn chat(&self, data: &LlmCallData) -> Result<Value>;
+
+    /// Starts a streaming chat completion. Text and tool-call fragments are emitted as
+    /// [`StreamEvent`]s and can be assembled with a [`StreamAccumulator`].
+    async fn chat_stream(&self, data: &LlmCallData) -> Result<BoxStream<'static, Result<StreamEvent>>>;
+
+    /// Lists the models the server offers.
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        Err(anyhow!("Listing models is not supported by the {} provider", self.name()))
+    }
+
+    /// Downloads a model, reporting each new status line through `progress`.
+    async fn pull_model(&self, _name: &str, _progress: &(dyn for<'s> Fn(&'s str) + Send + Sync)) -> Result<()> {
+        Err(anyhow!("Pulling models is not supported by the {} provider", self.name()))
+    }
+}
+
+/// A model available from the provider, as shown by `/models`.
+#[derive(Debug, Clone)]
+pub(crate) struct ModelInfo {
+    pub name: String,
+    pub size_bytes: Option<u64>,
+    pub details: Option<String>,
+}
+
+impl fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(size) = self.size_bytes {
+            write!(f, "  {:.1} GB", size as f64 / 1e9)?;
+        }
+        if let Some(details) = &self.details {
+            write!(f, "  {}", details)?;
+        }
+        Ok(())
+    }
+}
+
+/// One incremental piece of a streamed response.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StreamEvent {
+    Token(String),
+    ToolCallDelta {
+        index: u32,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: Option<String>,
+    },
+    /// Token counts (OpenAI `usage` shape), sent once near the end of the stream.
+    Usage(Value),
+    Finish(String),
+}
+
+/// An HTTP-level failure from a provider. The status code drives retry decisions
+/// and is reported by `call_llm_api`, so it is not repeated in the message.
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Builds the provider selected by the `PROVIDER` config key.
+pub(crate) fn create_provider(config: &Config) -> Arc<dyn LlmProvider> {
+    match config.provider {
+        ProviderKind::OpenAi => Arc::new(OpenAiProvider::new(config)),
+        ProviderKind::Anthropic => Arc::new(AnthropicProvider::new(config)),
+        ProviderKind::Ollama => Arc::new(OllamaProvider::new(config)),
+    }
+}
+
+/// Assembles streamed events into the final assistant message, in the same shape a
+/// non-streaming call returns. Tool calls are kept in index order.
+#[derive(Debug, Default)]
+pub(crate) struct StreamAccumulator {
+    pub content: String,
+    tool_calls: BTreeMap<u32, (String, String, String)>,
+    finish_reason: Option<String>,
+    usage: Option<Value>,
+}
+
+impl StreamAccumulator {
+    pub(crate) fn push(&mut self, event: &StreamEvent) {
+        match event {
+            StreamEvent::Token(text) => self.content.push_str(text),
+            StreamEvent::ToolCallDelta { index, id, name, arguments } => {
+                let entry = self.tool_calls.entry(*index).or_default();
+                if let Some(id) = id {
+                    entry.0 = id.clone();
+                }
+                if let Some(name) = name {
+                    entry.1 = name.clone();
+                }
+                if let Some(args) = arguments {
+                    entry.2.push_str(args);
+                }
+            }
+            StreamEvent::Usage(usage) => self.usage = Some(usage.clone()),
+            StreamEvent::Finish(reason) => self.finish_reason = Some(reason.clone()),
+        }
+    }
+
+    pub(crate) fn tool_call_count(&self) -> usize {
+        self.tool_calls.len()
+    }
+
+    /// Returns the assistant message for history and an OpenAI-shaped response wrapping it.
+    pub(crate) fn finish(self) -> (Value, Value) {
+        let tool_calls_json: Vec<Value> = self
+            .tool_calls
+            .values()
+            .map(|(id, name, raw_args)| {
+                let args_map: serde_json::Map<String, Value> =
+                    serde_json::from_str(raw_args).unwrap_or_default();
+                json!({
+                    "id": id,
+                    "type": "function",
+                    "function": {
+                        "name": name,
+                        "arguments": Value::Object(args_map).to_string()
+                    }
+                })
+            })
+            .collect();
+
+        let content_value = if self.content.is_empty() {
+            Value::Null
+        } else {
+            json!(self.content)
+        };
+
+        let message = if tool_calls_json.is_empty() {
+            json!({"role": "assistant", "content": content_value})
+        } else {
+            json!({"role": "assistant", "content": content_value, "tool_calls": tool_calls_json})
+        };
+
+        let finish_reason = match self.finish_reason {
+            Some(reason) if reason != "stop" || tool_calls_json.is_empty() => reason,
+            _ if !tool_calls_json.is_empty() => "tool_calls".to_string(),
+            _ => "stop".to_string(),
+        };
+        let mut response = json!({
+            "choices": [{
+                "index": 0,
+                "message": message,
+                "finish_reason": finish_reason
+            }]
+        });
+        if let Some(usage) = self.usage {
+            response["usage"] = usage;
+        }
+        (message, response)
+    }
+}
+
+/// One-line load/eval timing report for responses that include `usage.timings`
+/// (Ollama), e.g. `load 0.50s · prompt 20 tok in 0.10s · 10 tok in 1.00s (10.0 tok/s)`.
+pub(crate) fn timing_summary(response: &Value) -> Option<String> {
+    let usage = response.get("usage")?;
+    let timings = usage.get("timings")?;
+    let ms = |key: &str| timings.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let tokens = |key: &str| usage.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    let eval_ms = ms("eval_ms");
+    let completion = tokens("completion_tokens");
+    let rate = if eval_ms > 0.0 {
+        format!(" ({:.1} tok/s)", completion as f64 * 1000.0 / eval_ms)
+    } else {
+        String::new()
+    };
+    Some(format!(
+        "load {:.2}s \u{00b7} prompt {} tok in {:.2}s \u{00b7} {} tok in {:.2}s{}",
+        ms("load_ms") / 1000.0,
+        tokens("prompt_tokens"),
+        ms("prompt_eval_ms") / 1000.0,
+        completion,
+        eval_ms / 1000.0,
+        rate
+    ))
+}
+
+/// Returns the tool definitions in `data` as plain JSON function objects
+/// (`name`, `description`, `parameters`).
+pub(crate) fn tool_functions(data: &LlmCallData) -> Vec<Value> {
+    data.tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "name": tool.function.name,
+                "description": tool.function.description.clone().unwrap_or_default(),
+                "parameters": tool.function.parameters.clone().unwrap_or_else(|| json!({"type": "object"}))
+            })
+        })
+        .collect()
+}
+
+/// Converts a failed HTTP response into an [`ApiError`], keeping the body for context.
+pub(crate) async fn error_from_response(resp: reqwest::Response) -> anyhow::Error {
+    let status = resp.status().as_u16();
+    let body = resp.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|v| {
+            v.pointer("/error/message")
+                .or_else(|| v.get("error"))
+                .and_then(|m| m.as_str())
+                .map(|m| m.to_string())
+        })
+        .unwrap_or_else(|| crate::utils::truncate_str(body.trim(), 500));
+    ApiError {
+        status: Some(status),
+        message,
+    }
+    .into()
+}
+
+/// Maps a transport error to an [`ApiError`] (no status: connection or timeout).
+pub(crate) fn transport_error(e: reqwest::Error) -> anyhow::Error {
+    ApiError {
+        status: e.status().map(|s| s.as_u16()),
+        message: e.to_string(),
+    }
+    .into()
+}
+
+/// Splits a streaming HTTP body into text lines (without trailing `\r\n`).
+/// Used for both server-sent events and newline-delimited JSON.
+pub(crate) fn line_stream(resp: reqwest::Response) -> BoxStream<'static, Result<String>> {
+    let bytes = resp.bytes_stream();
+    stream::unfold(
+        (bytes, Vec::<u8>::new(), false),
+        |(mut bytes, mut buf, mut done)| async move {
+            loop {
+                if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=pos).collect();
+                    let text = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+                    return Some((Ok(text), (bytes, buf, done)));
+                }
+                if done {
+                    if buf.is_empty() {
+                        return None;
+                    }
+                    let text = String::from_utf8_lossy(&buf).trim_end().to_string();
+                    buf.clear();
+                    return Some((Ok(text), (bytes, buf, done)));
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                    Some(Err(e)) => {
+                        done = true;
+                        buf.clear();
+                        return Some((Err(anyhow!("Stream error: {}", e)), (bytes, buf, done)));
+                    }
+                    None => done = true,
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+/// Extracts the payload of server-sent event `data:` lines, skipping comments,
+/// `event:` lines and the OpenAI `[DONE]` sentinel.
+pub(crate) fn sse_data(line: &str) -> Option<&str> {
+    let data = line.strip_prefix("data:")?.trim_start();
+    if data.is_empty() || data == "[DONE]" {
+        None
+    } else {
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_data() {
+        assert_eq!(sse_data("data: {\"a\":1}"), Some("{\"a\":1}"));
+        assert_eq!(sse_data("data: [DONE]"), None);
+        assert_eq!(sse_data("event: ping"), None);
+        assert_eq!(sse_data(""), None);
+    }
+
+    #[test]
+    fn test_accumulator_assembles_tool_calls_in_order() {
+        let mut acc = StreamAccumulator::default();
+        acc.push(&StreamEvent::Token("Hi".into()));
+        acc.push(&StreamEvent::ToolCallDelta { index: 1, id: Some("b".into()), name: Some("two".into()), arguments: Some("{}".into()) });
+        acc.push(&StreamEvent::ToolCallDelta { index: 0, id: Some("a".into()), name: Some("one".into()), arguments: Some("{\"x\":".into()) });
+        acc.push(&StreamEvent::ToolCallDelta { index: 0, id: None, name: None, arguments: Some("1}".into()) });
+        acc.push(&StreamEvent::Finish("tool_calls".into()));
+        let (message, response) = acc.finish();
+        assert_eq!(message["content"], "Hi");
+        assert_eq!(message["tool_calls"][0]["id"], "a");
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], "{\"x\":1}");
+        assert_eq!(message["tool_calls"][1]["function"]["name"], "two");
+        assert_eq!(response["choices"][0]["finish_reason"], "tool_calls");
+    }
+
+    #[test]
+    fn test_create_provider_follows_config() {
+        let mut config = Config::default();
+        assert_eq!(create_provider(&config).name(), "openai");
+        config.provider = ProviderKind::Anthropic;
+        assert_eq!(create_provider(&config).name(), "anthropic");
+        config.provider = ProviderKind::Ollama;
+        assert_eq!(create_provider(&config).name(), "ollama");
+    }
+}
@@ -0,0 +1,483 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde_json::{json, Value};
+
+use super::{error_from_response, line_stream, tool_functions, transport_error, LlmProvider, ModelInfo, StreamEvent};
+use crate::chat::LlmCallData;
+use crate::config::{Config, ReasoningEffort};
+
+/// Native Ollama `/api/chat` backend. Unlike the OpenAI-compatible shim, it streams
+/// tool calls, and every response carries load and evaluation timings.
+#[derive(Debug)]
+pub(crate) struct OllamaProvider {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl OllamaProvider {
+    pub(crate) fn new(config: &Config) -> Self {
+        OllamaProvider {
+            base_url: config.api_base_url.trim_end_matches('/').to_string(),
+            client: crate::http::create_llm_http_client(),
+        }
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<reqwest::Response> {
+        let resp = self
+            .client
+            .post(format!("{}{}", self.base_url, path))
+            .json(body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+        if !resp.status().is_success() {
+            return Err(error_from_response(resp).await);
+        }
+        Ok(resp)
+    }
+}
+
+/// Ollama does not assign tool call ids; history needs them to pair results with calls.
+fn next_tool_call_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "call_{:x}{:04x}",
+        chrono::Local::now().timestamp_millis(),
+        COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+    )
+}
+
+/// Converts OpenAI-format history to Ollama messages. Tool call arguments become
+/// JSON objects and tool results carry the name of the tool that produced them.
+fn convert_history(system_instruction: &str, history: &[Value]) -> Vec<Value> {
+    let mut messages = vec![json!({"role": "system", "content": system_instruction})];
+    let mut tool_names: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+    for msg in history {
+        let role = msg.get("role").and_then(|r| r.as_str()).unwrap_or("user");
+        let content = match msg.get("content") {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(other) => other.to_string(),
+        };
+        let mut converted = json!({"role": role, "content": content});
+        if let Some(tool_calls) = msg.get("tool_calls").and_then(|t| t.as_array()) {
+            let calls: Vec<Value> = tool_calls
+                .iter()
+                .map(|tc| {
+                    let func = tc.get("function");
+                    let name = func.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or("");
+                    if let Some(id) = tc.get("id").and_then(|i| i.as_str()) {
+                        tool_names.insert(id.to_string(), name.to_string());
+                    }
+                    let arguments = func
+                        .and_then(|f| f.get("arguments"))
+                        .and_then(|a| a.as_str())
+                        .and_then(|a| serde_json::from_str::<Value>(a).ok())
+                        .unwrap_or_else(|| json!({}));
+                    json!({"function": {"name": name, "arguments": arguments}})
+                })
+                .collect();
+            converted["tool_calls"] = json!(calls);
+        }
+        if role == "tool" {
+            if let Some(name) = msg
+                .get("tool_call_id")
+                .and_then(|i| i.as_str())
+                .and_then(|id| tool_names.get(id))
+            {
+                converted["tool_name"] = json!(name);
+            }
+        }
+        messages.push(converted);
+    }
+    messages
+}
+
+fn build_request_body(data: &LlmCallData, stream: bool) -> Value {
+    let mut body = json!({
+        "model": data.model,
+        "messages": convert_history(&data.system_instruction, &data.history),
+        "stream": stream,
+    });
+    let tools: Vec<Value> = tool_functions(data)
+        .into_iter()
+        .map(|f| json!({"type": "function", "function": f}))
+        .collect();
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+    }
+    let sampling = &data.sampling;
+    let mut options = serde_json::Map::new();
+    if let Some(temperature) = sampling.temperature {
+        options.insert("temperature".into(), json!(temperature));
+    }
+    if let Some(top_p) = sampling.top_p {
+        options.insert("top_p".into(), json!(top_p));
+    }
+    if let Some(max_tokens) = sampling.max_tokens {
+        options.insert("num_predict".into(), json!(max_tokens));
+    }
+    if let Some(seed) = sampling.seed {
+        options.insert("seed".into(), json!(seed));
+    }
+    if !sampling.stop.is_empty() {
+        options.insert("stop".into(), json!(sampling.stop));
+    }
+    if !options.is_empty() {
+        body["options"] = Value::Object(options);
+    }
+    // Ollama only switches thinking on or off; `parallel_tool_calls` has no equivalent.
+    if let Some(effort) = sampling.reasoning_effort {
+        body["think"] = json!(effort != ReasoningEffort::Minimal);
+    }
+    body
+}
+
+fn convert_tool_call(tc: &Value) -> (String, String, String) {
+    let func = tc.get("function");
+    let name = func.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or("").to_string();
+    let arguments = match func.and_then(|f| f.get("arguments")) {
+        Some(Value::String(s)) => s.clone(),
+        Some(args) => args.to_string(),
+        None => "{}".to_string(),
+    };
+    let id = tc
+        .get("id")
+        .and_then(|i| i.as_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(next_tool_call_id);
+    (id, name, arguments)
+}
+
+/// Builds an OpenAI-style `usage` object from the final chunk, with Ollama's
+/// nanosecond durations added under `timings` in milliseconds.
+fn usage_from(resp: &Value) -> Value {
+    let count = |key: &str| resp.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    let ms = |key: &str| count(key) as f64 / 1_000_000.0;
+    let prompt = count("prompt_eval_count");
+    let completion = count("eval_count");
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": prompt + completion,
+        "timings": {
+            "load_ms": ms("load_duration"),
+            "prompt_eval_ms": ms("prompt_eval_duration"),
+            "eval_ms": ms("eval_duration"),
+            "total_ms": ms("total_duration")
+        }
+    })
+}
+
+fn finish_reason(resp: &Value, has_tool_calls: bool) -> &'static str {
+    if has_tool_calls {
+        return "tool_calls";
+    }
+    match resp.get("done_reason").and_then(|r| r.as_str()) {
+        Some("length") => "length",
+        _ => "stop",
+    }
+}
+
+/// Converts a non-streaming `/api/chat` response into an OpenAI-shaped `chat.completion`.
+fn convert_response(resp: &Value) -> Value {
+    let message = resp.get("message").cloned().unwrap_or_default();
+    let content = message.get("content").and_then(|c| c.as_str()).unwrap_or("");
+    let tool_calls: Vec<Value> = message
+        .get("tool_calls")
+        .and_then(|t| t.as_array())
+        .into_iter()
+        .flatten()
+        .map(|tc| {
+            let (id, name, arguments) = convert_tool_call(tc);
+            json!({"id": id, "type": "function", "function": {"name": name, "arguments": arguments}})
+        })
+        .collect();
+
+    let mut out_message = json!({
+        "role": "assistant",
+        "content": if content.is_empty() { Value::Null } else { json!(content) }
+    });
+    if !tool_calls.is_empty() {
+        out_message["tool_calls"] = json!(tool_calls);
+    }
+
+    json!({
+        "object": "chat.completion",
+        "model": resp.get("model").cloned().unwrap_or_default(),
+        "choices": [{
+            "index": 0,
+            "message": out_message,
+            "finish_reason": finish_reason(resp, !tool_calls.is_empty())
+        }],
+        "usage": usage_from(resp)
+    })
+}
+
+/// Maps one NDJSON chunk to stream events. Ollama sends each tool call whole, so a
+/// single delta carries its id, name and arguments; `next_index` keeps them ordered.
+fn convert_stream_chunk(chunk: &Value, next_index: &mut u32) -> Result<Vec<StreamEvent>> {
+    if let Some(error) = chunk.get("error") {
+        return Err(anyhow!("Stream error: {}", error.as_str().unwrap_or(&error.to_string())));
+    }
+    let mut events = Vec::new();
+    let message = chunk.get("message");
+    if let Some(content) = message.and_then(|m| m.get("content")).and_then(|c| c.as_str()) {
+        if !content.is_empty() {
+            events.push(StreamEvent::Token(content.to_string()));
+        }
+    }
+    for tc in message.and_then(|m| m.get("tool_calls")).and_then(|t| t.as_array()).into_iter().flatten() {
+        let (id, name, arguments) = convert_tool_call(tc);
+        events.push(StreamEvent::ToolCallDelta {
+            index: *next_index,
+            id: Some(id),
+            name: Some(name),
+            arguments: Some(arguments),
+        });
+        *next_index += 1;
+    }
+    if chunk.get("done").and_then(|d| d.as_bool()) == Some(true) {
+        events.push(StreamEvent::Usage(usage_from(chunk)));
+        events.push(StreamEvent::Finish(finish_reason(chunk, *next_index > 0).to_string()));
+    }
+    Ok(events)
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    async fn chat(&self, data: &LlmCallData) -> Result<Value> {
+        let resp = self.post("/api/chat", &build_request_body(data, false)).await?;
+        let body: Value = resp
+            .json()
+            .await
+            .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
+        Ok(convert_response(&body))
+    }
+
+    async fn chat_stream(&self, data: &LlmCallData) -> Result<BoxStream<'static, Result<StreamEvent>>> {
+        let resp = self.post("/api/chat", &build_request_body(data, true)).await?;
+        let mut next_index = 0;
+        Ok(line_stream(resp)
+            .flat_map(move |line| {
+                let events: Vec<Result<StreamEvent>> = match line {
+                    Ok(line) if line.trim().is_empty() => Vec::new(),
+                    Ok(line) => match serde_json::from_str::<Value>(&line) {
+                        Ok(chunk) => match convert_stream_chunk(&chunk, &mut next_index) {
+                            Ok(events) => events.into_iter().map(Ok).collect(),
+                            Err(e) => vec![Err(e)],
+                        },
+                        Err(e) => vec![Err(anyhow!("Invalid stream chunk: {}", e))],
+                    },
+                    Err(e) => vec![Err(e)],
+                };
+                stream::iter(events)
+            })
+            .boxed())
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let resp = self
+            .client
+            .get(format!("{}/api/tags", self.base_url))
+            .send()
+            .await
+            .map_err(transport_error)?;
+        if !resp.status().is_success() {
+            return Err(error_from_response(resp).await);
+        }
+        let body: Value = resp
+            .json()
+            .await
+            .map_err(|e| anyhow!("Failed to parse model list: {}", e))?;
+        Ok(body
+            .get("models")
+            .and_then(|m| m.as_array())
+            .into_iter()
+            .flatten()
+            .map(|m| {
+                let details = m.get("details");
+                let detail = |key: &str| details.and_then(|d| d.get(key)).and_then(|v| v.as_str()).unwrap_or("");
+                let description = format!("{} {}", detail("parameter_size"), detail("quantization_level"));
+                ModelInfo {
+                    name: m.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string(),
+                    size_bytes: m.get("size").and_then(|s| s.as_u64()),
+                    details: Some(description.trim().to_string()).filter(|d| !d.is_empty()),
+                }
+            })
+            .collect())
+    }
+
+    async fn pull_model(&self, name: &str, progress: &(dyn for<'s> Fn(&'s str) + Send + Sync)) -> Result<()> {
+        let resp = self.post("/api/pull", &json!({"model": name, "stream": true})).await?;
+        let mut lines = line_stream(resp);
+        let mut last_status = String::new();
+        while let Some(line) = lines.next().await {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let chunk: Value = serde_json::from_str(&line).map_err(|e| anyhow!("Invalid pull progress: {}", e))?;
+            if let Some(error) = chunk.get("error").and_then(|e| e.as_str()) {
+                return Err(anyhow!("Pull failed: {}", error));
+            }
+            let status = chunk.get("status").and_then(|s| s.as_str()).unwrap_or("");
+            if status == "success" {
+                return Ok(());
+            }
+            if status != last_status {
+                progress(status);
+                last_status = status.to_string();
+            }
+        }
+        Err(anyhow!("Pull ended before completion"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::mock_server::MockServer;
+    use crate::provider::{timing_summary, StreamAccumulator};
+    use std::sync::Arc;
+
+    fn provider_for(server: &MockServer) -> OllamaProvider {
+        OllamaProvider::new(&Config {
+            provider: crate::config::ProviderKind::Ollama,
+            api_base_url: server.url(),
+            ..Config::default()
+        })
+    }
+
+    fn call_data(history: Vec<Value>) -> LlmCallData {
+        LlmCallData {
+            provider: Arc::new(OllamaProvider::new(&Config::default())),
+            model: "llama3".into(),
+            system_instruction: "be brief".into(),
+            history,
+            tools: Vec::new(),
+            sampling: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_convert_history_maps_tool_calls_and_results() {
+        let history = vec![
+            json!({"role": "user", "content": "list files"}),
+            json!({"role": "assistant", "content": null, "tool_calls": [
+                {"id": "t1", "type": "function", "function": {"name": "execute_command", "arguments": "{\"command\":\"ls\"}"}}
+            ]}),
+            json!({"role": "tool", "tool_call_id": "t1", "content": "a.txt"}),
+        ];
+        let messages = convert_history("sys", &history);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[2]["content"], "");
+        assert_eq!(messages[2]["tool_calls"][0]["function"]["arguments"]["command"], "ls");
+        assert_eq!(messages[3]["tool_name"], "execute_command");
+    }
+
+    #[test]
+    fn test_request_body_sampling_options() {
+        let mut data = call_data(Vec::new());
+        assert!(build_request_body(&data, false).get("options").is_none());
+
+        data.sampling = crate::config::Sampling {
+            temperature: Some(0.2),
+            max_tokens: Some(64),
+            seed: Some(3),
+            reasoning_effort: Some(ReasoningEffort::Low),
+            ..Default::default()
+        };
+        let body = build_request_body(&data, false);
+        assert_eq!(body["options"], json!({"temperature": 0.2f32, "num_predict": 64, "seed": 3}));
+        assert_eq!(body["think"], true);
+    }
+
+    #[tokio::test]
+    async fn test_chat_converts_tool_calls_and_timings() {
+        let server = MockServer::start(vec![(200, "application/json", json!({
+            "model": "llama3", "done": true, "done_reason": "stop",
+            "message": {"role": "assistant", "content": "",
+                "tool_calls": [{"function": {"name": "execute_command", "arguments": {"command": "ls"}}}]},
+            "total_duration": 2_000_000_000u64, "load_duration": 500_000_000u64,
+            "prompt_eval_count": 20, "prompt_eval_duration": 100_000_000u64,
+            "eval_count": 10, "eval_duration": 1_000_000_000u64
+        }).to_string())]);
+
+        let response = provider_for(&server).chat(&call_data(vec![json!({"role": "user", "content": "hi"})])).await.unwrap();
+        let choice = &response["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert!(choice["message"]["tool_calls"][0]["id"].as_str().unwrap().starts_with("call_"));
+        assert_eq!(choice["message"]["tool_calls"][0]["function"]["arguments"], "{\"command\":\"ls\"}");
+        assert_eq!(response["usage"]["completion_tokens"], 10);
+        assert_eq!(response["usage"]["timings"]["load_ms"], 500.0);
+        assert!(timing_summary(&response).unwrap().contains("10.0 tok/s"));
+
+        let request = server.requests().remove(0);
+        assert_eq!(request.path, "/api/chat");
+        assert_eq!(request.body["stream"], false);
+        assert_eq!(request.body["messages"][1]["content"], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_emits_tokens_tool_calls_and_usage() {
+        let body = [
+            json!({"message": {"role": "assistant", "content": "Let me "}, "done": false}),
+            json!({"message": {"role": "assistant", "content": "check."}, "done": false}),
+            json!({"message": {"role": "assistant", "content": "",
+                "tool_calls": [{"function": {"name": "execute_command", "arguments": {"command": "ls"}}}]}, "done": false}),
+            json!({"message": {"role": "assistant", "content": ""}, "done": true, "done_reason": "stop",
+                "prompt_eval_count": 5, "eval_count": 7}),
+        ]
+        .iter()
+        .map(|c| format!("{}\n", c))
+        .collect::<String>();
+        let server = MockServer::start(vec![(200, "application/x-ndjson", body)]);
+
+        let mut stream = provider_for(&server).chat_stream(&call_data(Vec::new())).await.unwrap();
+        let mut acc = StreamAccumulator::default();
+        while let Some(event) = stream.next().await {
+            acc.push(&event.unwrap());
+        }
+        assert_eq!(acc.content, "Let me check.");
+        let (message, response) = acc.finish();
+        assert_eq!(message["tool_calls"][0]["function"]["name"], "execute_command");
+        assert_eq!(response["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(response["usage"]["completion_tokens"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_pull_models() {
+        let server = MockServer::start(vec![
+            (200, "application/json", json!({"models": [
+                {"name": "llama3:latest", "size": 4_700_000_000u64,
+                 "details": {"parameter_size": "8.0B", "quantization_level": "Q4_0"}}
+            ]}).to_string()),
+            (200, "application/x-ndjson", "{\"status\":\"pulling manifest\"}\n{\"status\":\"success\"}\n".to_string()),
+        ]);
+        let provider = provider_for(&server);
+
+        let models = provider.list_models().await.unwrap();
+        assert_eq!(models[0].name, "llama3:latest");
+        assert_eq!(models[0].details.as_deref(), Some("8.0B Q4_0"));
+
+        let seen = std::sync::Mutex::new(Vec::new());
+        provider.pull_model("llama3", &|s| seen.lock().unwrap().push(s.to_string())).await.unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec!["pulling manifest".to_string()]);
+        assert_eq!(server.requests()[1].body["model"], "llama3");
+    }
+
+    #[tokio::test]
+    async fn test_error_body_is_reported() {
+        let server = MockServer::start(vec![(404, "application/json", json!({"error": "model 'nope' not found"}).to_string())]);
+        let err = provider_for(&server).chat(&call_data(Vec::new())).await.unwrap_err();
+        assert!(err.to_string().contains("model 'nope' not found"));
+    }
+}
@@ -0,0 +1,391 @@
+use anyhow::{anyhow, Result};
+use async_openai::{
+    config::OpenAIConfig,
+    error::OpenAIError,
+    types::{
+        ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+        ChatCompletionRequestSystemMessageContent, ChatCompletionStreamOptions, CreateChatCompletionRequest,
+        CreateChatCompletionStreamResponse, Stop,
+    },
+    Client,
+};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{error_from_response, line_stream, sse_data, transport_error, ApiError, LlmProvider, ModelInfo, StreamEvent};
+use crate::chat::LlmCallData;
+use crate::config::{Config, ReasoningEffort};
+
+/// OpenAI-compatible `/chat/completions` backend (OpenAI, Gemini, most local servers).
+/// History is already in this API's format, so messages are passed through as-is.
+#[derive(Debug)]
+pub(crate) struct OpenAiProvider {
+    client: Client<OpenAIConfig>,
+    endpoint: String,
+    api_key: String,
+    http: reqwest::Client,
+    /// Set once the server rejected `stream_options`, so later streams go without usage.
+    no_stream_usage: AtomicBool,
+}
+
+impl OpenAiProvider {
+    pub(crate) fn new(config: &Config) -> Self {
+        let openai_config = OpenAIConfig::new()
+            .with_api_key(config.api_key.clone())
+            .with_api_base(format!("{}/{}", config.api_base_url, config.api_version));
+        OpenAiProvider {
+            client: Client::with_config(openai_config),
+            endpoint: config.get_api_endpoint(),
+            api_key: config.api_key.clone(),
+            http: crate::http::create_llm_http_client(),
+            no_stream_usage: AtomicBool::new(false),
+        }
+    }
+
+    /// Requests are sent as plain JSON rather than through async-openai 0.24, which has
+    /// no `reasoning_effort` or `max_completion_tokens` and drops `prompt_tokens_details`
+    /// from the returned usage.
+    async fn send(&self, request: &CreateChatCompletionRequest, effort: Option<ReasoningEffort>) -> Result<reqwest::Response> {
+        let mut body = serde_json::to_value(request)?;
+        if let Some(effort) = effort {
+            body["reasoning_effort"] = json!(effort.as_str());
+            // Reasoning models reject `max_tokens`.
+            if let Some(max_tokens) = body.as_object_mut().and_then(|b| b.remove("max_tokens")) {
+                body["max_completion_tokens"] = max_tokens;
+            }
+        }
+        if self.no_stream_usage.load(Ordering::Relaxed) {
+            body.as_object_mut().map(|b| b.remove("stream_options"));
+        }
+        match self.post(&body).await {
+            // Some OpenAI-compatible servers reject `stream_options`; retry without usage.
+            Err(e) if body.get("stream_options").is_some() && rejects_stream_options(&e) => {
+                self.no_stream_usage.store(true, Ordering::Relaxed);
+                body.as_object_mut().map(|b| b.remove("stream_options"));
+                self.post(&body).await
+            }
+            result => result,
+        }
+    }
+
+    async fn post(&self, body: &Value) -> Result<reqwest::Response> {
+        let resp = self
+            .http
+            .post(&self.endpoint)
+            .bearer_auth(&self.api_key)
+            .json(body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+        if !resp.status().is_success() {
+            return Err(error_from_response(resp).await);
+        }
+        Ok(resp)
+    }
+}
+
+fn rejects_stream_options(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ApiError>()
+        .is_some_and(|e| e.status == Some(400) && e.message.contains("stream_options"))
+}
+
+fn build_request(data: &LlmCallData, stream: bool) -> Result<CreateChatCompletionRequest> {
+    let mut chat_messages: Vec<ChatCompletionRequestMessage> = Vec::new();
+    chat_messages.push(ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+        content: ChatCompletionRequestSystemMessageContent::Text(data.system_instruction.clone()),
+        name: None,
+    }));
+    for msg in &data.history {
+        let message: ChatCompletionRequestMessage = serde_json::from_value(msg.clone())
+            .map_err(|e| anyhow!("Failed to parse message: {}", e))?;
+        chat_messages.push(message);
+    }
+
+    let sampling = &data.sampling;
+    Ok(CreateChatCompletionRequest {
+        model: data.model.clone(),
+        messages: chat_messages,
+        tools: if data.tools.is_empty() { None } else { Some(data.tools.clone()) },
+        // Rejected by the API when no tools are sent.
+        parallel_tool_calls: sampling.parallel_tool_calls.filter(|_| !data.tools.is_empty()),
+        stream: if stream { Some(true) } else { None },
+        // Adds a final chunk with token counts.
+        stream_options: stream.then_some(ChatCompletionStreamOptions { include_usage: true }),
+        temperature: sampling.temperature,
+        top_p: sampling.top_p,
+        max_tokens: sampling.max_tokens,
+        seed: sampling.seed,
+        stop: (!sampling.stop.is_empty()).then(|| Stop::StringArray(sampling.stop.clone())),
+        ..Default::default()
+    })
+}
+
+/// Converts one streamed chunk into stream events. The raw `usage` object is kept
+/// as-is so cached token counts survive.
+fn chunk_events(chunk: Value) -> Vec<Result<StreamEvent>> {
+    let mut events = Vec::new();
+    if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+        events.push(Ok(StreamEvent::Usage(usage.clone())));
+    }
+    let response: CreateChatCompletionStreamResponse = match serde_json::from_value(chunk) {
+        Ok(response) => response,
+        Err(e) => return vec![Err(anyhow!("Stream error: {}", e))],
+    };
+    for choice in response.choices {
+        if let Some(content) = choice.delta.content {
+            events.push(Ok(StreamEvent::Token(content)));
+        }
+        for tc in choice.delta.tool_calls.unwrap_or_default() {
+            let (name, arguments) = match tc.function {
+                Some(func) => (func.name, func.arguments),
+                None => (None, None),
+            };
+            events.push(Ok(StreamEvent::ToolCallDelta {
+                index: tc.index as u32,
+                id: tc.id,
+                name,
+                arguments,
+            }));
+        }
+        if let Some(reason) = choice.finish_reason {
+            let reason = serde_json::to_value(reason)
+                .ok()
+                .and_then(|v| v.as_str().map(|s| s.to_string()))
+                .unwrap_or_else(|| "stop".to_string());
+            events.push(Ok(StreamEvent::Finish(reason)));
+        }
+    }
+    events
+}
+
+fn api_error(e: OpenAIError) -> anyhow::Error {
+    let status = match &e {
+        OpenAIError::Reqwest(reqwest_err) => reqwest_err.status().map(|s| s.as_u16()),
+        _ => None,
+    };
+    ApiError {
+        status,
+        message: e.to_string(),
+    }
+    .into()
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    async fn chat(&self, data: &LlmCallData) -> Result<Value> {
+        let request = build_request(data, false)?;
+        let resp = self.send(&request, data.sampling.reasoning_effort).await?;
+        resp.json().await.map_err(transport_error)
+    }
+
+    async fn chat_stream(&self, data: &LlmCallData) -> Result<BoxStream<'static, Result<StreamEvent>>> {
+        let request = build_request(data, true)?;
+        let resp = self.send(&request, data.sampling.reasoning_effort).await?;
+        Ok(line_stream(resp)
+            .flat_map(|line| {
+                let events = match line {
+                    Ok(line) => match sse_data(&line).map(serde_json::from_str::<Value>) {
+                        Some(Ok(chunk)) => chunk_events(chunk),
+                        Some(Err(e)) => vec![Err(anyhow!("Stream error: {}", e))],
+                        None => Vec::new(),
+                    },
+                    Err(e) => vec![Err(e)],
+                };
+                stream::iter(events)
+            })
+            .boxed())
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let response = self.client.models().list().await.map_err(api_error)?;
+        let mut models: Vec<ModelInfo> = response
+            .data
+            .into_iter()
+            .map(|m| ModelInfo {
+                name: m.id,
+                size_bytes: None,
+                details: None,
+            })
+            .collect();
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(models)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::mock_server::MockServer;
+    use crate::provider::StreamAccumulator;
+    use serde_json::json;
+
+    fn test_config(base: &str) -> Config {
+        Config {
+            api_base_url: base.to_string(),
+            api_key: "test-key".to_string(),
+            ..Config::default()
+        }
+    }
+
+    fn call_data(history: Vec<Value>) -> LlmCallData {
+        LlmCallData {
+            provider: std::sync::Arc::new(OpenAiProvider::new(&Config::default())),
+            model: "test-model".into(),
+            system_instruction: "be brief".into(),
+            history,
+            tools: Vec::new(),
+            sampling: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_sends_history_and_returns_response() {
+        let server = MockServer::start(vec![(200, "application/json", json!({
+            "id": "1", "object": "chat.completion", "created": 0, "model": "test-model",
+            "choices": [{"index": 0, "finish_reason": "stop",
+                "message": {"role": "assistant", "content": "hello"}}]
+        }).to_string())]);
+        let provider = OpenAiProvider::new(&test_config(&server.url()));
+        let data = call_data(vec![json!({"role": "user", "content": "hi"})]);
+
+        let response = provider.chat(&data).await.unwrap();
+        assert_eq!(response["choices"][0]["message"]["content"], "hello");
+
+        let request = server.requests().remove(0);
+        assert!(request.path.ends_with("/v1/chat/completions"));
+        assert_eq!(request.header("authorization").as_deref(), Some("Bearer test-key"));
+        assert_eq!(request.body["messages"][0]["role"], "system");
+        assert_eq!(request.body["messages"][1]["content"], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_keeps_its_name_without_reasoning_effort() {
+        let server = MockServer::start(vec![(200, "application/json", json!({
+            "id": "1", "object": "chat.completion", "created": 0, "model": "test-model",
+            "choices": [{"index": 0, "finish_reason": "stop",
+                "message": {"role": "assistant", "content": "ok"}}]
+        }).to_string())]);
+        let provider = OpenAiProvider::new(&test_config(&server.url()));
+        let mut data = call_data(Vec::new());
+        data.sampling.max_tokens = Some(300);
+
+        provider.chat(&data).await.unwrap();
+        let request = server.requests().remove(0);
+        assert_eq!(request.body["max_tokens"], 300);
+        assert!(request.body.get("max_completion_tokens").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_emits_tokens_and_tool_calls() {
+        let chunk = |delta: Value, finish: Value| {
+            format!("data: {}\n\n", json!({
+                "id": "1", "object": "chat.completion.chunk", "created": 0, "model": "m",
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish}]
+            }))
+        };
+        let body = [
+            chunk(json!({"role": "assistant", "content": "Hel"}), Value::Null),
+            chunk(json!({"content": "lo"}), Value::Null),
+            chunk(json!({"tool_calls": [{"index": 0, "id": "call_1", "type": "function",
+                "function": {"name": "execute_command", "arguments": "{\"command\":"}}]}), Value::Null),
+            chunk(json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"ls\"}"}}]}), json!("tool_calls")),
+            format!("data: {}\n\n", json!({
+                "id": "1", "object": "chat.completion.chunk", "created": 0, "model": "m", "choices": [],
+                "usage": {"prompt_tokens": 50, "completion_tokens": 9, "total_tokens": 59,
+                    "prompt_tokens_details": {"cached_tokens": 32}}
+            })),
+            "data: [DONE]\n\n".to_string(),
+        ]
+        .concat();
+        let server = MockServer::start(vec![(200, "text/event-stream", body)]);
+        let provider = OpenAiProvider::new(&test_config(&server.url()));
+
+        let mut stream = provider.chat_stream(&call_data(Vec::new())).await.unwrap();
+        let mut acc = StreamAccumulator::default();
+        while let Some(event) = stream.next().await {
+            acc.push(&event.unwrap());
+        }
+        assert_eq!(acc.content, "Hello");
+        let (message, response) = acc.finish();
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], "{\"command\":\"ls\"}");
+        assert_eq!(response["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(response["usage"]["prompt_tokens_details"]["cached_tokens"], 32);
+        assert_eq!(server.requests()[0].body["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn test_stream_retries_without_stream_options_when_rejected() {
+        let chunk = json!({
+            "id": "1", "object": "chat.completion.chunk", "created": 0, "model": "m",
+            "choices": [{"index": 0, "delta": {"content": "ok"}, "finish_reason": "stop"}]
+        });
+        let body = format!("data: {}\n\ndata: [DONE]\n\n", chunk);
+        let rejected = json!({"error": {"message": "Unrecognized request argument supplied: stream_options"}}).to_string();
+        let server = MockServer::start(vec![
+            (400, "application/json", rejected),
+            (200, "text/event-stream", body.clone()),
+            (200, "text/event-stream", body),
+        ]);
+        let provider = OpenAiProvider::new(&test_config(&server.url()));
+
+        for _ in 0..2 {
+            let mut stream = provider.chat_stream(&call_data(Vec::new())).await.unwrap();
+            let mut acc = StreamAccumulator::default();
+            while let Some(event) = stream.next().await {
+                acc.push(&event.unwrap());
+            }
+            assert_eq!(acc.content, "ok");
+        }
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].body["stream_options"]["include_usage"], true);
+        assert!(requests[1].body.get("stream_options").is_none());
+        assert!(requests[2].body.get("stream_options").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sampling_options_and_reasoning_effort_are_sent() {
+        let chunk = json!({
+            "id": "1", "object": "chat.completion.chunk", "created": 0, "model": "m",
+            "choices": [{"index": 0, "delta": {"content": "ok"}, "finish_reason": "stop"}]
+        });
+        let body = format!("data: {}\n\ndata: [DONE]\n\n", chunk);
+        let server = MockServer::start(vec![(200, "text/event-stream", body)]);
+        let provider = OpenAiProvider::new(&test_config(&server.url()));
+        let mut data = call_data(Vec::new());
+        data.sampling = crate::config::Sampling {
+            top_p: Some(0.5),
+            seed: Some(42),
+            max_tokens: Some(300),
+            stop: vec!["END".into()],
+            reasoning_effort: Some(crate::config::ReasoningEffort::High),
+            parallel_tool_calls: Some(false),
+            ..Default::default()
+        };
+
+        let mut stream = provider.chat_stream(&data).await.unwrap();
+        let mut acc = StreamAccumulator::default();
+        while let Some(event) = stream.next().await {
+            acc.push(&event.unwrap());
+        }
+        assert_eq!(acc.content, "ok");
+
+        let request = server.requests().remove(0);
+        assert_eq!(request.header("authorization").as_deref(), Some("Bearer test-key"));
+        assert_eq!(request.body["reasoning_effort"], "high");
+        assert_eq!(request.body["top_p"], 0.5);
+        assert_eq!(request.body["seed"], 42);
+        assert_eq!(request.body["max_completion_tokens"], 300);
+        assert!(request.body.get("max_tokens").is_none());
+        assert_eq!(request.body["stop"], json!(["END"]));
+        assert!(request.body.get("temperature").is_none());
+        // Only sent along with tools.
+        assert!(request.body.get("parallel_tool_calls").is_none());
+    }
+}
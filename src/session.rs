@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A saved conversation. Each session is stored as one JSON file named `<id>.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub model: String,
+    pub sandbox_root: String,
+    pub history: Vec<Value>,
+}
+
+impl Session {
+    pub fn new(model: &str) -> Self {
+        let now = Local::now().to_rfc3339();
+        Session {
+            id: new_session_id(),
+            created_at: now.clone(),
+            updated_at: now,
+            model: model.to_string(),
+            sandbox_root: crate::sandbox::get_sandbox_root().clone(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Returns the first user message, used as a short title in session listings.
+    pub fn title(&self) -> String {
+        self.history
+            .iter()
+            .find(|msg| msg.get("role").and_then(|r| r.as_str()) == Some("user"))
+            .and_then(|msg| msg.get("content")?.as_str())
+            .map(|s| s.lines().next().unwrap_or("").to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Generates a sortable, human-readable session id (e.g. `20250101-120000-1a2b`).
+pub fn new_session_id() -> String {
+    let now = Local::now();
+    format!(
+        "{}-{:04x}",
+        now.format("%Y%m%d-%H%M%S"),
+        now.timestamp_subsec_nanos() & 0xffff
+    )
+}
+
+fn validate_session_id(id: &str) -> Result<()> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!("Invalid session id '{}'", id));
+    }
+    Ok(())
+}
+
+/// Creates `dir` readable only by the user, since sessions hold whole transcripts,
+/// and tightens it if an earlier version created it world-readable.
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)
+}
+
+/// Writes `data` to a new file at `path` that only the user can read.
+fn write_private_file(path: &Path, data: &str) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(data.as_bytes())
+}
+
+/// On-disk store for chat sessions.
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: PathBuf) -> Self {
+        SessionStore { dir }
+    }
+
+    /// Opens the default store under the user's data directory
+    /// (e.g. `~/.local/share/ai-cli/sessions/` on Linux), creating it if needed.
+    pub fn open_default() -> Result<Self> {
+        let data_dir = dirs::data_dir().context("Could not determine data directory")?;
+        let store = SessionStore::new(data_dir.join("ai-cli").join("sessions"));
+        create_private_dir(&store.dir)
+            .with_context(|| format!("Failed to create session directory {}", store.dir.display()))?;
+        Ok(store)
+    }
+
+    fn path_for(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Writes the session atomically (temp file + rename) so a crash never leaves a half-written file.
+    pub fn save(&self, session: &Session) -> Result<()> {
+        validate_session_id(&session.id)?;
+        create_private_dir(&self.dir)?;
+        let path = self.path_for(&session.id);
+        let tmp_path = path.with_extension("json.tmp");
+        let data = serde_json::to_string_pretty(session)?;
+        // The temp file may be left over from a crash with looser permissions.
+        let _ = fs::remove_file(&tmp_path);
+        write_private_file(&tmp_path, &data)
+            .with_context(|| format!("Failed to write session {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to save session {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn load(&self, id: &str) -> Result<Session> {
+        validate_session_id(id)?;
+        let path = self.path_for(id);
+        let data = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read session '{}': {}", id, e))?;
+        serde_json::from_str(&data).with_context(|| format!("Session '{}' is corrupted", id))
+    }
+
+    /// Lists all sessions, most recently updated first.
+    pub fn list(&self) -> Result<Vec<Session>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut sessions: Vec<Session> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .filter_map(|data| serde_json::from_str::<Session>(&data).ok())
+            .collect();
+        sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then(b.id.cmp(&a.id)));
+        Ok(sessions)
+    }
+
+    /// Returns the most recently updated session started in `sandbox_root`, if any.
+    pub fn latest(&self, sandbox_root: &str) -> Result<Option<Session>> {
+        Ok(self.list()?.into_iter().find(|s| s.sandbox_root == sandbox_root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_store(name: &str) -> SessionStore {
+        let dir = std::env::temp_dir().join(format!("ai-cli-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        SessionStore::new(dir)
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let store = temp_store("roundtrip");
+        let mut session = Session::new("gpt-4o-mini");
+        session.history.push(json!({"role": "user", "content": "hello"}));
+        store.save(&session).unwrap();
+
+        let loaded = store.load(&session.id).unwrap();
+        assert_eq!(loaded.id, session.id);
+        assert_eq!(loaded.history, session.history);
+        assert_eq!(loaded.title(), "hello");
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+
+    #[test]
+    fn test_latest_returns_most_recent() {
+        let store = temp_store("latest");
+        let mut older = Session::new("m");
+        older.id = "20240101-000000-0000".into();
+        older.updated_at = "2024-01-01T00:00:00+00:00".into();
+        let mut newer = Session::new("m");
+        newer.id = "20240102-000000-0000".into();
+        newer.updated_at = "2024-01-02T00:00:00+00:00".into();
+        let mut elsewhere = Session::new("m");
+        elsewhere.id = "20240103-000000-0000".into();
+        elsewhere.updated_at = "2024-01-03T00:00:00+00:00".into();
+        elsewhere.sandbox_root = "/some/other/project".into();
+        store.save(&older).unwrap();
+        store.save(&newer).unwrap();
+        store.save(&elsewhere).unwrap();
+
+        assert_eq!(store.latest(&newer.sandbox_root).unwrap().unwrap().id, newer.id);
+        assert_eq!(store.latest("/some/other/project").unwrap().unwrap().id, elsewhere.id);
+        assert!(store.latest("/nowhere").unwrap().is_none());
+        assert_eq!(store.list().unwrap().len(), 3);
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sessions_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let store = temp_store("private");
+        fs::create_dir_all(&store.dir).unwrap();
+        fs::set_permissions(&store.dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let session = Session::new("m");
+        store.save(&session).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&store.dir), 0o700);
+        assert_eq!(mode(&store.path_for(&session.id)), 0o600);
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+
+    #[test]
+    fn test_list_missing_directory_is_empty() {
+        let store = temp_store("missing");
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.latest("/").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_rejects_path_traversal() {
+        let store = temp_store("traversal");
+        assert!(store.load("../../etc/passwd").is_err());
+        assert!(store.load("").is_err());
+    }
+}
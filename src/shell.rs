@@ -1,4 +1,4 @@
-use crate::command::execute_command;
+use crate::command::{execute_command, CommandOptions};
 use anyhow::Result;
 use colored::{Color, Colorize};
 use rustyline::DefaultEditor;
@@ -95,7 +95,7 @@ fn detect_unix_shell() -> String {
     }
 }
 
-pub async fn interactive_shell(debug: bool) -> Result<String> {
+pub async fn interactive_shell(options: &CommandOptions, debug: bool) -> Result<String> {
     println!(
         "{}",
         "Entering interactive shell mode. Type 'exit' to return.".color(Color::Cyan)
@@ -112,7 +112,7 @@ pub async fn interactive_shell(debug: bool) -> Result<String> {
                     break;
                 }
                 rl.add_history_entry(input).ok();
-                let output = execute_command(input, debug).await.unwrap_or_else(|e| e.to_string());
+                let output = execute_command(input, options, debug).await.unwrap_or_else(|e| e.to_string());
                 println!("{}", output.color(Color::Magenta));
                 accumulated_output.push_str(&format!("Command: {}\nOutput: {}\n\n", input, output));
             }
@@ -0,0 +1,343 @@
+//! A long-lived sandboxed shell on a pseudo-terminal. Unlike `execute_command`,
+//! which starts a fresh shell per call, `cd`, exported variables and activated
+//! virtualenvs carry over between calls. One session is shared per conversation.
+
+use anyhow::{anyhow, Result};
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use regex::Regex;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+use tokio::time::Duration;
+
+use crate::command::CommandObserver;
+use crate::config::Config;
+use crate::sandbox::{get_sandbox_policy, get_sandbox_root};
+
+/// Prompt set in the session so a finished command can be recognised.
+const PROMPT: &str = "aicli$ ";
+
+/// Output that stays quiet this long counts as complete, even without a prompt.
+const QUIET_PERIOD: Duration = Duration::from_millis(1000);
+
+/// How often a read checks for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Default seconds a read waits for output.
+pub const DEFAULT_WAIT_SECS: u64 = 10;
+
+static ANSI_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(\x07|\x1b\\)|\x1b[()][0-9A-Za-z]|\r").unwrap()
+});
+
+static SESSION: Mutex<Option<Arc<ShellSession>>> = Mutex::new(None);
+
+/// Output buffered since the last read, keeping the most recent `limit` bytes.
+#[derive(Debug)]
+struct Buffered {
+    bytes: Vec<u8>,
+    dropped: usize,
+    limit: usize,
+    last_activity: Instant,
+    exited: bool,
+    /// Receives output lines as they arrive while a read waits for them
+    observer: Option<Arc<dyn CommandObserver>>,
+    /// Output after the last newline passed to `observer`
+    partial_line: Vec<u8>,
+}
+
+impl Buffered {
+    /// Passes the complete lines in `chunk` to the observer, if any, keeping the rest
+    /// until its line ends.
+    fn forward(&mut self, chunk: &[u8]) {
+        let Some(observer) = &self.observer else {
+            return;
+        };
+        self.partial_line.extend_from_slice(chunk);
+        while let Some(end) = self.partial_line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial_line.drain(..=end).collect();
+            observer.line(ANSI_RE.replace_all(&String::from_utf8_lossy(&line), "").trim_end_matches('\n'));
+        }
+    }
+
+    /// Starts or stops forwarding output. Stopping passes on an unfinished line unless
+    /// it is just the prompt.
+    fn watch(&mut self, observer: Option<Arc<dyn CommandObserver>>) {
+        if let Some(old) = self.observer.take() {
+            let rest = ANSI_RE.replace_all(&String::from_utf8_lossy(&self.partial_line), "").to_string();
+            if !rest.is_empty() && rest != PROMPT {
+                old.line(&rest);
+            }
+        }
+        self.partial_line.clear();
+        self.observer = observer;
+        let pending = self.bytes.clone();
+        self.forward(&pending);
+    }
+}
+
+pub struct ShellSession {
+    writer: Mutex<Box<dyn Write + Send>>,
+    child: Mutex<Box<dyn Child + Send + Sync>>,
+    // Dropping the master hangs up the terminal.
+    _master: Mutex<Box<dyn MasterPty + Send>>,
+    output: Arc<(Mutex<Buffered>, Notify)>,
+}
+
+impl ShellSession {
+    /// Starts a shell inside the command sandbox (bubblewrap on Linux).
+    fn start(config: &Config) -> Result<ShellSession> {
+        let root = get_sandbox_root();
+        let policy = get_sandbox_policy();
+
+        #[cfg(target_os = "linux")]
+        let mut cmd = {
+            let mut cmd = CommandBuilder::new("bwrap");
+            cmd.args(policy.bwrap_args(root, "exec /bin/sh -i"));
+            cmd
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let mut cmd = {
+            let mut cmd = CommandBuilder::new("/bin/sh");
+            cmd.arg("-i");
+            cmd
+        };
+
+        cmd.cwd(root);
+        cmd.env_clear();
+        for (key, value) in policy.filter_env(std::env::vars()) {
+            cmd.env(key, value);
+        }
+        Self::spawn(cmd, config.command_max_output_bytes)
+    }
+
+    fn spawn(mut cmd: CommandBuilder, max_output_bytes: usize) -> Result<ShellSession> {
+        cmd.env("PS1", PROMPT);
+        cmd.env("TERM", "dumb");
+        let pair = native_pty_system().openpty(PtySize { rows: 50, cols: 200, pixel_width: 0, pixel_height: 0 })?;
+        let child = pair.slave.spawn_command(cmd)?;
+        drop(pair.slave);
+        let mut reader = pair.master.try_clone_reader()?;
+        let writer = pair.master.take_writer()?;
+
+        let output = Arc::new((
+            Mutex::new(Buffered {
+                bytes: Vec::new(),
+                dropped: 0,
+                limit: max_output_bytes,
+                last_activity: Instant::now(),
+                exited: false,
+                observer: None,
+                partial_line: Vec::new(),
+            }),
+            Notify::new(),
+        ));
+        let shared = output.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                let n = reader.read(&mut buf).unwrap_or(0);
+                let mut out = shared.0.lock().unwrap();
+                if n == 0 {
+                    out.exited = true;
+                } else {
+                    out.bytes.extend_from_slice(&buf[..n]);
+                    out.forward(&buf[..n]);
+                    if out.bytes.len() > out.limit {
+                        let excess = out.bytes.len() - out.limit;
+                        out.bytes.drain(..excess);
+                        out.dropped += excess;
+                    }
+                    out.last_activity = Instant::now();
+                }
+                let exited = out.exited;
+                drop(out);
+                shared.1.notify_one();
+                if exited {
+                    break;
+                }
+            }
+        });
+
+        Ok(ShellSession {
+            writer: Mutex::new(writer),
+            child: Mutex::new(child),
+            _master: Mutex::new(pair.master),
+            output,
+        })
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.output.0.lock().unwrap().exited
+    }
+
+    /// Writes `input` to the shell, followed by a newline unless it is a single
+    /// control character such as Ctrl+C (`\u0003`).
+    pub fn send(&self, input: &str) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(input.as_bytes())?;
+        let is_control = input.chars().count() == 1 && input.chars().all(|c| c.is_control());
+        if !is_control && !input.ends_with('\n') {
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Waits up to `wait` for output to settle (the prompt comes back, or it goes
+    /// quiet) and returns everything buffered since the last read. `observer` gets the
+    /// output line by line as it arrives. Setting `cancel` interrupts the foreground
+    /// program with Ctrl+C.
+    pub async fn read(&self, wait: Duration, cancel: Option<&AtomicBool>, observer: Option<Arc<dyn CommandObserver>>) -> String {
+        self.output.0.lock().unwrap().watch(observer);
+        let deadline = Instant::now() + wait;
+        let mut interrupted = false;
+        loop {
+            {
+                let out = self.output.0.lock().unwrap();
+                let idle = out.last_activity.elapsed();
+                let at_prompt = out.bytes.ends_with(PROMPT.as_bytes());
+                if out.exited || (!out.bytes.is_empty() && (at_prompt || idle >= QUIET_PERIOD)) {
+                    break;
+                }
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            if !interrupted && cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+                let _ = self.send("\u{3}");
+                interrupted = true;
+            }
+            let _ = tokio::time::timeout(POLL_INTERVAL, self.output.1.notified()).await;
+        }
+        self.output.0.lock().unwrap().watch(None);
+        self.take_output()
+    }
+
+    fn take_output(&self) -> String {
+        let mut out = self.output.0.lock().unwrap();
+        let bytes = std::mem::take(&mut out.bytes);
+        let dropped = std::mem::take(&mut out.dropped);
+        let mut text = ANSI_RE.replace_all(&String::from_utf8_lossy(&bytes), "").to_string();
+        if dropped > 0 {
+            text = format!("[... {} earlier bytes dropped ...]\n{}", dropped, text);
+        }
+        if text.trim().is_empty() {
+            text = "(no output)".to_string();
+        }
+        if out.exited {
+            text.push_str("\n[shell exited; the next call starts a new session]");
+        } else if !text.ends_with(PROMPT) {
+            text.push_str("\n[no shell prompt yet: the command may still be running; call shell_session again to read more]");
+        }
+        text
+    }
+
+    fn kill(&self) {
+        let _ = self.child.lock().unwrap().kill();
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// The conversation's shell session, started on first use or after the shell exited.
+pub fn shared_session(config: &Config) -> Result<Arc<ShellSession>> {
+    let mut slot = SESSION.lock().unwrap();
+    if let Some(session) = slot.as_ref().filter(|s| !s.has_exited()) {
+        return Ok(session.clone());
+    }
+    let session = Arc::new(ShellSession::start(config).map_err(|e| anyhow!("Failed to start shell session: {}", e))?);
+    *slot = Some(session.clone());
+    Ok(session)
+}
+
+/// Kills the shared session, if any. Returns whether one was running.
+pub fn close_shared_session() -> bool {
+    let session = SESSION.lock().unwrap().take();
+    if let Some(session) = &session {
+        session.kill();
+    }
+    session.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell() -> ShellSession {
+        let mut cmd = CommandBuilder::new("/bin/sh");
+        cmd.arg("-i");
+        cmd.cwd(std::env::temp_dir());
+        ShellSession::spawn(cmd, 4096).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_state_persists_between_inputs() {
+        let session = shell();
+        session.read(Duration::from_secs(5), None, None).await;
+        session.send("cd /; export GREETING=hello").unwrap();
+        session.read(Duration::from_secs(5), None, None).await;
+        session.send("echo $GREETING from $(pwd)").unwrap();
+        let output = session.read(Duration::from_secs(5), None, None).await;
+        assert!(output.contains("hello from /"), "{}", output);
+        assert!(output.ends_with(PROMPT), "{}", output);
+
+        session.send("exit").unwrap();
+        let output = session.read(Duration::from_secs(5), None, None).await;
+        assert!(output.contains("[shell exited"), "{}", output);
+        assert!(session.has_exited());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_interrupts_foreground_program() {
+        let session = shell();
+        session.read(Duration::from_secs(5), None, None).await;
+        session.send("sleep 30; echo done-$((1 + 1))").unwrap();
+        let cancel = AtomicBool::new(true);
+        let start = Instant::now();
+        let output = session.read(Duration::from_secs(20), Some(&cancel), None).await;
+        assert!(start.elapsed() < Duration::from_secs(15));
+        assert!(!output.contains("done-2"), "{}", output);
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl CommandObserver for Recorder {
+        fn started(&self, _command: &str) {}
+        fn line(&self, line: &str) {
+            self.0.lock().unwrap().push(line.to_string());
+        }
+        fn finished(&self, _status: &str, _success: bool) {}
+    }
+
+    #[tokio::test]
+    async fn test_observer_sees_output_while_read_waits() {
+        let session = shell();
+        session.read(Duration::from_secs(5), None, None).await;
+        let recorder = Arc::new(Recorder::default());
+        session.send("echo one-$((0 + 1)); sleep 0.6; echo two-$((1 + 1))").unwrap();
+        let (output, early) = tokio::join!(session.read(Duration::from_secs(5), None, Some(recorder.clone())), async {
+            tokio::time::sleep(Duration::from_millis(400)).await;
+            recorder.0.lock().unwrap().clone()
+        });
+        assert!(early.iter().any(|l| l == "one-1"), "{:?}", early);
+        assert!(output.contains("two-2"), "{}", output);
+        let lines = recorder.0.lock().unwrap();
+        assert!(lines.iter().any(|l| l == "two-2"), "{:?}", lines);
+        assert!(!lines.iter().any(|l| l == PROMPT), "{:?}", lines);
+    }
+
+    #[test]
+    fn test_ansi_sequences_are_stripped() {
+        let raw = "\x1b[1;32mok\x1b[0m\r\n\x1b]0;title\x07done";
+        assert_eq!(ANSI_RE.replace_all(raw, ""), "ok\ndone");
+    }
+}
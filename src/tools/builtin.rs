@@ -0,0 +1,605 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::registry::{ConfirmRequest, Scope, Tool, ToolContext};
+use crate::command::{CommandOptions, MAX_TIMEOUT_SECS};
+use crate::command_rules::command_prefix;
+use crate::shell_session::{close_shared_session, shared_session, DEFAULT_WAIT_SECS};
+use crate::utils::{get_opt_bool, get_opt_str};
+
+/// The tools shipped with ai-cli, in the order their schemas are sent to the model.
+pub(crate) fn builtin_tools() -> Vec<Arc<dyn Tool>> {
+    vec![
+        Arc::new(SearchOnline),
+        Arc::new(ExecuteCommand),
+        Arc::new(ShellSession),
+        Arc::new(JobStatus),
+        Arc::new(JobOutput),
+        Arc::new(JobKill),
+        Arc::new(SendEmail),
+        Arc::new(AlphaVantageQuery),
+        Arc::new(ScrapeUrl),
+        Arc::new(FileEditor),
+    ]
+}
+
+fn required_str<'a>(args: &'a Value, key: &str) -> Result<&'a str> {
+    args.get(key)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("Missing '{}' parameter", key))
+}
+
+struct SearchOnline;
+
+#[async_trait]
+impl Tool for SearchOnline {
+    fn name(&self) -> &str {
+        "search_online"
+    }
+
+    fn description(&self) -> &str {
+        "Search the web for a query and return a synthesized answer. Use for factual lookups, current events, or research. Defaults to concise summaries for speed."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {
+                    "type": "string",
+                    "description": "The search query"
+                },
+                "include_results": {
+                    "type": "boolean",
+                    "description": "Whether to include a list of search results (default: false). Set to true only if you need to review sources directly, e.g., for verification or multiple options.",
+                    "default": false
+                },
+                "answer_mode": {
+                    "type": "string",
+                    "enum": ["basic", "full"],
+                    "description": "Answer detail level. 'basic' (default): Quick summary in 3 sentences, ideal for straightforward queries. 'full': Comprehensive answer with all available details, best for in-depth research or ambiguous topics.",
+                    "default": "basic"
+                }
+            },
+            "required": ["query"]
+        })
+    }
+
+    fn is_concurrent(&self, _args: &Value) -> bool {
+        true
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        get_opt_str(args, "query", "")
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        let query = required_str(args, "query")?;
+        let include_results = get_opt_bool(args, "include_results", false);
+        let answer_mode = get_opt_str(args, "answer_mode", "basic");
+        Ok(crate::search::search_online(query, &ctx.config.tavily_api_key, include_results, &answer_mode, ctx.debug).await)
+    }
+}
+
+struct ExecuteCommand;
+
+#[async_trait]
+impl Tool for ExecuteCommand {
+    fn name(&self) -> &str {
+        "execute_command"
+    }
+
+    fn description(&self) -> &str {
+        "Execute a system command. Use this for any shell task. The result ends with the exit code; long output is cut from the middle, and commands that run past the timeout are killed. Set background to true for dev servers, watchers or long test runs: the call returns a job id at once, and job_status, job_output and job_kill manage the job."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": {"type": "string"},
+                "timeout_secs": {
+                    "type": "integer",
+                    "description": "Seconds before the command is killed (default: the configured limit, at most 3600; background jobs have no limit unless set). Raise it for long builds or test runs."
+                },
+                "background": {
+                    "type": "boolean",
+                    "description": "Run as a background job and return its id immediately (default: false).",
+                    "default": false
+                }
+            },
+            "required": ["command"]
+        })
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["commands"]
+    }
+
+    fn approval(&self, args: &Value) -> Option<ConfirmRequest> {
+        let command = args.get("command").and_then(|c| c.as_str())?;
+        Some(ConfirmRequest {
+            prompt: format!("Execute command: {}?", command),
+            preview: None,
+            scope: command_prefix(command).map(Scope::CommandPrefix),
+        })
+    }
+
+    fn shell_command<'a>(&self, args: &'a Value) -> Option<&'a str> {
+        args.get("command").and_then(|c| c.as_str())
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        get_opt_str(args, "command", "")
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        let command = required_str(args, "command")?;
+        let timeout_secs = args.get("timeout_secs").and_then(|t| t.as_u64());
+        if get_opt_bool(args, "background", false) {
+            let mut options = CommandOptions::from_config(&ctx.config);
+            options.timeout = Duration::MAX;
+            let job = crate::jobs::start_job(command, options.with_timeout_secs(timeout_secs), ctx.debug)?;
+            return Ok(format!(
+                "Started background job {}: {}\nUse job_output with job_id {} to read its output, job_status to check on it and job_kill to stop it.",
+                job.id, command, job.id
+            ));
+        }
+        let options = CommandOptions::from_config(&ctx.config)
+            .with_timeout_secs(timeout_secs)
+            .with_cancel(ctx.cancel.clone())
+            .with_observer(ctx.command_observer.clone());
+        crate::command::execute_command(command, &options, ctx.debug).await
+    }
+}
+
+fn required_job(args: &Value) -> Result<Arc<crate::jobs::Job>> {
+    let id = args
+        .get("job_id")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow!("Missing 'job_id' parameter"))?;
+    u32::try_from(id)
+        .ok()
+        .and_then(crate::jobs::find_job)
+        .ok_or_else(|| anyhow!("No background job {}", id))
+}
+
+fn job_id_schema() -> Value {
+    json!({"type": "integer", "description": "The id returned when the job was started"})
+}
+
+struct JobStatus;
+
+#[async_trait]
+impl Tool for JobStatus {
+    fn name(&self) -> &str {
+        "job_status"
+    }
+
+    fn description(&self) -> &str {
+        "Show whether a background job is still running, its exit status and how many bytes of output it has produced. Without job_id, lists all jobs."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {"job_id": job_id_schema()}
+        })
+    }
+
+    fn is_concurrent(&self, _args: &Value) -> bool {
+        true
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        args.get("job_id").map(|id| id.to_string()).unwrap_or_default()
+    }
+
+    async fn execute(&self, args: &Value, _ctx: &ToolContext) -> Result<String> {
+        if args.get("job_id").is_some() {
+            return Ok(required_job(args)?.summary().describe());
+        }
+        let jobs = crate::jobs::list_jobs();
+        if jobs.is_empty() {
+            return Ok("No background jobs.".to_string());
+        }
+        Ok(jobs.iter().map(|j| j.describe()).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+struct JobOutput;
+
+#[async_trait]
+impl Tool for JobOutput {
+    fn name(&self) -> &str {
+        "job_output"
+    }
+
+    fn description(&self) -> &str {
+        "Read a background job's combined stdout and stderr from a byte offset. The result ends with the offset to pass next time, so each call only returns new output."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "job_id": job_id_schema(),
+                "offset": {
+                    "type": "integer",
+                    "description": "Byte offset to read from (default: 0, the start of the output)",
+                    "default": 0
+                }
+            },
+            "required": ["job_id"]
+        })
+    }
+
+    fn is_concurrent(&self, _args: &Value) -> bool {
+        true
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        args.get("job_id").map(|id| id.to_string()).unwrap_or_default()
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        let job = required_job(args)?;
+        let offset = args.get("offset").and_then(|o| o.as_u64()).unwrap_or(0) as usize;
+        let (output, next) = job.output_since(offset, ctx.config.command_max_output_bytes);
+        let summary = job.summary();
+        let state = summary.status.as_deref().unwrap_or("running");
+        let more = if next < summary.output_bytes { "; more output is available" } else { "" };
+        let output = if output.is_empty() { "(no new output)\n".to_string() } else { output };
+        Ok(format!("{}[job {} {}; next offset {}{}]", output, job.id, state, next, more))
+    }
+}
+
+struct JobKill;
+
+#[async_trait]
+impl Tool for JobKill {
+    fn name(&self) -> &str {
+        "job_kill"
+    }
+
+    fn description(&self) -> &str {
+        "Stop a background job and everything it started."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {"job_id": job_id_schema()},
+            "required": ["job_id"]
+        })
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        args.get("job_id").map(|id| id.to_string()).unwrap_or_default()
+    }
+
+    async fn execute(&self, args: &Value, _ctx: &ToolContext) -> Result<String> {
+        let job = required_job(args)?;
+        if !job.summary().is_running() {
+            return Ok(format!("Job {} had already ended: {}", job.id, job.summary().describe()));
+        }
+        Ok(crate::jobs::kill_job(&job).await.describe())
+    }
+}
+
+struct ShellSession;
+
+#[async_trait]
+impl Tool for ShellSession {
+    fn name(&self) -> &str {
+        "shell_session"
+    }
+
+    fn description(&self) -> &str {
+        "Send input to a persistent sandboxed shell and read its output. Unlike execute_command, the working directory, exported variables and activated virtualenvs persist between calls. Omit input to read more output from a command that is still running; send \\u0003 to interrupt it. The shell prompt is 'aicli$ '."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "input": {
+                    "type": "string",
+                    "description": "A command line or program input. A newline is added unless it is a single control character such as \\u0003 (Ctrl+C)."
+                },
+                "wait_secs": {
+                    "type": "integer",
+                    "description": "Seconds to wait for the output to settle (default: 10). Raise it for slow commands."
+                },
+                "close": {
+                    "type": "boolean",
+                    "description": "End the session. The next call starts a fresh shell.",
+                    "default": false
+                }
+            }
+        })
+    }
+
+    fn approval(&self, args: &Value) -> Option<ConfirmRequest> {
+        let input = args.get("input").and_then(|c| c.as_str()).filter(|i| !i.is_empty())?;
+        Some(ConfirmRequest {
+            prompt: format!("Send to shell session: {}?", input.escape_debug()),
+            preview: None,
+            // Earlier input can redefine any command, so approvals cover the whole tool or nothing.
+            scope: None,
+        })
+    }
+
+    fn shell_command<'a>(&self, args: &'a Value) -> Option<&'a str> {
+        args.get("input").and_then(|c| c.as_str())
+    }
+
+    fn trusts_allow_rules(&self) -> bool {
+        false
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        get_opt_str(args, "input", "")
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        if get_opt_bool(args, "close", false) {
+            return Ok(if close_shared_session() {
+                "Shell session closed.".to_string()
+            } else {
+                "No shell session was running.".to_string()
+            });
+        }
+        let session = shared_session(&ctx.config)?;
+        let input = get_opt_str(args, "input", "");
+        if let Some(observer) = &ctx.command_observer {
+            observer.started(&format!("[session] {}", input.escape_debug()));
+        }
+        if !input.is_empty() {
+            session.send(&input)?;
+        }
+        let wait = args.get("wait_secs").and_then(|w| w.as_u64()).unwrap_or(DEFAULT_WAIT_SECS);
+        let wait = Duration::from_secs(wait.clamp(1, MAX_TIMEOUT_SECS));
+        let output = session.read(wait, ctx.cancel.as_deref(), ctx.command_observer.clone()).await;
+        if let Some(observer) = &ctx.command_observer {
+            observer.finished("shell session", true);
+        }
+        Ok(output)
+    }
+}
+
+struct SendEmail;
+
+#[async_trait]
+impl Tool for SendEmail {
+    fn name(&self) -> &str {
+        "send_email"
+    }
+
+    fn description(&self) -> &str {
+        "Sends an email to a fixed address using SMTP."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "subject": {"type": "string", "description": "Email subject line"},
+                "body": {"type": "string", "description": "Email message body"}
+            },
+            "required": ["subject", "body"]
+        })
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["email"]
+    }
+
+    fn approval(&self, args: &Value) -> Option<ConfirmRequest> {
+        let subject = get_opt_str(args, "subject", "unknown");
+        let body_preview = get_opt_str(args, "body", "").chars().take(100).collect::<String>();
+        Some(ConfirmRequest {
+            prompt: format!("Send email? Subject: {}, Body: {}...", subject, body_preview),
+            preview: None,
+            scope: None,
+        })
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        get_opt_str(args, "subject", "")
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        let subject = required_str(args, "subject")?;
+        let body = required_str(args, "body")?;
+        crate::email::send_email(subject, body, &ctx.config, ctx.debug).await
+    }
+}
+
+struct AlphaVantageQuery;
+
+#[async_trait]
+impl Tool for AlphaVantageQuery {
+    fn name(&self) -> &str {
+        "alpha_vantage_query"
+    }
+
+    fn description(&self) -> &str {
+        "Query the Alpha Vantage API for stock/financial data"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "function": {
+                    "type": "string",
+                    "description": "The Alpha Vantage function (e.g., TIME_SERIES_DAILY)"
+                },
+                "symbol": {
+                    "type": "string",
+                    "description": "The stock symbol (e.g., IBM)"
+                },
+                "outputsize": {
+                    "type": "string",
+                    "enum": ["compact", "full"],
+                    "description": "The size of the output data. 'compact' returns the last 100 data points, 'full' returns all available data. Defaults to 'compact'."
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "Maximum number of most recent data points to return (default 5)",
+                    "default": 5
+                }
+            },
+            "required": ["function", "symbol"]
+        })
+    }
+
+    fn is_concurrent(&self, _args: &Value) -> bool {
+        true
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        format!("{} {}", get_opt_str(args, "function", ""), get_opt_str(args, "symbol", ""))
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        let function = required_str(args, "function")?;
+        let symbol = required_str(args, "symbol")?;
+        let outputsize = args.get("outputsize").and_then(|s| s.as_str());
+        let limit = args.get("limit").and_then(|l| l.as_u64()).map(|l| l as usize);
+        crate::alpha_vantage::alpha_vantage_query(function, symbol, &ctx.config.alpha_vantage_api_key, outputsize, limit, ctx.debug).await
+    }
+}
+
+struct ScrapeUrl;
+
+#[async_trait]
+impl Tool for ScrapeUrl {
+    fn name(&self) -> &str {
+        "scrape_url"
+    }
+
+    fn description(&self) -> &str {
+        "Scrapes the content of a single URL"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": {
+                    "type": "string",
+                    "description": "The URL to scrape"
+                },
+                "mode": {
+                    "type": "string",
+                    "enum": ["summarized", "full"],
+                    "default": "summarized",
+                    "description": "Mode: 'summarized' provides a concise summary (default), 'full' returns complete extracted text"
+                }
+            },
+            "required": ["url"]
+        })
+    }
+
+    fn is_concurrent(&self, _args: &Value) -> bool {
+        true
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        get_opt_str(args, "url", "")
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        let url = required_str(args, "url")?;
+        let mode = get_opt_str(args, "mode", "summarized");
+        crate::scrape::scrape_url(url, &mode, ctx.debug).await
+    }
+}
+
+struct FileEditor;
+
+#[async_trait]
+impl Tool for FileEditor {
+    fn name(&self) -> &str {
+        "file_editor"
+    }
+
+    fn description(&self) -> &str {
+        "Edit files in the sandbox with sub-commands: read, write, search, search_and_replace, apply_diff."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "subcommand": {
+                    "type": "string",
+                    "description": "The sub-command to execute: read, write, search, search_and_replace, apply_diff",
+                    "enum": ["read", "write", "search", "search_and_replace", "apply_diff"]
+                },
+                "filename": {
+                    "type": "string",
+                    "description": "The name of the file in the sandbox to operate on"
+                },
+                "data": {
+                    "type": "string",
+                    "description": "Content to write (for write), regex pattern (for search/search_and_replace), or diff content (for apply_diff)"
+                },
+                "replacement": {
+                    "type": "string",
+                    "description": "Replacement text for search_and_replace"
+                }
+            },
+            "required": ["subcommand", "filename"]
+        })
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["files"]
+    }
+
+    fn approval(&self, args: &Value) -> Option<ConfirmRequest> {
+        let subcommand = args.get("subcommand").and_then(|s| s.as_str())?;
+        let filename = args.get("filename").and_then(|f| f.as_str())?;
+        if crate::file_edit::is_read_only(subcommand) {
+            return None;
+        }
+        let data = args.get("data").and_then(|d| d.as_str());
+        let replacement = args.get("replacement").and_then(|r| r.as_str());
+        Some(ConfirmRequest {
+            prompt: format!("File operation: {} on {}?", subcommand, filename),
+            preview: crate::file_edit::preview_change(subcommand, filename, data, replacement),
+            scope: Some(Scope::Path(filename.to_string())),
+        })
+    }
+
+    fn is_concurrent(&self, args: &Value) -> bool {
+        args.get("subcommand")
+            .and_then(|s| s.as_str())
+            .is_some_and(crate::file_edit::is_read_only)
+    }
+
+    fn describe(&self, args: &Value) -> String {
+        format!("{} {}", get_opt_str(args, "subcommand", ""), get_opt_str(args, "filename", ""))
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String> {
+        let (Some(subcommand), Some(filename)) = (
+            args.get("subcommand").and_then(|s| s.as_str()),
+            args.get("filename").and_then(|f| f.as_str()),
+        ) else {
+            return Err(anyhow!("Missing required parameters 'subcommand' or 'filename'"));
+        };
+        let data = args.get("data").and_then(|d| d.as_str());
+        let replacement = args.get("replacement").and_then(|r| r.as_str());
+        // Approval already happened in the registry, so the editor must not prompt again.
+        let (result, _) = crate::file_edit::file_editor(subcommand, filename, data, replacement, true, ctx.debug);
+        Ok(result)
+    }
+}
@@ -0,0 +1,294 @@
+use colored::{Color, Colorize};
+use serde_json::Value;
+use regex::Regex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use termimad::MadSkin;
+use termimad::crossterm::style::Color as TermColor;
+use termimad::crossterm::style::Attribute;
+
+use crate::chat::ChatManager;
+use crate::output::JsonReporter;
+use crate::utils::{confirm_with_always, ConfirmAnswer};
+use std::sync::LazyLock;
+use anyhow::Result;
+use async_trait::async_trait;
+
+mod builtin;
+mod registry;
+mod stream;
+
+pub(crate) use registry::{
+    extract_tool_calls, parse_tool_list, ApprovalMemory, ConfirmRequest, Confirmation, Gate, Tool, ToolContext, ToolPolicy,
+    ToolRegistry, ToolUi,
+};
+pub(crate) use stream::{stream_llm_response, ActiveTools};
+pub use stream::install_interrupt_handler;
+
+static MULTI_NEWLINE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\n{3,}").unwrap()
+});
+
+/// Normalizes LLM output text by removing excessive whitespace and ensuring consistent formatting
+pub(crate) fn normalize_output(text: &str) -> String {
+    let trimmed = text.trim();
+    let normalized_line_endings = trimmed.replace("\r\n", "\n").replace('\r', "\n");
+    let limited_newlines = MULTI_NEWLINE_RE.replace_all(&normalized_line_endings, "\n\n");
+    limited_newlines.trim_end().to_string()
+}
+
+/// Reports a response stream cancelled with Ctrl+C.
+pub fn print_cancelled() {
+    println!("{}", "Response cancelled.".color(Color::Yellow));
+    println!();
+}
+
+/// Adds consistent block spacing with a single blank line
+pub fn add_block_spacing() {
+    println!();
+}
+
+/// Compacts conversation history for the REPL and single-prompt modes and reports the outcome.
+/// Automatic compaction failures are only logged, since the request can still proceed.
+pub async fn compact_and_report(chat_manager: &Arc<Mutex<ChatManager>>, force: bool, debug: bool, quiet: bool) {
+    match crate::chat::compact_history(chat_manager, force, debug).await {
+        Ok(Some(count)) if !quiet => {
+            println!("{}", format!("Compacted {} earlier messages into a summary.", count).color(Color::Cyan));
+            println!();
+        }
+        Ok(None) if force && !quiet => {
+            println!("{}", "Nothing to compact yet.".color(Color::Cyan));
+            println!();
+        }
+        Err(e) if force => crate::utils::print_error(&format!("Compaction failed: {}", e)),
+        Err(e) => crate::utils::log_to_file(debug, &format!("Automatic compaction failed: {}", e)),
+        _ => {}
+    }
+}
+
+/// Lists the provider's models, or pulls one first when `pull` is given (REPL `models` command).
+pub async fn show_models(chat_manager: &Arc<Mutex<ChatManager>>, pull: Option<&str>) {
+    let provider = chat_manager.lock().await.provider();
+    if let Some(name) = pull {
+        let progress = |status: &str| println!("{}", status.color(Color::Cyan));
+        match provider.pull_model(name, &progress).await {
+            Ok(()) => println!("{}", format!("Pulled {}.", name).color(Color::Cyan)),
+            Err(e) => {
+                crate::utils::print_error(&format!("Failed to pull {}: {}", name, e));
+                return;
+            }
+        }
+    }
+    match provider.list_models().await {
+        Ok(models) if models.is_empty() => println!("{}", "No models available.".color(Color::Cyan)),
+        Ok(models) => {
+            for model in models {
+                println!("{}", model);
+            }
+        }
+        Err(e) => crate::utils::print_error(&format!("Failed to list models: {}", e)),
+    }
+    println!();
+}
+
+/// Displays normalized LLM output with Markdown rendering
+pub fn display_llm_output(content: &str) {
+    print!("{}", markdown_skin().term_text(content));
+}
+
+/// The termimad skin used for all Markdown output in the REPL and single-prompt modes.
+pub(crate) fn markdown_skin() -> MadSkin {
+    let mut skin = MadSkin::default();
+    skin.paragraph.set_fg(TermColor::AnsiValue(222)); // Light orange from Ubuntu palette
+    // Configure styles for headers using Ubuntu-inspired colors
+    skin.headers[0].set_fg(TermColor::AnsiValue(202)); // H1: Orange (#ff5f00 ~ Ubuntu orange)
+    skin.headers[1].set_fg(TermColor::AnsiValue(89)); // H2: Aubergine purple (#87005f ~ #772953)
+    skin.headers[2].set_fg(TermColor::AnsiValue(34)); // H3: Green (#00af00 ~ Ubuntu green)
+    skin.headers[3].set_fg(TermColor::AnsiValue(33)); // H4: Blue (#0087ff ~ Ubuntu blue)
+    skin.headers[4].set_fg(TermColor::AnsiValue(201)); // H5: Magenta (#ff00ff ~ Ubuntu magenta)
+    skin.headers[5].set_fg(TermColor::AnsiValue(226)); // H6: Yellow (#ffff00 ~ Ubuntu yellow)
+    // Bold text
+    skin.bold.set_fg(TermColor::AnsiValue(255)); // White
+    skin.bold.add_attr(Attribute::Bold);
+    // Italic text
+    skin.italic.set_fg(TermColor::AnsiValue(93)); // Purple
+    skin.italic.add_attr(Attribute::Italic);
+    // Code blocks
+    skin.code_block.set_bg(TermColor::AnsiValue(0)); // Black
+    skin.code_block.set_fg(TermColor::AnsiValue(255)); // White
+    // Inline code
+    skin.inline_code.set_bg(TermColor::AnsiValue(8)); // Dark grey
+    skin.inline_code.set_fg(TermColor::AnsiValue(255)); // White
+    skin
+}
+
+/// REPL front end for tool dispatch: confirmations on stdin, activity printed inline.
+struct ConsoleToolUi;
+
+#[async_trait]
+impl ToolUi for ConsoleToolUi {
+    async fn confirm(&self, request: &ConfirmRequest) -> Confirmation {
+        if crate::attachments::stdin_consumed() {
+            eprintln!(
+                "{}",
+                format!(
+                    "Refused: {} needs confirmation, but standard input held the prompt. Allow it with --allow-tools (or --allow-commands for shell commands).",
+                    request.prompt
+                )
+                .color(Color::Yellow)
+            );
+            return Confirmation::Reject;
+        }
+        if let Some(preview) = &request.preview {
+            for line in preview.lines() {
+                match line.chars().next() {
+                    Some('-') => println!("{}", line.color(Color::Red)),
+                    Some('+') => println!("{}", line.color(Color::Green)),
+                    _ => println!("{}", line),
+                }
+            }
+        }
+        let scope = request.scope.as_ref().map(|s| s.to_string());
+        match confirm_with_always(&format!("LLM request: {}", request.prompt), scope.as_deref()) {
+            ConfirmAnswer::Always => {
+                println!("{}", "Approved for the rest of the session; /permissions lists and revokes approvals.".color(Color::Cyan));
+                Confirmation::ApproveTool
+            }
+            ConfirmAnswer::AlwaysScoped => {
+                println!(
+                    "{}",
+                    format!("Approved {} for the rest of the session; /permissions lists and revokes approvals.", scope.unwrap_or_default()).color(Color::Cyan)
+                );
+                Confirmation::ApproveScope
+            }
+            ConfirmAnswer::Yes => Confirmation::Approve,
+            ConfirmAnswer::No => Confirmation::Reject,
+        }
+    }
+
+    fn started(&self, tool: &str, summary: &str) {
+        println!("Running {}: {}", tool, summary.color(Color::Magenta));
+    }
+}
+
+/// Front end for JSON output: nobody can answer a prompt and stdout must stay
+/// machine-readable, so calls that need confirmation are refused.
+struct HeadlessToolUi;
+
+#[async_trait]
+impl ToolUi for HeadlessToolUi {
+    async fn confirm(&self, _request: &ConfirmRequest) -> Confirmation {
+        Confirmation::Reject
+    }
+}
+
+pub(crate) async fn process_tool_calls(response: &Value, chat_manager: &Arc<Mutex<ChatManager>>, debug: bool, quiet: bool, policy: &ToolPolicy, reporter: Option<&JsonReporter>) -> Result<()> {
+    let mut current_response = response.clone();
+    let ui: &dyn ToolUi = if reporter.is_some() { &HeadlessToolUi } else { &ConsoleToolUi };
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (registry, ctx) = {
+        let manager = chat_manager.lock().await;
+        (manager.tool_registry(), ToolContext { config: manager.get_config().clone(), debug, cancel: Some(cancel.clone()), command_observer: None })
+    };
+
+    loop {
+        let tool_calls = extract_tool_calls(&current_response);
+        if debug && !tool_calls.is_empty() {
+            crate::utils::log_to_file(debug, &format!("Tool calls found: {:?}", tool_calls));
+        }
+
+        if tool_calls.is_empty() {
+            break;
+        }
+
+        if let Some(reporter) = reporter {
+            for (id, name, args) in &tool_calls {
+                reporter.tool_call(id, name, args);
+            }
+        }
+        let outcomes = {
+            let _interruptible = ActiveTools::register(&cancel);
+            registry.run_all(tool_calls, policy, ui, &ctx).await
+        };
+        let cancelled = cancel.load(Ordering::Relaxed);
+        if let Some(reporter) = reporter {
+            for outcome in &outcomes {
+                reporter.tool_result(&outcome.tool_call_id, &outcome.content, outcome.rejected);
+            }
+        }
+        let rejection_occurred = outcomes.iter().any(|o| o.rejected);
+        let tool_results: Vec<(String, String)> = outcomes
+            .into_iter()
+            .map(|o| (o.tool_call_id, o.content))
+            .collect();
+
+        if !tool_results.is_empty() {
+            // Push tool results to history while locked, extract LLM call data, release lock
+            chat_manager.lock().await.push_tool_results_to_history(&tool_results, debug);
+            if cancelled {
+                match reporter {
+                    Some(reporter) => reporter.error("Cancelled by user"),
+                    None => println!("{}", "Tool calls cancelled.".color(Color::Yellow)),
+                }
+                break;
+            }
+            compact_and_report(chat_manager, false, debug, quiet).await;
+            let llm_data = chat_manager.lock().await.prepare_llm_call();
+            // LLM call WITHOUT holding the mutex
+            let Some(llm_result) = stream_llm_response(&llm_data, quiet, debug, reporter).await? else {
+                match reporter {
+                    Some(reporter) => reporter.error("Cancelled by user"),
+                    None => print_cancelled(),
+                }
+                break;
+            };
+            // Re-acquire lock to update history
+            {
+                let mut manager = chat_manager.lock().await;
+                manager.apply_llm_result(&llm_result);
+            }
+            current_response = llm_result.response;
+            if reporter.is_none() {
+                add_block_spacing();
+            }
+            if rejection_occurred {
+                break;
+            }
+        } else {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_output_trailing_whitespace() {
+        assert_eq!(normalize_output("hello  \n\n"), "hello");
+    }
+
+    #[test]
+    fn test_normalize_output_excessive_newlines() {
+        assert_eq!(normalize_output("hello\n\n\n\nworld"), "hello\n\nworld");
+    }
+
+    #[test]
+    fn test_normalize_output_crlf() {
+        assert_eq!(normalize_output("hello\r\nworld"), "hello\nworld");
+    }
+
+    #[test]
+    fn test_normalize_output_empty() {
+        assert_eq!(normalize_output(""), "");
+    }
+
+    #[test]
+    fn test_normalize_output_preserves_double_newline() {
+        assert_eq!(normalize_output("hello\n\nworld"), "hello\n\nworld");
+    }
+}
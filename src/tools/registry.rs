@@ -0,0 +1,788 @@
+use std::collections::HashSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_openai::types::{ChatCompletionTool, ChatCompletionToolType, FunctionObject};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde_json::{json, Value};
+
+use super::normalize_output;
+use crate::command::CommandObserver;
+use crate::command_rules::RuleDecision;
+use crate::config::Config;
+
+/// Most tool calls of one response that run at the same time.
+pub(crate) const MAX_PARALLEL_TOOLS: usize = 4;
+
+/// A tool the model can call. Implementations describe themselves (name, schema,
+/// approval policy) and run without knowing which front end invoked them.
+#[async_trait]
+pub(crate) trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments object.
+    fn parameters(&self) -> Value;
+
+    /// Short names accepted by `--allow-tools` in addition to `name()`.
+    fn aliases(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// The confirmation to show before running with `args`, or `None` when the call
+    /// is safe to run unattended.
+    fn approval(&self, _args: &Value) -> Option<ConfirmRequest> {
+        None
+    }
+
+    /// Whether this call only reads or fetches data, so it can run alongside other such
+    /// calls. Anything that changes state runs on its own, in the model's order.
+    fn is_concurrent(&self, _args: &Value) -> bool {
+        false
+    }
+
+    /// The shell command line this call would run, checked against `[command_rules]`.
+    fn shell_command<'a>(&self, _args: &'a Value) -> Option<&'a str> {
+        None
+    }
+
+    /// Whether an allow rule may run the command unasked. Not for a persistent shell,
+    /// where an earlier alias, function or `PATH` change decides what `ls` really runs.
+    fn trusts_allow_rules(&self) -> bool {
+        true
+    }
+
+    /// One-line summary of the call for transcripts (the command, query, file, ...).
+    fn describe(&self, args: &Value) -> String;
+
+    /// Runs the tool. The returned text is sent to the model as the tool result.
+    async fn execute(&self, args: &Value, ctx: &ToolContext) -> Result<String>;
+}
+
+/// What the user is asked before a tool call runs.
+#[derive(Debug, Clone)]
+pub(crate) struct ConfirmRequest {
+    pub prompt: String,
+    /// Optional detail shown before the prompt, such as a diff of a file change.
+    pub preview: Option<String>,
+    /// The narrower part of the call an approval can be remembered for, if any.
+    pub scope: Option<Scope>,
+}
+
+/// A command prefix or file path that a remembered approval is limited to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Scope {
+    /// Single commands starting with this program and subcommand, e.g. `cargo test`
+    CommandPrefix(String),
+    Path(String),
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::CommandPrefix(prefix) => write!(f, "`{}` commands", prefix),
+            Scope::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Confirmation {
+    Approve,
+    /// Approve this call and every later call of the same tool in the session.
+    ApproveTool,
+    /// Approve this call and later calls of the tool with the same `Scope`.
+    ApproveScope,
+    Reject,
+}
+
+/// A "yes, always" answer remembered for the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Approval {
+    pub tool: String,
+    /// `None` approves every call of the tool
+    pub scope: Option<Scope>,
+}
+
+impl std::fmt::Display for Approval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.scope {
+            None => write!(f, "{} (every call)", self.tool),
+            Some(scope) => write!(f, "{}: {}", self.tool, scope),
+        }
+    }
+}
+
+/// The approvals remembered in one session, shared by every `ToolPolicy` of a front end
+/// and listed or revoked with `/permissions`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ApprovalMemory(Arc<Mutex<Vec<Approval>>>);
+
+impl ApprovalMemory {
+    pub(crate) fn remember(&self, approval: Approval) {
+        let mut approvals = self.0.lock().unwrap();
+        if !approvals.contains(&approval) {
+            approvals.push(approval);
+        }
+    }
+
+    pub(crate) fn list(&self) -> Vec<Approval> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn covers(&self, tool: &str, scope: Option<&Scope>) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|a| a.tool == tool && (a.scope.is_none() || a.scope.as_ref() == scope))
+    }
+
+    /// Runs `/permissions [revoke <n>|revoke all]` and returns the text to show.
+    pub(crate) fn command(&self, args: &str) -> String {
+        let args = args.trim();
+        if let Some(which) = args.strip_prefix("revoke").map(str::trim) {
+            let mut approvals = self.0.lock().unwrap();
+            if which == "all" {
+                let count = approvals.len();
+                approvals.clear();
+                return format!("Revoked {} remembered approvals.", count);
+            }
+            return match which.parse::<usize>() {
+                Ok(n) if (1..=approvals.len()).contains(&n) => format!("Revoked {}.", approvals.remove(n - 1)),
+                _ => format!("No approval numbered '{}'; /permissions lists them.", which),
+            };
+        }
+        if !args.is_empty() {
+            return "Usage: /permissions, /permissions revoke <number>, /permissions revoke all".to_string();
+        }
+        let approvals = self.list();
+        if approvals.is_empty() {
+            return "No approvals remembered. Answer [a] or [p] at a confirmation to remember one.".to_string();
+        }
+        let lines: Vec<String> = approvals.iter().enumerate().map(|(i, a)| format!("  {}. {}", i + 1, a)).collect();
+        format!(
+            "Remembered approvals:\n{}\nUse /permissions revoke <number> or /permissions revoke all.",
+            lines.join("\n")
+        )
+    }
+}
+
+/// Front-end hooks used while dispatching tool calls: the confirmation callback and
+/// progress notifications. The REPL prompts on stdin; the TUI sends `AppEvent`s.
+#[async_trait]
+pub(crate) trait ToolUi: Send + Sync {
+    async fn confirm(&self, request: &ConfirmRequest) -> Confirmation;
+
+    /// Called right before an approved call runs.
+    fn started(&self, _tool: &str, _summary: &str) {}
+
+    /// Called with the raw result, or an error message, once a call has finished,
+    /// been rejected or failed to resolve.
+    fn finished(&self, _tool: &str, _outcome: &Result<String, String>) {}
+}
+
+/// Decides which calls run without confirmation: tools named (or aliased) in the
+/// allowed set, and calls covered by an approval remembered this session.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ToolPolicy {
+    approvals: ApprovalMemory,
+    allowed_tools: HashSet<String>,
+}
+
+impl ToolPolicy {
+    pub(crate) fn new(approvals: ApprovalMemory, allowed_tools: HashSet<String>) -> Self {
+        ToolPolicy {
+            approvals,
+            allowed_tools,
+        }
+    }
+
+    fn is_auto_approved(&self, tool: &dyn Tool, scope: Option<&Scope>) -> bool {
+        is_named(tool, &self.allowed_tools) || self.approvals.covers(tool.name(), scope)
+    }
+
+    pub(crate) fn approvals(&self) -> &ApprovalMemory {
+        &self.approvals
+    }
+
+    /// This policy with more tools allowed, such as a profile's `auto_approve` list.
+    /// Remembered approvals stay shared with `self`.
+    pub(crate) fn with_allowed(&self, tools: &HashSet<String>) -> ToolPolicy {
+        ToolPolicy {
+            approvals: self.approvals.clone(),
+            allowed_tools: self.allowed_tools.union(tools).cloned().collect(),
+        }
+    }
+
+    /// Tool names (or aliases) that run every call without asking, sorted: the allowed
+    /// set plus tools approved as a whole this session.
+    pub(crate) fn auto_approved_tools(&self) -> Vec<String> {
+        let remembered = self.approvals.list().into_iter().filter(|a| a.scope.is_none()).map(|a| a.tool);
+        let mut tools: Vec<String> = self.allowed_tools.iter().cloned().chain(remembered).collect();
+        tools.sort();
+        tools.dedup();
+        tools
+    }
+
+    /// Remembers the answer to a confirmation for `tool`.
+    fn remember(&self, tool: &str, answer: Confirmation, request: &ConfirmRequest) {
+        let scope = match answer {
+            Confirmation::ApproveTool => None,
+            Confirmation::ApproveScope if request.scope.is_some() => request.scope.clone(),
+            _ => return,
+        };
+        self.approvals.remember(Approval { tool: tool.to_string(), scope });
+    }
+
+    /// Whether a call runs, needs confirmation first or is refused. Command rules
+    /// from `config` come first, then the tool's own approval and the allowed set.
+    pub(crate) fn gate(&self, tool: &dyn Tool, args: &Value, config: &Config) -> Gate {
+        let rule = match tool.shell_command(args) {
+            Some(command) => config.command_rules.check(command),
+            None => RuleDecision::Default,
+        };
+        let Some(request) = tool.approval(args) else {
+            return Gate::Run;
+        };
+        match rule {
+            RuleDecision::Deny(pattern) => Gate::Deny(format!(
+                "Command refused: it matches the deny rule '{}' in [command_rules]. Do not retry it or work around the rule; ask the user if it is needed.",
+                pattern
+            )),
+            RuleDecision::Allow if tool.trusts_allow_rules() => Gate::Run,
+            RuleDecision::Ask(_) => Gate::Confirm(request),
+            _ if self.is_auto_approved(tool, request.scope.as_ref()) => Gate::Run,
+            _ => Gate::Confirm(request),
+        }
+    }
+}
+
+/// What `ToolPolicy::gate` decided for one call.
+#[derive(Debug, Clone)]
+pub(crate) enum Gate {
+    Run,
+    Confirm(ConfirmRequest),
+    /// Refused by a rule; the message is returned to the model as a tool error
+    Deny(String),
+}
+
+/// Splits a comma-separated tool list (`--allow-tools`, profile keys) into lowercased names.
+pub(crate) fn parse_tool_list(list: &str) -> HashSet<String> {
+    list.split(',')
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Whether `names` lists the tool by name or alias. Names are compared lowercased.
+fn is_named(tool: &dyn Tool, names: &HashSet<String>) -> bool {
+    names.contains(&tool.name().to_lowercase()) || tool.aliases().iter().any(|alias| names.contains(*alias))
+}
+
+/// Settings a tool may need while running.
+#[derive(Debug, Clone)]
+pub(crate) struct ToolContext {
+    pub config: Config,
+    pub debug: bool,
+    /// Set to stop long-running tools, e.g. by Ctrl+C in the TUI
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Receives command output live as it is produced
+    pub command_observer: Option<Arc<dyn CommandObserver>>,
+}
+
+/// The result of one dispatched tool call, ready to be pushed to history.
+#[derive(Debug, Clone)]
+pub(crate) struct ToolOutcome {
+    pub tool_call_id: String,
+    pub content: String,
+    pub rejected: bool,
+}
+
+pub(crate) fn tool_result(name: &str, msg: &str) -> String {
+    normalize_output(&format!("[Tool result] {}: {}", name, msg))
+}
+
+pub(crate) fn tool_error(name: &str, err: &str) -> String {
+    normalize_output(&format!("[Tool error] {}: {}", name, err))
+}
+
+/// All tools available to the model. Both front ends dispatch through it, and the
+/// schemas sent with each request are generated from it.
+#[derive(Clone, Default)]
+pub(crate) struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl std::fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.tools.iter().map(|t| t.name())).finish()
+    }
+}
+
+impl ToolRegistry {
+    /// A registry holding the built-in tools.
+    pub(crate) fn builtin() -> Self {
+        let mut registry = ToolRegistry::default();
+        for tool in super::builtin::builtin_tools() {
+            registry.register(tool);
+        }
+        registry
+    }
+
+    /// Adds a tool, replacing any existing tool with the same name.
+    pub(crate) fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.tools.retain(|t| t.name() != tool.name());
+        self.tools.push(tool);
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.tools.iter().find(|t| t.name() == name).cloned()
+    }
+
+    /// A registry with only the tools listed by name or alias in `names`.
+    pub(crate) fn only(&self, names: &HashSet<String>) -> Self {
+        ToolRegistry {
+            tools: self.tools.iter().filter(|t| is_named(t.as_ref(), names)).cloned().collect(),
+        }
+    }
+
+    /// Function definitions for the chat completion request, in registration order.
+    pub(crate) fn schemas(&self) -> Vec<ChatCompletionTool> {
+        self.tools
+            .iter()
+            .map(|tool| ChatCompletionTool {
+                r#type: ChatCompletionToolType::Function,
+                function: FunctionObject {
+                    name: tool.name().to_string(),
+                    description: Some(tool.description().to_string()),
+                    parameters: Some(tool.parameters()),
+                    strict: Some(false),
+                },
+            })
+            .collect()
+    }
+
+    /// Runs one tool call: asks for confirmation unless the policy approves it,
+    /// executes it and formats the result for history.
+    pub(crate) async fn run(
+        &self,
+        tool_call_id: String,
+        name: &str,
+        args: &Value,
+        policy: &ToolPolicy,
+        ui: &dyn ToolUi,
+        ctx: &ToolContext,
+    ) -> ToolOutcome {
+        let Some(tool) = self.get(name) else {
+            let error = format!("Unknown function: {}", name);
+            ui.finished(name, &Err(error.clone()));
+            return ToolOutcome {
+                tool_call_id,
+                content: tool_error("unknown", &error),
+                rejected: false,
+            };
+        };
+
+        match policy.gate(tool.as_ref(), args, &ctx.config) {
+            Gate::Run => {}
+            Gate::Confirm(request) => match ui.confirm(&request).await {
+                Confirmation::Reject => {
+                    ui.finished(name, &Err("User rejected".into()));
+                    return ToolOutcome {
+                        tool_call_id,
+                        content: tool_result(name, crate::file_edit::CANCELLATION_MESSAGE),
+                        rejected: true,
+                    };
+                }
+                answer => policy.remember(tool.name(), answer, &request),
+            },
+            Gate::Deny(error) => {
+                ui.finished(name, &Err(error.clone()));
+                return ToolOutcome {
+                    tool_call_id,
+                    content: tool_error(name, &error),
+                    rejected: false,
+                };
+            }
+        }
+
+        ui.started(name, &tool.describe(args));
+        crate::utils::log_to_file(ctx.debug, &format!("Running tool {} with {}", name, args));
+        match tool.execute(args, ctx).await {
+            Ok(result) => {
+                ui.finished(name, &Ok(result.clone()));
+                ToolOutcome {
+                    tool_call_id,
+                    content: tool_result(name, &result),
+                    rejected: false,
+                }
+            }
+            Err(e) => {
+                ui.finished(name, &Err(e.to_string()));
+                ToolOutcome {
+                    tool_call_id,
+                    content: tool_error(name, &e.to_string()),
+                    rejected: false,
+                }
+            }
+        }
+    }
+
+    /// Concurrent calls never prompt, so confirmations are still asked one at a time.
+    fn runs_concurrently(&self, name: &str, args: &Value) -> bool {
+        self.get(name)
+            .is_some_and(|tool| tool.is_concurrent(args) && tool.approval(args).is_none())
+    }
+
+    /// Runs the tool calls of one model response. Consecutive concurrent calls run
+    /// together (at most `MAX_PARALLEL_TOOLS` at once); every other call runs alone,
+    /// after the calls before it. Outcomes are returned in the original order.
+    pub(crate) async fn run_all(
+        &self,
+        tool_calls: Vec<(String, String, Value)>,
+        policy: &ToolPolicy,
+        ui: &dyn ToolUi,
+        ctx: &ToolContext,
+    ) -> Vec<ToolOutcome> {
+        let mut outcomes = Vec::with_capacity(tool_calls.len());
+        let mut calls = tool_calls.into_iter().peekable();
+        while let Some((tool_call_id, name, args)) = calls.next() {
+            if !self.runs_concurrently(&name, &args) {
+                outcomes.push(self.run(tool_call_id, &name, &args, policy, ui, ctx).await);
+                continue;
+            }
+            let mut batch = vec![(tool_call_id, name, args)];
+            while let Some(next) = calls.next_if(|(_, name, args)| self.runs_concurrently(name, args)) {
+                batch.push(next);
+            }
+            let batch_outcomes: Vec<ToolOutcome> = stream::iter(batch)
+                .map(|(tool_call_id, name, args)| async move {
+                    self.run(tool_call_id, &name, &args, policy, ui, ctx).await
+                })
+                .buffered(MAX_PARALLEL_TOOLS)
+                .collect()
+                .await;
+            outcomes.extend(batch_outcomes);
+        }
+        outcomes
+    }
+}
+
+/// Extracts `(tool_call_id, name, arguments)` from an OpenAI-shaped response.
+/// Calls without an id or name are skipped; unparsable arguments become `{}`.
+pub(crate) fn extract_tool_calls(response: &Value) -> Vec<(String, String, Value)> {
+    response
+        .get("choices")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|choice| choice.get("message")?.get("tool_calls")?.as_array())
+        .flatten()
+        .filter_map(|tc| {
+            let tool_call_id = tc.get("id").and_then(|id| id.as_str()).unwrap_or("").to_string();
+            let func = tc.get("function");
+            let name = func
+                .and_then(|f| f.get("name"))
+                .and_then(|n| n.as_str())
+                .unwrap_or("")
+                .to_string();
+            let args = func
+                .and_then(|f| f.get("arguments"))
+                .and_then(|a| serde_json::from_str::<Value>(a.as_str()?).ok())
+                .unwrap_or(json!({}));
+            if !name.is_empty() && !tool_call_id.is_empty() {
+                Some((tool_call_id, name, args))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_rules::{command_prefix, CommandRules};
+    use crate::config::CommandRulesConfig;
+    use std::sync::atomic::Ordering;
+
+    struct EchoTool;
+
+    #[async_trait]
+    impl Tool for EchoTool {
+        fn name(&self) -> &str {
+            "echo"
+        }
+        fn description(&self) -> &str {
+            "Echoes its input"
+        }
+        fn parameters(&self) -> Value {
+            json!({"type": "object", "properties": {"text": {"type": "string"}}})
+        }
+        fn aliases(&self) -> &[&'static str] {
+            &["say"]
+        }
+        fn approval(&self, args: &Value) -> Option<ConfirmRequest> {
+            Some(ConfirmRequest {
+                prompt: "Echo?".into(),
+                preview: None,
+                scope: args["text"].as_str().and_then(command_prefix).map(Scope::CommandPrefix),
+            })
+        }
+        fn shell_command<'a>(&self, args: &'a Value) -> Option<&'a str> {
+            args["text"].as_str()
+        }
+        fn describe(&self, args: &Value) -> String {
+            args["text"].as_str().unwrap_or("").to_string()
+        }
+        async fn execute(&self, args: &Value, _ctx: &ToolContext) -> Result<String> {
+            match args["text"].as_str() {
+                Some(text) => Ok(text.to_string()),
+                None => Err(anyhow::anyhow!("Missing 'text' parameter")),
+            }
+        }
+    }
+
+    struct ScriptedUi {
+        answer: Confirmation,
+        prompts: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ToolUi for ScriptedUi {
+        async fn confirm(&self, request: &ConfirmRequest) -> Confirmation {
+            self.prompts.lock().unwrap().push(request.prompt.clone());
+            self.answer
+        }
+    }
+
+    fn setup(answer: Confirmation) -> (ToolRegistry, ScriptedUi, ToolContext) {
+        let mut registry = ToolRegistry::default();
+        registry.register(Arc::new(EchoTool));
+        let ui = ScriptedUi {
+            answer,
+            prompts: Mutex::new(Vec::new()),
+        };
+        let ctx = ToolContext {
+            config: Config::default(),
+            debug: false,
+            cancel: None,
+            command_observer: None,
+        };
+        (registry, ui, ctx)
+    }
+
+    #[tokio::test]
+    async fn test_run_asks_and_formats_result() {
+        let (registry, ui, ctx) = setup(Confirmation::Approve);
+        let outcome = registry
+            .run("1".into(), "echo", &json!({"text": "hi"}), &ToolPolicy::default(), &ui, &ctx)
+            .await;
+        assert_eq!(outcome.content, "[Tool result] echo: hi");
+        assert!(!outcome.rejected);
+        assert_eq!(ui.prompts.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_rejected() {
+        let (registry, ui, ctx) = setup(Confirmation::Reject);
+        let outcome = registry
+            .run("1".into(), "echo", &json!({"text": "hi"}), &ToolPolicy::default(), &ui, &ctx)
+            .await;
+        assert!(outcome.rejected);
+        assert!(outcome.content.contains("cancelled"));
+    }
+
+    #[tokio::test]
+    async fn test_command_rules_gate_calls() {
+        let (registry, ui, mut ctx) = setup(Confirmation::Reject);
+        ctx.config.command_rules = CommandRules::from_config(&CommandRulesConfig {
+            allow: Some("hi *".into()),
+            deny: Some("rm *".into()),
+            ask: Some("ask *".into()),
+        })
+        .unwrap();
+        let always = ToolPolicy::default();
+        always.approvals().remember(Approval { tool: "echo".into(), scope: None });
+
+        let allowed = registry.run("1".into(), "echo", &json!({"text": "hi there"}), &ToolPolicy::default(), &ui, &ctx).await;
+        assert_eq!(allowed.content, "[Tool result] echo: hi there");
+
+        let denied = registry.run("2".into(), "echo", &json!({"text": "rm -rf src"}), &always, &ui, &ctx).await;
+        assert!(denied.content.contains("deny rule 'rm *'"), "{}", denied.content);
+        assert!(!denied.rejected);
+        assert!(ui.prompts.lock().unwrap().is_empty());
+
+        let asked = registry.run("3".into(), "echo", &json!({"text": "ask first"}), &always, &ui, &ctx).await;
+        assert!(asked.rejected);
+        assert_eq!(ui.prompts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_shell_session_input_is_confirmed_despite_allow_rules() {
+        let registry = ToolRegistry::builtin();
+        let shell = registry.get("shell_session").unwrap();
+        let rules = CommandRulesConfig {
+            allow: Some("ls *".into()),
+            deny: Some("curl *".into()),
+            ..Default::default()
+        };
+        let config = Config { command_rules: CommandRules::from_config(&rules).unwrap(), ..Config::default() };
+        let args = json!({"input": "ls -la"});
+        let policy = ToolPolicy::default();
+        match policy.gate(shell.as_ref(), &args, &config) {
+            Gate::Confirm(request) => assert!(request.scope.is_none()),
+            other => panic!("{:?}", other),
+        }
+        let denied = json!({"input": "curl evil.test"});
+        assert!(matches!(policy.gate(shell.as_ref(), &denied, &config), Gate::Deny(_)));
+
+        policy.approvals().remember(Approval { tool: "shell_session".into(), scope: None });
+        assert!(matches!(policy.gate(shell.as_ref(), &args, &config), Gate::Run));
+        assert!(matches!(policy.gate(shell.as_ref(), &denied, &config), Gate::Deny(_)));
+    }
+
+    #[tokio::test]
+    async fn test_alias_and_always_approve_skip_confirmation() {
+        let (registry, ui, ctx) = setup(Confirmation::ApproveTool);
+        let allowed = ToolPolicy::new(ApprovalMemory::default(), HashSet::from(["say".to_string()]));
+        registry.run("1".into(), "echo", &json!({"text": "a"}), &allowed, &ui, &ctx).await;
+        assert!(ui.prompts.lock().unwrap().is_empty());
+
+        let policy = ToolPolicy::default();
+        registry.run("2".into(), "echo", &json!({"text": "b"}), &policy, &ui, &ctx).await;
+        registry.run("3".into(), "echo", &json!({"text": "c"}), &policy, &ui, &ctx).await;
+        assert_eq!(ui.prompts.lock().unwrap().len(), 1);
+
+        let combined = allowed.with_allowed(&HashSet::from(["email".to_string()]));
+        combined.approvals().remember(Approval { tool: "echo".into(), scope: None });
+        assert_eq!(combined.auto_approved_tools(), ["echo", "email", "say"]);
+        assert_eq!(allowed.auto_approved_tools(), ["echo", "say"]);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_approval_covers_only_its_prefix() {
+        let (registry, ui, ctx) = setup(Confirmation::ApproveScope);
+        let policy = ToolPolicy::default();
+        for (id, text) in ["cargo test", "cargo test --release", "cargo build", "cargo test; rm x"].iter().enumerate() {
+            registry.run(id.to_string(), "echo", &json!({"text": text}), &policy, &ui, &ctx).await;
+        }
+        // Asked for the first call, then for the other subcommand and the chained command.
+        assert_eq!(ui.prompts.lock().unwrap().len(), 3);
+        let approvals = policy.approvals();
+        assert_eq!(approvals.list().len(), 2);
+        assert!(approvals.command("").contains("1. echo: `cargo test` commands"));
+        assert!(approvals.command("").contains("2. echo: `cargo build` commands"));
+
+        assert_eq!(approvals.command("revoke 1"), "Revoked echo: `cargo test` commands.");
+        assert!(approvals.command("revoke 5").starts_with("No approval numbered"));
+        assert_eq!(approvals.command("revoke all"), "Revoked 1 remembered approvals.");
+        registry.run("9".into(), "echo", &json!({"text": "cargo build"}), &policy, &ui, &ctx).await;
+        assert_eq!(ui.prompts.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tool_and_execution_error() {
+        let (registry, ui, ctx) = setup(Confirmation::Approve);
+        let policy = ToolPolicy::default();
+        let unknown = registry.run("1".into(), "nope", &json!({}), &policy, &ui, &ctx).await;
+        assert_eq!(unknown.content, "[Tool error] unknown: Unknown function: nope");
+        let failed = registry.run("2".into(), "echo", &json!({}), &policy, &ui, &ctx).await;
+        assert_eq!(failed.content, "[Tool error] echo: Missing 'text' parameter");
+    }
+
+    /// Records how many calls are in flight; `write` calls are serialized.
+    struct ProbeTool {
+        in_flight: std::sync::atomic::AtomicUsize,
+        peak: std::sync::atomic::AtomicUsize,
+        writes_seen_peak: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl Tool for ProbeTool {
+        fn name(&self) -> &str {
+            "probe"
+        }
+        fn description(&self) -> &str {
+            "Probe"
+        }
+        fn parameters(&self) -> Value {
+            json!({"type": "object"})
+        }
+        fn is_concurrent(&self, args: &Value) -> bool {
+            args["mode"] == "read"
+        }
+        fn describe(&self, _args: &Value) -> String {
+            String::new()
+        }
+        async fn execute(&self, args: &Value, _ctx: &ToolContext) -> Result<String> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            if args["mode"] == "write" {
+                self.writes_seen_peak.lock().unwrap().push(now);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(args["n"].to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_all_parallelizes_reads_and_keeps_order() {
+        let probe = Arc::new(ProbeTool {
+            in_flight: Default::default(),
+            peak: Default::default(),
+            writes_seen_peak: Mutex::new(Vec::new()),
+        });
+        let mut registry = ToolRegistry::default();
+        registry.register(probe.clone());
+        let (_, ui, ctx) = setup(Confirmation::Approve);
+        let modes = ["read", "read", "read", "read", "read", "read", "write", "read", "read"];
+        let calls = modes
+            .iter()
+            .enumerate()
+            .map(|(n, mode)| (format!("id{}", n), "probe".to_string(), json!({"mode": mode, "n": n})))
+            .collect();
+
+        let outcomes = registry.run_all(calls, &ToolPolicy::default(), &ui, &ctx).await;
+        let ids: Vec<&str> = outcomes.iter().map(|o| o.tool_call_id.as_str()).collect();
+        assert_eq!(ids, ["id0", "id1", "id2", "id3", "id4", "id5", "id6", "id7", "id8"]);
+        assert_eq!(outcomes[8].content, "[Tool result] probe: 8");
+        assert_eq!(probe.peak.load(Ordering::SeqCst), MAX_PARALLEL_TOOLS);
+        assert_eq!(*probe.writes_seen_peak.lock().unwrap(), [1]);
+    }
+
+    #[test]
+    fn test_builtin_schemas_and_extract() {
+        let names: Vec<String> = ToolRegistry::builtin()
+            .schemas()
+            .into_iter()
+            .map(|t| t.function.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["search_online", "execute_command", "shell_session", "job_status", "job_output", "job_kill", "send_email", "alpha_vantage_query", "scrape_url", "file_editor"]
+        );
+
+        let response = json!({"choices": [{"message": {"tool_calls": [
+            {"id": "a", "function": {"name": "echo", "arguments": "{\"text\":\"x\"}"}},
+            {"id": "", "function": {"name": "echo", "arguments": "{}"}}
+        ]}}]});
+        let calls = extract_tool_calls(&response);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].2["text"], "x");
+    }
+
+    #[test]
+    fn test_only_keeps_listed_tools_and_aliases() {
+        let names = parse_tool_list(" Search_Online, files ,,");
+        assert_eq!(names.len(), 2);
+        let registry = ToolRegistry::builtin().only(&names);
+        let kept: Vec<String> = registry.schemas().into_iter().map(|t| t.function.name).collect();
+        assert_eq!(kept, ["search_online", "file_editor"]);
+    }
+}
@@ -0,0 +1,270 @@
+//! Streaming LLM output for the REPL and single-prompt modes.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use spinners::{Spinner, Spinners};
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration};
+
+use crate::chat::{LlmCallData, LlmCallResult};
+use crate::output::JsonReporter;
+use crate::provider::{ApiError, StreamAccumulator, StreamEvent};
+use crate::utils::log_to_file;
+
+/// What Ctrl+C interrupts in the REPL and `-p` modes.
+#[derive(Clone)]
+enum Interruptible {
+    /// The stream currently being rendered
+    Stream(Arc<Notify>),
+    /// The tool calls currently running, through `ToolContext::cancel`
+    Tools(Arc<AtomicBool>),
+}
+
+/// The work Ctrl+C cancels instead of exiting, if any.
+static INTERRUPTIBLE: Mutex<Option<Interruptible>> = Mutex::new(None);
+
+/// Routes Ctrl+C for the REPL and `-p` modes: cancels an in-flight stream or running
+/// tools, otherwise exits as the default handler would. Readline runs in raw mode and
+/// sees Ctrl+C as a key.
+pub fn install_interrupt_handler() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            let active = INTERRUPTIBLE.lock().unwrap().clone();
+            match active {
+                Some(Interruptible::Stream(notify)) => notify.notify_one(),
+                Some(Interruptible::Tools(cancel)) => cancel.store(true, Ordering::Relaxed),
+                None => std::process::exit(130),
+            }
+        }
+    });
+}
+
+/// Registers a stream as cancellable for as long as it is alive.
+struct ActiveStream(Arc<Notify>);
+
+impl ActiveStream {
+    fn register() -> Self {
+        let notify = Arc::new(Notify::new());
+        *INTERRUPTIBLE.lock().unwrap() = Some(Interruptible::Stream(Arc::clone(&notify)));
+        ActiveStream(notify)
+    }
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        *INTERRUPTIBLE.lock().unwrap() = None;
+    }
+}
+
+/// Lets Ctrl+C set `cancel` for as long as it is alive, so running tools stop instead
+/// of the process exiting. `cancel` is cleared on registration.
+pub(crate) struct ActiveTools;
+
+impl ActiveTools {
+    pub(crate) fn register(cancel: &Arc<AtomicBool>) -> Self {
+        cancel.store(false, Ordering::Relaxed);
+        *INTERRUPTIBLE.lock().unwrap() = Some(Interruptible::Tools(Arc::clone(cancel)));
+        ActiveTools
+    }
+}
+
+impl Drop for ActiveTools {
+    fn drop(&mut self) {
+        *INTERRUPTIBLE.lock().unwrap() = None;
+    }
+}
+
+/// Splits streamed Markdown into blocks that can be rendered on their own: text up
+/// to a blank line outside a code fence. termimad needs whole blocks to lay out
+/// paragraphs, lists, tables and code correctly.
+#[derive(Default)]
+pub(crate) struct MarkdownStream {
+    buffer: String,
+}
+
+impl MarkdownStream {
+    /// Adds streamed text and returns the completed blocks, if any.
+    pub(crate) fn push(&mut self, text: &str) -> Option<String> {
+        self.buffer.push_str(text);
+        let mut in_fence = false;
+        let mut split = None;
+        let mut pos = 0;
+        for line in self.buffer.split_inclusive('\n') {
+            if !line.ends_with('\n') {
+                break;
+            }
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+            } else if trimmed.is_empty() && !in_fence {
+                split = Some((pos, pos + line.len()));
+            }
+            pos += line.len();
+        }
+        let (block_end, rest_start) = split?;
+        let block = self.buffer[..block_end].to_string();
+        self.buffer.drain(..rest_start);
+        Some(block).filter(|b| !b.trim().is_empty())
+    }
+
+    /// Returns whatever is left once the stream has ended.
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        Some(std::mem::take(&mut self.buffer)).filter(|b| !b.trim().is_empty())
+    }
+}
+
+/// Opens the stream, retrying on the same errors as `call_llm_api`.
+async fn open_stream(data: &LlmCallData, debug: bool) -> Result<futures::stream::BoxStream<'static, Result<StreamEvent>>> {
+    let mut attempt = 0;
+    loop {
+        log_to_file(debug, &format!("LLM stream attempt {}/3 ({})", attempt + 1, data.provider.name()));
+        match data.provider.chat_stream(data).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                let status = e.downcast_ref::<ApiError>().and_then(|api_err| api_err.status);
+                log_to_file(debug, &format!("LLM stream error (attempt {}/3): {}", attempt + 1, e));
+                if !crate::chat::is_retryable(status) || attempt == 2 {
+                    return Err(match status {
+                        Some(code) => anyhow!("API request failed: {} (HTTP {})", e, code),
+                        None => anyhow!("API request failed: {}", e),
+                    });
+                }
+            }
+        }
+        attempt += 1;
+        sleep(Duration::from_secs(2u64.pow(attempt - 1))).await;
+    }
+}
+
+/// Streams one LLM call, rendering text with the Markdown skin as blocks complete,
+/// or handing tokens to `reporter` for JSON output. Tool-call deltas are accumulated
+/// into the same response shape `call_llm_api` returns. Returns `Ok(None)` when the
+/// user cancelled with Ctrl+C; partial output is then discarded from history.
+pub(crate) async fn stream_llm_response(
+    data: &LlmCallData,
+    quiet: bool,
+    debug: bool,
+    reporter: Option<&JsonReporter>,
+) -> Result<Option<LlmCallResult>> {
+    let active = ActiveStream::register();
+    let mut spinner = (!quiet && reporter.is_none()).then(|| Spinner::new(Spinners::Dots, "".into()));
+    let mut stop_spinner = || {
+        if let Some(mut s) = spinner.take() {
+            s.stop();
+            print!("\r\x1b[2K");
+        }
+    };
+
+    let mut stream = tokio::select! {
+        stream = open_stream(data, debug) => match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                stop_spinner();
+                return Err(e);
+            }
+        },
+        _ = active.0.notified() => {
+            stop_spinner();
+            return Ok(None);
+        }
+    };
+
+    let skin = super::markdown_skin();
+    let mut markdown = MarkdownStream::default();
+    let mut acc = StreamAccumulator::default();
+    let render = |block: &str| {
+        print!("{}", skin.term_text(block));
+        println!();
+        let _ = std::io::stdout().flush();
+    };
+
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => event,
+            _ = active.0.notified() => {
+                stop_spinner();
+                if let Some(rest) = markdown.finish() {
+                    render(&rest);
+                }
+                log_to_file(debug, "LLM stream cancelled by user");
+                return Ok(None);
+            }
+        };
+        let Some(event) = event else { break };
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                stop_spinner();
+                return Err(anyhow!("Stream error: {}", e));
+            }
+        };
+        if let StreamEvent::Token(text) = &event {
+            stop_spinner();
+            if let Some(reporter) = reporter {
+                reporter.token(text);
+            } else if let Some(block) = markdown.push(text) {
+                render(&block);
+            }
+        }
+        acc.push(&event);
+    }
+    stop_spinner();
+    if let Some(rest) = markdown.finish() {
+        render(&rest);
+    }
+
+    log_to_file(
+        debug,
+        &format!("LLM stream completed, {} content chars, {} tool calls", acc.content.len(), acc.tool_call_count()),
+    );
+    if let Some(reporter) = reporter {
+        reporter.answer(&acc.content);
+    }
+    let (message, response) = acc.finish();
+    log_to_file(debug, &format!("LLM Response: {}", crate::utils::truncate_str(&response.to_string(), 500)));
+    if let Some(timings) = crate::provider::timing_summary(&response) {
+        log_to_file(debug, &format!("LLM Timings: {}", timings));
+    }
+    Ok(Some(LlmCallResult {
+        response,
+        new_messages: vec![message],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_stream_splits_at_blank_lines() {
+        let mut md = MarkdownStream::default();
+        assert_eq!(md.push("First para"), None);
+        assert_eq!(md.push("graph.\n"), None);
+        assert_eq!(md.push("\nSecond"), Some("First paragraph.\n".to_string()));
+        assert_eq!(md.finish(), Some("Second".to_string()));
+        assert_eq!(md.finish(), None);
+    }
+
+    #[test]
+    fn test_markdown_stream_keeps_code_fences_whole() {
+        let mut md = MarkdownStream::default();
+        assert_eq!(md.push("```rust\nfn a() {}\n\nfn b() {}\n"), None);
+        let block = md.push("```\n\nafter").unwrap();
+        assert_eq!(block, "```rust\nfn a() {}\n\nfn b() {}\n```\n");
+        assert_eq!(md.finish(), Some("after".to_string()));
+    }
+
+    #[test]
+    fn test_ctrl_c_targets_running_tools_until_they_finish() {
+        let cancel = Arc::new(AtomicBool::new(true));
+        let running = ActiveTools::register(&cancel);
+        assert!(!cancel.load(Ordering::Relaxed));
+        assert!(matches!(&*INTERRUPTIBLE.lock().unwrap(), Some(Interruptible::Tools(c)) if Arc::ptr_eq(c, &cancel)));
+        drop(running);
+        assert!(INTERRUPTIBLE.lock().unwrap().is_none());
+    }
+}
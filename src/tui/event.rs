@@ -4,12 +4,15 @@ use tokio::sync::{Mutex, mpsc};
 use anyhow::Result;
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use serde_json::json;
+use tokio::time::Duration;
 
 use crate::chat::{ChatManager, LlmCallResult};
+use crate::command::CommandObserver;
+use crate::tools::Confirmation;
 
 use super::types::{App, AppEvent, AppState, ChatMessage};
 use super::llm::start_llm_call;
-use super::tools::run_tool_processing;
+use super::tools::{run_tool_processing, TuiCommandObserver};
 
 pub(crate) async fn handle_event(
     app: &mut App,
@@ -52,24 +55,37 @@ pub(crate) async fn handle_event(
             new_messages,
         } => {
             app.finalize_streaming_message(&full_content);
+            if let Some(timing) = full_response.as_ref().and_then(crate::provider::timing_summary) {
+                crate::utils::log_to_file(debug, &format!("LLM Timings: {}", timing));
+                app.last_timing = Some(timing);
+            }
 
-            if !new_messages.is_empty() {
-                let result = LlmCallResult {
-                    response: full_response.clone().unwrap_or(json!({})),
-                    new_messages,
-                };
-                chat_manager.lock().await.apply_llm_result(&result);
+            {
+                let mut manager = chat_manager.lock().await;
+                if !new_messages.is_empty() {
+                    let result = LlmCallResult {
+                        response: full_response.clone().unwrap_or(json!({})),
+                        new_messages,
+                    };
+                    manager.apply_llm_result(&result);
+                } else if let Some(response) = &full_response {
+                    manager.record_usage(response);
+                }
+                app.usage = manager.usage();
+                if let Some(message) = manager.take_save_warning(debug) {
+                    app.add_message(ChatMessage::Error { message });
+                }
             }
 
             if let Some(ref response) = full_response {
-                let tool_calls = crate::tui::tools::extract_tool_calls_from_response(response);
+                let tool_calls = crate::tools::extract_tool_calls(response);
                 if !tool_calls.is_empty() {
                     tokio::spawn(run_tool_processing(
                         chat_manager.clone(),
                         tx.clone(),
                         response.clone(),
                         debug,
-                        app.always_approve.clone(),
+                        app.tool_policy(),
                         app.cancel_stream.clone(),
                     ));
                     app.state = AppState::ProcessingTools;
@@ -81,13 +97,11 @@ pub(crate) async fn handle_event(
             Ok(())
         }
         AppEvent::LlmError(message) => {
-            if let Some(last) = app.messages.last_mut() {
-                if let ChatMessage::Assistant { content, is_streaming } = last {
-                    if *is_streaming {
-                        *is_streaming = false;
-                        if content.is_empty() {
-                            *content = "(error)".to_string();
-                        }
+            if let Some(ChatMessage::Assistant { content, is_streaming }) = app.messages.last_mut() {
+                if *is_streaming {
+                    *is_streaming = false;
+                    if content.is_empty() {
+                        *content = "(error)".to_string();
                     }
                 }
             }
@@ -95,8 +109,8 @@ pub(crate) async fn handle_event(
             app.state = AppState::Idle;
             Ok(())
         }
-        AppEvent::NeedConfirmation { prompt, respond_to } => {
-            app.state = AppState::WaitingConfirmation { prompt, respond_to };
+        AppEvent::NeedConfirmation { prompt, scope, respond_to } => {
+            app.state = AppState::WaitingConfirmation { prompt, scope, respond_to };
             Ok(())
         }
         AppEvent::ToolDone { name, result } => {
@@ -113,14 +127,61 @@ pub(crate) async fn handle_event(
             });
             Ok(())
         }
+        AppEvent::Info(message) => {
+            app.add_message(ChatMessage::Info { message });
+            Ok(())
+        }
+        AppEvent::CommandStarted(command) => {
+            app.command_started(&command);
+            Ok(())
+        }
+        AppEvent::CommandLine(line) => {
+            app.command_line(line);
+            Ok(())
+        }
+        AppEvent::CommandFinished { status, success } => {
+            app.command_finished(status, success);
+            Ok(())
+        }
+        AppEvent::CompactionDone(result) => {
+            app.usage = chat_manager.lock().await.usage();
+            let message = match result {
+                Ok(Some(count)) => format!("Compacted {} earlier messages into a summary.", count),
+                Ok(None) => "Nothing to compact yet.".to_string(),
+                Err(e) => {
+                    app.add_message(ChatMessage::Error {
+                        message: format!("Compaction failed: {}", e),
+                    });
+                    app.state = AppState::Idle;
+                    return Ok(());
+                }
+            };
+            app.add_message(ChatMessage::Info { message });
+            app.state = AppState::Idle;
+            Ok(())
+        }
+        AppEvent::ModelsDone(result) => {
+            match result {
+                Ok(models) if models.is_empty() => app.add_message(ChatMessage::Info {
+                    message: "No models available.".into(),
+                }),
+                Ok(models) => app.add_message(ChatMessage::Info {
+                    message: format!("Available models:\n{}", models.join("\n")),
+                }),
+                Err(e) => app.add_message(ChatMessage::Error { message: e }),
+            }
+            app.state = AppState::Idle;
+            Ok(())
+        }
         AppEvent::ShellCommandDone { command, output } => {
-            app.add_message(ChatMessage::Info {
-                message: format!("Output:\n{}", output),
-            });
             let llm_input = format!("User ran command '!{}' with output: {}", command, output);
             start_llm_call(app, llm_input, chat_manager, tx, debug).await;
             Ok(())
         }
+        AppEvent::ShellSessionDone => {
+            app.state = AppState::Idle;
+            Ok(())
+        }
     }
 }
 
@@ -131,29 +192,36 @@ pub(crate) async fn handle_key_event(
     tx: &mpsc::UnboundedSender<AppEvent>,
     debug: bool,
 ) -> Result<()> {
+    match key.code {
+        KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.toggle_command_output();
+            return Ok(());
+        }
+        KeyCode::PageUp => {
+            app.auto_scroll = false;
+            app.scroll_offset = app.scroll_offset.saturating_sub(10);
+            return Ok(());
+        }
+        KeyCode::PageDown => {
+            app.auto_scroll = false;
+            app.scroll_offset = app.scroll_offset.saturating_add(10);
+            return Ok(());
+        }
+        _ => {}
+    }
+
     match &app.state {
-        AppState::WaitingConfirmation { .. } => {
-            match key.code {
-                KeyCode::Char('y') | KeyCode::Char('Y') => {
-                    let old_state = std::mem::replace(&mut app.state, AppState::ProcessingTools);
-                    if let AppState::WaitingConfirmation { prompt: _, respond_to } = old_state {
-                        let _ = respond_to.send(true);
-                    }
-                }
-                KeyCode::Char('n') | KeyCode::Char('N') => {
-                    let old_state = std::mem::replace(&mut app.state, AppState::Idle);
-                    if let AppState::WaitingConfirmation { prompt: _, respond_to } = old_state {
-                        let _ = respond_to.send(false);
-                    }
-                }
-                KeyCode::Char('a') | KeyCode::Char('A') => {
-                    let old_state = std::mem::replace(&mut app.state, AppState::ProcessingTools);
-                    if let AppState::WaitingConfirmation { prompt: _, respond_to } = old_state {
-                        let _ = respond_to.send(true);
-                    }
-                    app.always_approve.store(true, Ordering::Relaxed);
-                }
-                _ => {}
+        AppState::WaitingConfirmation { scope, .. } => {
+            let answer = match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => Confirmation::Approve,
+                KeyCode::Char('n') | KeyCode::Char('N') => Confirmation::Reject,
+                KeyCode::Char('a') | KeyCode::Char('A') => Confirmation::ApproveTool,
+                KeyCode::Char('p') | KeyCode::Char('P') if scope.is_some() => Confirmation::ApproveScope,
+                _ => return Ok(()),
+            };
+            let next = if answer == Confirmation::Reject { AppState::Idle } else { AppState::ProcessingTools };
+            if let AppState::WaitingConfirmation { respond_to, .. } = std::mem::replace(&mut app.state, next) {
+                let _ = respond_to.send(answer);
             }
         }
         AppState::Streaming => {
@@ -185,26 +253,134 @@ pub(crate) async fn handle_key_event(
                     if input_lower == "/clear" {
                         app.messages.clear();
                         chat_manager.lock().await.create_chat();
+                        app.usage = Default::default();
                         app.scroll_offset = 0;
                         app.auto_scroll = true;
                         return Ok(());
                     }
 
+                    if input_lower == "/compact" {
+                        app.add_message(ChatMessage::Info {
+                            message: "Compacting conversation...".into(),
+                        });
+                        app.state = AppState::ProcessingTools;
+                        let tx_clone = tx.clone();
+                        let chat_manager = chat_manager.clone();
+                        tokio::spawn(async move {
+                            let result = crate::chat::compact_history(&chat_manager, true, debug)
+                                .await
+                                .map_err(|e| e.to_string());
+                            let _ = tx_clone.send(AppEvent::CompactionDone(result));
+                        });
+                        return Ok(());
+                    }
+
+                    if input_lower == "/profile" || input_lower.starts_with("/profile ") {
+                        let name = input_text["/profile".len()..].trim();
+                        let mut manager = chat_manager.lock().await;
+                        let message = if name.is_empty() {
+                            let names = manager.profile_names();
+                            if names.is_empty() {
+                                "No profiles configured. Add [profile.<name>] sections to ~/.aicli.conf.".to_string()
+                            } else {
+                                format!(
+                                    "Profiles: {} (active: {}). Use /profile <name>, or /profile none for the defaults.",
+                                    names.join(", "),
+                                    manager.profile_name().unwrap_or("none")
+                                )
+                            }
+                        } else {
+                            match manager.set_profile(Some(name).filter(|n| *n != "none")) {
+                                Ok(()) => {
+                                    app.model = manager.get_config().model.clone();
+                                    app.profile = manager.profile_name().map(|p| p.to_string());
+                                    app.profile_approved = manager.auto_approved_tools();
+                                    format!(
+                                        "Using profile {} (model {}).",
+                                        app.profile.as_deref().unwrap_or("none"),
+                                        app.model
+                                    )
+                                }
+                                Err(e) => e.to_string(),
+                            }
+                        };
+                        app.add_message(ChatMessage::Info { message });
+                        return Ok(());
+                    }
+
+                    if input_lower == "/permissions" || input_lower.starts_with("/permissions ") {
+                        let message = app.policy.approvals().command(&input_text["/permissions".len()..]);
+                        app.add_message(ChatMessage::Info { message });
+                        return Ok(());
+                    }
+
+                    if input_lower == "/usage" {
+                        let message = chat_manager.lock().await.usage().report();
+                        app.add_message(ChatMessage::Info { message });
+                        return Ok(());
+                    }
+
+                    if input_lower == "/instructions" {
+                        let message = chat_manager.lock().await.system_instruction().to_string();
+                        app.add_message(ChatMessage::Info { message });
+                        return Ok(());
+                    }
+
+                    if input_lower == "/models" || input_lower.starts_with("/models pull ") {
+                        let pull = input_text
+                            .get("/models pull ".len()..)
+                            .map(|name| name.trim().to_string())
+                            .filter(|name| !name.is_empty());
+                        app.state = AppState::ProcessingTools;
+                        let tx_clone = tx.clone();
+                        let provider = chat_manager.lock().await.provider();
+                        tokio::spawn(async move {
+                            if let Some(name) = pull {
+                                let progress_tx = tx_clone.clone();
+                                let progress = move |status: &str| {
+                                    let _ = progress_tx.send(AppEvent::Info(format!("Pull {}", status)));
+                                };
+                                if let Err(e) = provider.pull_model(&name, &progress).await {
+                                    let _ = tx_clone.send(AppEvent::ModelsDone(Err(format!("Failed to pull {}: {}", name, e))));
+                                    return;
+                                }
+                                let _ = tx_clone.send(AppEvent::Info(format!("Pulled {}.", name)));
+                            }
+                            let result = provider
+                                .list_models()
+                                .await
+                                .map(|models| models.iter().map(|m| m.to_string()).collect())
+                                .map_err(|e| format!("Failed to list models: {}", e));
+                            let _ = tx_clone.send(AppEvent::ModelsDone(result));
+                        });
+                        return Ok(());
+                    }
+
+                    if app.shell_attached && !input_text.starts_with('!') {
+                        send_to_shell_session(app, input_text, chat_manager, tx).await;
+                        return Ok(());
+                    }
+
                     if let Some(command) = input_text.strip_prefix('!') {
                         let command = command.trim();
                         if command.is_empty() {
-                            app.add_message(ChatMessage::Info {
-                                message: "Interactive shell mode is not available in TUI. Use !command syntax instead.".into(),
-                            });
+                            app.shell_attached = !app.shell_attached;
+                            let message = if app.shell_attached {
+                                "Attached to the shell session shared with the model. Input now goes to the shell; enter ! again to detach."
+                            } else {
+                                "Detached from the shell session. It keeps running until /clear."
+                            };
+                            app.add_message(ChatMessage::Info { message: message.into() });
                         } else {
-                            app.add_message(ChatMessage::Info {
-                                message: format!("Running: {}", command),
-                            });
                             app.state = AppState::ProcessingTools;
+                            app.cancel_stream.store(false, Ordering::Relaxed);
+                            let options = crate::command::CommandOptions::from_config(chat_manager.lock().await.get_config())
+                                .with_cancel(Some(app.cancel_stream.clone()))
+                                .with_observer(Some(Arc::new(TuiCommandObserver { tx: tx.clone() })));
                             let tx_clone = tx.clone();
                             let cmd_owned = command.to_string();
                             tokio::spawn(async move {
-                                let output = crate::command::execute_command(&cmd_owned, debug)
+                                let output = crate::command::execute_command(&cmd_owned, &options, debug)
                                     .await
                                     .unwrap_or_else(|e| e.to_string());
                                 let _ = tx_clone.send(AppEvent::ShellCommandDone {
@@ -225,14 +401,6 @@ pub(crate) async fn handle_key_event(
                         app.input = App::make_textarea();
                     }
                 }
-                KeyCode::PageUp => {
-                    app.auto_scroll = false;
-                    app.scroll_offset = app.scroll_offset.saturating_sub(10);
-                }
-                KeyCode::PageDown => {
-                    app.auto_scroll = false;
-                    app.scroll_offset = app.scroll_offset.saturating_add(10);
-                }
                 _ => {
                     app.input.input(key);
                 }
@@ -241,3 +409,33 @@ pub(crate) async fn handle_key_event(
     }
     Ok(())
 }
+
+/// Sends one line to the shared shell session and shows what comes back as a command block.
+async fn send_to_shell_session(
+    app: &mut App,
+    input: String,
+    chat_manager: &Arc<Mutex<ChatManager>>,
+    tx: &mpsc::UnboundedSender<AppEvent>,
+) {
+    app.state = AppState::ProcessingTools;
+    app.cancel_stream.store(false, Ordering::Relaxed);
+    let config = chat_manager.lock().await.get_config().clone();
+    let cancel = app.cancel_stream.clone();
+    let tx_clone = tx.clone();
+    tokio::spawn(async move {
+        let observer = Arc::new(TuiCommandObserver { tx: tx_clone.clone() });
+        let wait = Duration::from_secs(crate::shell_session::DEFAULT_WAIT_SECS);
+        observer.started(&input);
+        let result: Result<String> = async {
+            let session = crate::shell_session::shared_session(&config)?;
+            session.send(&input)?;
+            Ok(session.read(wait, Some(&cancel), Some(observer.clone())).await)
+        }
+        .await;
+        match result {
+            Ok(_) => observer.finished("shell session", true),
+            Err(e) => observer.finished(&e.to_string(), false),
+        }
+        let _ = tx_clone.send(AppEvent::ShellSessionDone);
+    });
+}
@@ -1,11 +1,10 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::collections::HashMap;
 use tokio::sync::{Mutex, mpsc};
-use serde_json::{json, Value};
 use futures::StreamExt;
 
 use crate::chat::{ChatManager, LlmCallData};
+use crate::provider::{StreamAccumulator, StreamEvent};
 
 use super::types::{App, AppEvent, AppState, ChatMessage, StreamResult};
 
@@ -26,15 +25,14 @@ pub(crate) async fn start_llm_call(
     app.state = AppState::Streaming;
     app.cancel_stream.store(false, Ordering::Relaxed);
 
-    let llm_data = {
-        let mut manager = chat_manager.lock().await;
-        manager.push_user_message(&user_input, debug);
-        manager.prepare_llm_call()
-    };
+    chat_manager.lock().await.push_user_message(&user_input, debug);
 
     let tx_clone = tx.clone();
     let cancel = app.cancel_stream.clone();
+    let chat_manager = chat_manager.clone();
     tokio::spawn(async move {
+        compact_if_needed(&chat_manager, &tx_clone, debug).await;
+        let llm_data = chat_manager.lock().await.prepare_llm_call();
         if let Some(result) = perform_streaming_call(llm_data, &tx_clone, &cancel, debug).await {
             let _ = tx_clone.send(AppEvent::LlmDone {
                 full_content: result.full_content,
@@ -45,51 +43,35 @@ pub(crate) async fn start_llm_call(
     });
 }
 
+/// Runs automatic history compaction before an LLM call, reporting it in the transcript.
+pub(crate) async fn compact_if_needed(
+    chat_manager: &Arc<Mutex<ChatManager>>,
+    tx: &mpsc::UnboundedSender<AppEvent>,
+    debug: bool,
+) {
+    match crate::chat::compact_history(chat_manager, false, debug).await {
+        Ok(Some(count)) => {
+            let _ = tx.send(AppEvent::Info(format!(
+                "Compacted {} earlier messages into a summary.",
+                count
+            )));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            crate::utils::log_to_file(debug, &format!("Automatic compaction failed: {}", e));
+        }
+    }
+}
+
 pub(crate) async fn perform_streaming_call(
     llm_data: LlmCallData,
     tx: &mpsc::UnboundedSender<AppEvent>,
     cancel: &Arc<AtomicBool>,
     debug: bool,
 ) -> Option<StreamResult> {
-    use async_openai::types::{
-        ChatCompletionRequestMessage,
-        ChatCompletionRequestSystemMessage,
-        ChatCompletionRequestSystemMessageContent,
-        CreateChatCompletionRequest,
-    };
-
-    let mut chat_messages: Vec<ChatCompletionRequestMessage> = vec![
-        ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
-            content: ChatCompletionRequestSystemMessageContent::Text(
-                llm_data.system_instruction.clone(),
-            ),
-            name: None,
-        }),
-    ];
-
-    for msg in &llm_data.history {
-        let message: ChatCompletionRequestMessage =
-            match serde_json::from_value(msg.clone()) {
-                Ok(m) => m,
-                Err(e) => {
-                    let _ = tx.send(AppEvent::LlmError(format!("Failed to parse message: {}", e)));
-                    return None;
-                }
-            };
-        chat_messages.push(message);
-    }
-
-    let request = CreateChatCompletionRequest {
-        model: llm_data.model.clone(),
-        messages: chat_messages,
-        tools: Some(llm_data.tools.clone()),
-        stream: Some(true),
-        ..Default::default()
-    };
+    crate::utils::log_to_file(debug, &format!("TUI: Starting streaming LLM call ({})...", llm_data.provider.name()));
 
-    crate::utils::log_to_file(debug, "TUI: Starting streaming LLM call...");
-
-    let stream = match llm_data.client.chat().create_stream(request).await {
+    let mut stream = match llm_data.provider.chat_stream(&llm_data).await {
         Ok(s) => s,
         Err(e) => {
             let _ = tx.send(AppEvent::LlmError(format!("Failed to start stream: {}", e)));
@@ -97,49 +79,19 @@ pub(crate) async fn perform_streaming_call(
         }
     };
 
-    let mut full_content = String::new();
-    let mut tool_calls_acc: HashMap<u32, (String, String, String, String)> =
-        HashMap::new();
-    let mut finish_reason: Option<String> = None;
-
-    let mut pinned = std::pin::pin!(stream);
+    let mut acc = StreamAccumulator::default();
 
-    while let Some(result) = pinned.next().await {
+    while let Some(result) = stream.next().await {
         if cancel.load(Ordering::Relaxed) {
             let _ = tx.send(AppEvent::LlmError("Stream cancelled by user.".into()));
             return None;
         }
         match result {
-            Ok(response) => {
-                for choice in &response.choices {
-                    let delta = &choice.delta;
-                    if let Some(content) = &delta.content {
-                        let _ = tx.send(AppEvent::LlmToken(content.clone()));
-                        full_content.push_str(content);
-                    }
-                    if let Some(tc_deltas) = &delta.tool_calls {
-                        for tc_delta in tc_deltas {
-                            let idx = tc_delta.index as u32;
-                            let entry = tool_calls_acc.entry(idx).or_insert_with(|| {
-                                (String::new(), String::new(), String::new(), String::new())
-                            });
-                            if let Some(id) = &tc_delta.id {
-                                entry.0 = id.clone();
-                            }
-                            if let Some(ref func) = tc_delta.function {
-                                if let Some(name) = &func.name {
-                                    entry.1 = name.clone();
-                                }
-                                if let Some(args) = &func.arguments {
-                                    entry.3.push_str(args);
-                                }
-                            }
-                        }
-                    }
-                    if let Some(reason) = &choice.finish_reason {
-                        finish_reason = Some(format!("{:?}", reason));
-                    }
+            Ok(event) => {
+                if let StreamEvent::Token(content) = &event {
+                    let _ = tx.send(AppEvent::LlmToken(content.clone()));
                 }
+                acc.push(&event);
             }
             Err(e) => {
                 let _ = tx.send(AppEvent::LlmError(format!("Stream error: {}", e)));
@@ -152,50 +104,13 @@ pub(crate) async fn perform_streaming_call(
         debug,
         &format!(
             "TUI: Stream completed, {} content chars, {} tool calls",
-            full_content.len(),
-            tool_calls_acc.len()
+            acc.content.len(),
+            acc.tool_call_count()
         ),
     );
 
-    let mut tool_calls_json = Vec::new();
-    for (_, (id, name, _, raw_args)) in &tool_calls_acc {
-        let args_map: serde_json::Map<String, Value> = serde_json::from_str(raw_args).unwrap_or_default();
-        let args_json = serde_json::Value::Object(args_map);
-        tool_calls_json.push(json!({
-            "id": id,
-            "type": "function",
-            "function": {
-                "name": name,
-                "arguments": serde_json::to_string(&args_json).unwrap_or_default()
-            }
-        }));
-    }
-
-    let content_value = if full_content.is_empty() {
-        Value::Null
-    } else {
-        json!(full_content)
-    };
-
-    let msg_for_history = if !tool_calls_json.is_empty() {
-        json!({
-            "role": "assistant",
-            "content": content_value,
-            "tool_calls": tool_calls_json
-        })
-    } else {
-        json!({
-            "role": "assistant",
-            "content": content_value
-        })
-    };
-
-    let full_response = json!({
-        "choices": [{
-            "message": msg_for_history,
-            "finish_reason": finish_reason.unwrap_or_else(|| "stop".to_string())
-        }]
-    });
+    let full_content = acc.content.clone();
+    let (msg_for_history, full_response) = acc.finish();
 
     Some(StreamResult {
         full_content,
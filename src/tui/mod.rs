@@ -5,13 +5,11 @@ mod event;
 mod llm;
 mod tools;
 
-pub(crate) use theme::*;
 pub(crate) use types::*;
 pub(crate) use render::*;
 pub(crate) use event::*;
 
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
 use std::time::Duration;
 use tokio::sync::{Mutex, mpsc};
 use tokio::time;
@@ -19,20 +17,27 @@ use anyhow::Result;
 use crossterm::event as crossterm_event;
 
 use crate::chat::ChatManager;
+use crate::tools::ToolPolicy;
 
 pub async fn run_tui(
     chat_manager: Arc<Mutex<ChatManager>>,
     debug: bool,
-    always_approve: Arc<AtomicBool>,
+    policy: ToolPolicy,
 ) -> Result<()> {
     let mut terminal = init_terminal()?;
 
     let (tx, mut rx) = mpsc::unbounded_channel::<AppEvent>();
     let tx_input = tx.clone();
 
-    let model_name = {
+    let (model_name, profile, profile_approved, history, session_id) = {
         let manager = chat_manager.lock().await;
-        manager.get_config().model.clone()
+        (
+            manager.get_config().model.clone(),
+            manager.profile_name().map(|p| p.to_string()),
+            manager.auto_approved_tools(),
+            manager.get_history().clone(),
+            manager.session_id().to_string(),
+        )
     };
 
     tokio::task::spawn_blocking(move || {
@@ -47,7 +52,15 @@ pub async fn run_tui(
         }
     });
 
-    let mut app = App::new(model_name, always_approve);
+    let mut app = App::new(model_name, policy);
+    app.profile = profile;
+    app.profile_approved = profile_approved;
+    if !history.is_empty() {
+        app.load_history(&history);
+        app.add_message(ChatMessage::Info {
+            message: format!("Resumed session {} ({} messages)", session_id, history.len()),
+        });
+    }
 
     let mut tick_interval = time::interval(Duration::from_millis(80));
 
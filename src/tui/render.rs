@@ -23,6 +23,10 @@ fn truncate_str(s: &str, max_chars: usize) -> String {
     }
 }
 
+/// Output lines shown for a collapsed command block, while running and once finished.
+const LIVE_OUTPUT_LINES: usize = 8;
+const COLLAPSED_OUTPUT_LINES: usize = 3;
+
 // ── Root render ──────────────────────────────────────────────────────────────
 
 pub(crate) fn render(f: &mut Frame, app: &mut App) {
@@ -187,6 +191,58 @@ fn render_chat(f: &mut Frame, app: &mut App, theme: &Theme, area: Rect) {
                         theme.info_style(),
                     )));
                 }
+                ChatMessage::CommandOutput {
+                    command,
+                    lines: output,
+                    dropped,
+                    status,
+                    failed,
+                    expanded,
+                } => {
+                    lines.push(Line::raw(""));
+                    let (marker, marker_style, state) = match status {
+                        None => (
+                            spinner_frame(app.tick_counter).to_string(),
+                            theme.accent_style(),
+                            "running".to_string(),
+                        ),
+                        Some(s) if *failed => ("\u{2717}".to_string(), theme.error_style(), s.clone()),
+                        Some(s) => ("\u{2713}".to_string(), theme.success_style(), s.clone()),
+                    };
+                    lines.push(Line::from(vec![
+                        Span::styled(format!("  {marker} "), marker_style),
+                        Span::styled(
+                            format!("$ {}", truncate_str(command, width.saturating_sub(40))),
+                            theme.text_style(),
+                        ),
+                        Span::styled(
+                            format!("  {state} \u{00b7} {} lines", output.len() + dropped),
+                            theme.muted_style(),
+                        ),
+                    ]));
+
+                    let shown = match (expanded, status) {
+                        (true, _) => output.len(),
+                        (false, None) => LIVE_OUTPUT_LINES,
+                        (false, Some(_)) => COLLAPSED_OUTPUT_LINES,
+                    }
+                    .min(output.len());
+                    let hidden = output.len() - shown + dropped;
+                    if hidden > 0 {
+                        let hint = if *expanded {
+                            format!("    \u{22ef} {hidden} earlier lines dropped")
+                        } else {
+                            format!("    \u{22ef} {hidden} more lines \u{00b7} Ctrl+O to expand")
+                        };
+                        lines.push(Line::from(Span::styled(hint, theme.muted_style())));
+                    }
+                    for ol in output.iter().skip(output.len() - shown) {
+                        lines.push(Line::from(vec![
+                            Span::styled("    \u{2502} ", Style::default().fg(theme.border)),
+                            Span::styled(ol.clone(), Style::default().fg(theme.text_muted)),
+                        ]));
+                    }
+                }
             }
         }
     }
@@ -243,17 +299,10 @@ fn render_markdown(content: &str, _max_width: usize, theme: &Theme) -> Vec<Line<
             continue;
         }
 
-        if raw_line.starts_with("### ") {
-            lines.push(Line::from(Span::styled(
-                raw_line.to_string(),
-                theme.accent_style().add_modifier(Modifier::BOLD),
-            )));
-        } else if raw_line.starts_with("## ") {
-            lines.push(Line::from(Span::styled(
-                raw_line.to_string(),
-                theme.accent_style().add_modifier(Modifier::BOLD),
-            )));
-        } else if raw_line.starts_with("# ") {
+        if raw_line.starts_with("### ")
+            || raw_line.starts_with("## ")
+            || raw_line.starts_with("# ")
+        {
             lines.push(Line::from(Span::styled(
                 raw_line.to_string(),
                 theme.accent_style().add_modifier(Modifier::BOLD),
@@ -277,10 +326,9 @@ fn render_markdown(content: &str, _max_width: usize, theme: &Theme) -> Vec<Line<
 
 fn render_inline_code(line: &str, theme: &Theme) -> Line<'static> {
     let mut spans: Vec<Span<'static>> = Vec::new();
-    let mut parts = line.split('`');
     let mut in_code = false;
 
-    while let Some(part) = parts.next() {
+    for part in line.split('`') {
         if in_code {
             spans.push(Span::styled(
                 format!("`{part}`"),
@@ -312,18 +360,22 @@ fn render_input(f: &mut Frame, app: &mut App, theme: &Theme, area: Rect) {
     };
 
     match &app.state {
-        AppState::WaitingConfirmation { prompt, .. } => {
+        AppState::WaitingConfirmation { prompt, scope, .. } => {
+            let mut choices = vec![
+                Span::styled("  [y] Yes  ", theme.success_style()),
+                Span::styled("  [n] No  ", theme.error_style()),
+                Span::styled("  [a] Always for this tool", theme.muted_style()),
+            ];
+            if let Some(scope) = scope {
+                choices.push(Span::styled(format!("  [p] Always for {scope}"), theme.muted_style()));
+            }
             let lines = vec![
                 Line::from(Span::styled(
                     format!("\u{2503} {prompt}"),
                     Style::default().fg(theme.warning),
                 )),
                 Line::raw(""),
-                Line::from(vec![
-                    Span::styled("  [y] Yes  ", theme.success_style()),
-                    Span::styled("  [n] No  ", theme.error_style()),
-                    Span::styled("  [a] Always approve", theme.muted_style()),
-                ]),
+                Line::from(choices),
             ];
             let block = Block::default()
                 .borders(Borders::LEFT)
@@ -407,15 +459,31 @@ fn render_status_bar(f: &mut Frame, app: &App, theme: &Theme, area: Rect) {
             ),
             Span::styled(" Processing", theme.accent_style()),
         ],
+        AppState::Idle if app.shell_attached => vec![
+            Span::styled("Shell session", theme.warning_style()),
+            Span::styled(
+                "  \u{2022}  ! to detach  \u{2022}  Ctrl+D quit",
+                Style::default().fg(theme.text_muted),
+            ),
+        ],
         AppState::Idle => {
             let msg_count = app.messages.len();
-            vec![
+            let auto_approved = app.tool_policy().auto_approved_tools();
+            let mut spans = Vec::new();
+            if !auto_approved.is_empty() {
+                spans.push(Span::styled(
+                    format!("Auto-approve: {}  \u{2022}  ", truncate_str(&auto_approved.join(", "), 40)),
+                    theme.warning_style(),
+                ));
+            }
+            spans.extend([
                 Span::styled(format!("{msg_count} messages"), theme.muted_style()),
                 Span::styled(
                     "  \u{2022}  Enter to send  \u{2022}  Ctrl+D quit",
                     Style::default().fg(theme.text_muted),
                 ),
-            ]
+            ]);
+            spans
         }
         AppState::WaitingConfirmation { .. } => {
             vec![Span::styled("Awaiting confirmation", theme.warning_style())]
@@ -467,6 +535,55 @@ fn render_sidebar(f: &mut Frame, app: &App, theme: &Theme, area: Rect) {
         Line::raw(""),
     ];
 
+    if let Some(profile) = &app.profile {
+        lines.insert(3, Line::from(Span::styled(format!("  Profile: {}", profile), theme.muted_style())));
+    }
+
+    if let Some(timing) = &app.last_timing {
+        lines.push(Line::from(Span::styled("  Last response", theme.muted_style())));
+        for part in timing.split(" \u{00b7} ") {
+            lines.push(Line::from(Span::styled(format!("  {}", part), theme.muted_style())));
+        }
+        lines.push(Line::raw(""));
+    }
+
+    let usage = &app.usage;
+    if usage.session.requests > 0 {
+        let tokens = |u: &crate::usage::Usage| format!("{} in \u{00b7} {} out", u.prompt_tokens, u.completion_tokens);
+        lines.push(Line::from(Span::styled("  Tokens", theme.muted_style())));
+        lines.push(Line::from(Span::styled(format!("  Turn: {}", tokens(&usage.turn)), theme.muted_style())));
+        lines.push(Line::from(Span::styled(format!("  Session: {}", tokens(&usage.session)), theme.muted_style())));
+        if usage.session.cached_tokens > 0 {
+            lines.push(Line::from(Span::styled(
+                format!("  Cached: {}", usage.session.cached_tokens),
+                theme.muted_style(),
+            )));
+        }
+        if let Some(cost) = usage.session.cost {
+            lines.push(Line::from(Span::styled(format!("  Cost: ${:.4}", cost), theme.muted_style())));
+        }
+        lines.push(Line::raw(""));
+    }
+
+    let jobs: Vec<_> = crate::jobs::list_jobs().into_iter().filter(|j| j.is_running()).collect();
+    if !jobs.is_empty() {
+        lines.push(Line::from(Span::styled("  Jobs", theme.muted_style())));
+        for job in &jobs {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  #{} ", job.id), theme.accent_style()),
+                Span::styled(
+                    format!(
+                        "{} ({}s)",
+                        truncate_str(&job.command, (area.width as usize).saturating_sub(16)),
+                        job.elapsed.as_secs()
+                    ),
+                    theme.muted_style(),
+                ),
+            ]));
+        }
+        lines.push(Line::raw(""));
+    }
+
     // State indicator
     match &app.state {
         AppState::Streaming => {
@@ -496,7 +613,7 @@ fn render_sidebar(f: &mut Frame, app: &App, theme: &Theme, area: Rect) {
     }
 
     // Fill remaining space, then add version footer
-    let remaining = area.height as usize - lines.len();
+    let remaining = (area.height as usize).saturating_sub(lines.len());
     for _ in 0..remaining.saturating_sub(1) {
         lines.push(Line::raw(""));
     }
@@ -16,6 +16,7 @@ pub(crate) struct Theme {
     pub(crate) warning: Color,            // #f5a742
     pub(crate) info: Color,               // #56b6c2
     pub(crate) border: Color,             // #484848
+    #[allow(dead_code)]
     pub(crate) border_active: Color,      // #606060
 }
 
@@ -72,6 +73,7 @@ impl Theme {
         Style::default().fg(self.info)
     }
 
+    #[allow(dead_code)]
     pub(crate) fn panel_block<'a>(&self, title: Option<&'a str>) -> Block<'a> {
         let mut block = Block::default()
             .borders(Borders::NONE)
@@ -5,11 +5,19 @@ use ratatui::{
     Terminal as RatatuiTerminal,
 };
 use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use tokio::sync::oneshot;
 use tui_textarea::TextArea;
 
+use crate::chat::SUMMARY_PREFIX;
+use crate::tools::{Confirmation, ToolPolicy};
+use crate::usage::UsageTotals;
+
+/// Lines kept per command output block; older lines are dropped and counted.
+const MAX_COMMAND_LINES: usize = 5000;
+
 pub(crate) enum ChatMessage {
     User { content: String },
     Assistant { content: String, is_streaming: bool },
@@ -17,6 +25,16 @@ pub(crate) enum ChatMessage {
     ToolResult { name: String, result: String },
     Error { message: String },
     Info { message: String },
+    /// Live output of a command, collapsed to its last lines unless expanded
+    CommandOutput {
+        command: String,
+        lines: VecDeque<String>,
+        dropped: usize,
+        /// Final status such as `exit code 0`; `None` while running
+        status: Option<String>,
+        failed: bool,
+        expanded: bool,
+    },
 }
 
 pub(crate) enum AppState {
@@ -24,7 +42,9 @@ pub(crate) enum AppState {
     Streaming,
     WaitingConfirmation {
         prompt: String,
-        respond_to: oneshot::Sender<bool>,
+        /// What `p` approves for the session, e.g. "`cargo test` commands"
+        scope: Option<String>,
+        respond_to: oneshot::Sender<Confirmation>,
     },
     ProcessingTools,
 }
@@ -40,7 +60,9 @@ pub(crate) enum AppEvent {
     LlmError(String),
     NeedConfirmation {
         prompt: String,
-        respond_to: oneshot::Sender<bool>,
+        /// What `p` approves for the session, e.g. "`cargo test` commands"
+        scope: Option<String>,
+        respond_to: oneshot::Sender<Confirmation>,
     },
     ToolDone {
         name: String,
@@ -58,6 +80,16 @@ pub(crate) enum AppEvent {
         command: String,
         output: String,
     },
+    ShellSessionDone,
+    Info(String),
+    CommandStarted(String),
+    CommandLine(String),
+    CommandFinished {
+        status: String,
+        success: bool,
+    },
+    CompactionDone(Result<Option<usize>, String>),
+    ModelsDone(Result<Vec<String>, String>),
 }
 
 pub(crate) struct App {
@@ -67,14 +99,25 @@ pub(crate) struct App {
     pub(crate) auto_scroll: bool,
     pub(crate) input: TextArea<'static>,
     pub(crate) model: String,
+    /// Active `[profile.<name>]`, if any
+    pub(crate) profile: Option<String>,
     pub(crate) should_quit: bool,
-    pub(crate) always_approve: Arc<AtomicBool>,
+    /// Approval policy from the command line, with the approvals remembered this session
+    pub(crate) policy: ToolPolicy,
+    /// `auto_approve` tools of the active profile, added to `policy`
+    pub(crate) profile_approved: HashSet<String>,
     pub(crate) cancel_stream: Arc<AtomicBool>,
     pub(crate) tick_counter: u32,
+    /// Load/eval timing of the last response, for providers that report it
+    pub(crate) last_timing: Option<String>,
+    /// Token counts shown in the sidebar, refreshed after each response
+    pub(crate) usage: UsageTotals,
+    /// Input goes to the shared shell session instead of the model (toggled by `!`)
+    pub(crate) shell_attached: bool,
 }
 
 impl App {
-    pub(crate) fn new(model: String, always_approve: Arc<AtomicBool>) -> Self {
+    pub(crate) fn new(model: String, policy: ToolPolicy) -> Self {
         App {
             messages: Vec::new(),
             state: AppState::Idle,
@@ -82,13 +125,23 @@ impl App {
             auto_scroll: true,
             input: Self::make_textarea(),
             model,
+            profile: None,
             should_quit: false,
-            always_approve,
+            policy,
+            profile_approved: HashSet::new(),
             cancel_stream: Arc::new(AtomicBool::new(false)),
             tick_counter: 0,
+            last_timing: None,
+            usage: UsageTotals::default(),
+            shell_attached: false,
         }
     }
 
+    /// The policy tool calls run under: the command line's plus the active profile's.
+    pub(crate) fn tool_policy(&self) -> ToolPolicy {
+        self.policy.with_allowed(&self.profile_approved)
+    }
+
     pub(crate) fn make_textarea() -> TextArea<'static> {
         let mut ta = TextArea::default();
         ta.set_style(Style::default().fg(Color::White));
@@ -112,25 +165,121 @@ impl App {
     }
 
     pub(crate) fn append_to_streaming_message(&mut self, token: &str) {
-        if let Some(last) = self.messages.last_mut() {
-            if let ChatMessage::Assistant { content, .. } = last {
-                content.push_str(token);
-                self.auto_scroll = true;
+        if let Some(ChatMessage::Assistant { content, .. }) = self.messages.last_mut() {
+            content.push_str(token);
+            self.auto_scroll = true;
+        }
+    }
+
+    pub(crate) fn command_started(&mut self, command: &str) {
+        self.add_message(ChatMessage::CommandOutput {
+            command: command.to_string(),
+            lines: VecDeque::new(),
+            dropped: 0,
+            status: None,
+            failed: false,
+            expanded: false,
+        });
+    }
+
+    /// The block of the command still running, if any.
+    fn running_command(&mut self) -> Option<&mut ChatMessage> {
+        self.messages
+            .iter_mut()
+            .rev()
+            .find(|msg| matches!(msg, ChatMessage::CommandOutput { status: None, .. }))
+    }
+
+    pub(crate) fn command_line(&mut self, line: String) {
+        if let Some(ChatMessage::CommandOutput { lines, dropped, .. }) = self.running_command() {
+            lines.push_back(line);
+            if lines.len() > MAX_COMMAND_LINES {
+                lines.pop_front();
+                *dropped += 1;
             }
         }
     }
 
-    pub(crate) fn finalize_streaming_message(&mut self, full_content: &str) {
-        if let Some(last) = self.messages.last_mut() {
-            if let ChatMessage::Assistant {
-                content,
-                is_streaming,
-            } = last
-            {
-                *content = full_content.to_string();
-                *is_streaming = false;
+    pub(crate) fn command_finished(&mut self, final_status: String, success: bool) {
+        if let Some(ChatMessage::CommandOutput { status, failed, .. }) = self.running_command() {
+            *status = Some(final_status);
+            *failed = !success;
+        }
+    }
+
+    /// Expands or collapses the most recent command output block.
+    pub(crate) fn toggle_command_output(&mut self) {
+        let last = self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|msg| matches!(msg, ChatMessage::CommandOutput { .. }));
+        if let Some(ChatMessage::CommandOutput { expanded, .. }) = last {
+            *expanded = !*expanded;
+        }
+    }
+
+    /// Rebuild the visible transcript from a (resumed) conversation history.
+    pub(crate) fn load_history(&mut self, history: &[Value]) {
+        let mut tool_names: HashMap<String, String> = HashMap::new();
+        for msg in history {
+            let content = msg.get("content").and_then(|c| c.as_str()).unwrap_or("");
+            match msg.get("role").and_then(|r| r.as_str()) {
+                Some("user") if content.starts_with(SUMMARY_PREFIX) => {
+                    self.messages.push(ChatMessage::Info {
+                        message: "Earlier messages were compacted into a summary.".into(),
+                    })
+                }
+                Some("user") => self.messages.push(ChatMessage::User {
+                    content: content.to_string(),
+                }),
+                Some("assistant") => {
+                    if !content.is_empty() {
+                        self.messages.push(ChatMessage::Assistant {
+                            content: content.to_string(),
+                            is_streaming: false,
+                        });
+                    }
+                    let tool_calls = msg.get("tool_calls").and_then(|tc| tc.as_array());
+                    for tc in tool_calls.into_iter().flatten() {
+                        let id = tc.get("id").and_then(|i| i.as_str()).unwrap_or("");
+                        let func = tc.get("function");
+                        let name = func
+                            .and_then(|f| f.get("name"))
+                            .and_then(|n| n.as_str())
+                            .unwrap_or("")
+                            .to_string();
+                        let args = func
+                            .and_then(|f| f.get("arguments"))
+                            .and_then(|a| a.as_str())
+                            .unwrap_or("")
+                            .to_string();
+                        tool_names.insert(id.to_string(), name.clone());
+                        self.messages.push(ChatMessage::ToolCall { name, args });
+                    }
+                }
+                Some("tool") => {
+                    let id = msg.get("tool_call_id").and_then(|i| i.as_str()).unwrap_or("");
+                    self.messages.push(ChatMessage::ToolResult {
+                        name: tool_names.get(id).cloned().unwrap_or_default(),
+                        result: content.to_string(),
+                    });
+                }
+                _ => {}
             }
         }
+        self.auto_scroll = true;
+    }
+
+    pub(crate) fn finalize_streaming_message(&mut self, full_content: &str) {
+        if let Some(ChatMessage::Assistant {
+            content,
+            is_streaming,
+        }) = self.messages.last_mut()
+        {
+            *content = full_content.to_string();
+            *is_streaming = false;
+        }
     }
 }
 
@@ -0,0 +1,201 @@
+//! Token usage counting per turn and per session, with cost estimates from the
+//! `[prices]` config table.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Token counts for one or more requests. Cached prompt tokens are included in
+/// `prompt_tokens`, as in the OpenAI `usage` object.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Usage {
+    pub(crate) prompt_tokens: u64,
+    pub(crate) completion_tokens: u64,
+    pub(crate) cached_tokens: u64,
+    pub(crate) requests: u32,
+    /// Estimated cost in USD; `None` once any counted request had no price
+    pub(crate) cost: Option<f64>,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Usage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            cached_tokens: 0,
+            requests: 0,
+            cost: Some(0.0),
+        }
+    }
+}
+
+impl Usage {
+    /// Reads the OpenAI-shaped `usage` object every provider puts on its responses.
+    pub(crate) fn from_response(response: &Value) -> Option<Usage> {
+        let usage = response.get("usage").filter(|u| u.is_object())?;
+        let count = |value: Option<&Value>| value.and_then(|v| v.as_u64()).unwrap_or(0);
+        Some(Usage {
+            prompt_tokens: count(usage.get("prompt_tokens")),
+            completion_tokens: count(usage.get("completion_tokens")),
+            cached_tokens: count(usage.pointer("/prompt_tokens_details/cached_tokens")),
+            requests: 1,
+            cost: None,
+        })
+    }
+
+    fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cached_tokens += other.cached_tokens;
+        self.requests += other.requests;
+        self.cost = self.cost.zip(other.cost).map(|(a, b)| a + b);
+    }
+
+    /// e.g. `1.2k in (800 cached) · 340 out · $0.0021`
+    pub(crate) fn summary(&self) -> String {
+        let mut parts = vec![format!("{} in", format_tokens(self.prompt_tokens))];
+        if self.cached_tokens > 0 {
+            parts[0].push_str(&format!(" ({} cached)", format_tokens(self.cached_tokens)));
+        }
+        parts.push(format!("{} out", format_tokens(self.completion_tokens)));
+        if let Some(cost) = self.cost.filter(|_| self.requests > 0) {
+            parts.push(format!("${:.4}", cost));
+        }
+        parts.join(" \u{00b7} ")
+    }
+}
+
+fn format_tokens(count: u64) -> String {
+    if count >= 1_000_000 {
+        format!("{:.1}M", count as f64 / 1_000_000.0)
+    } else if count >= 1_000 {
+        format!("{:.1}k", count as f64 / 1_000.0)
+    } else {
+        count.to_string()
+    }
+}
+
+/// USD per million tokens for one model, written as `input, output[, cached_input]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Price {
+    pub(crate) input: f64,
+    pub(crate) output: f64,
+    /// Defaults to the input price
+    pub(crate) cached_input: f64,
+}
+
+impl Price {
+    pub(crate) fn parse(value: &str) -> Result<Price> {
+        let numbers = value
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| anyhow!("Invalid price '{}': {}", value, e))?;
+        match numbers[..] {
+            [input, output] => Ok(Price { input, output, cached_input: input }),
+            [input, output, cached_input] => Ok(Price { input, output, cached_input }),
+            _ => Err(anyhow!("Invalid price '{}': expected input, output[, cached_input]", value)),
+        }
+    }
+
+    pub(crate) fn cost(&self, usage: &Usage) -> f64 {
+        let uncached = usage.prompt_tokens.saturating_sub(usage.cached_tokens);
+        (uncached as f64 * self.input
+            + usage.cached_tokens as f64 * self.cached_input
+            + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// Finds the price for a model: an exact (case-insensitive) match, otherwise the
+/// longest entry the model name starts with, so `gpt-4o` also covers dated snapshots.
+/// Entries are validated when the config is loaded.
+pub(crate) fn price_for(prices: &BTreeMap<String, String>, model: &str) -> Option<Price> {
+    let model = model.to_lowercase();
+    prices
+        .iter()
+        .filter(|(name, _)| model.starts_with(&name.to_lowercase()))
+        .max_by_key(|(name, _)| name.len())
+        .and_then(|(_, value)| Price::parse(value).ok())
+}
+
+/// Usage of the current turn (since the last user message) and of the whole session.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct UsageTotals {
+    pub(crate) turn: Usage,
+    pub(crate) session: Usage,
+}
+
+impl UsageTotals {
+    pub(crate) fn start_turn(&mut self) {
+        self.turn = Usage::default();
+    }
+
+    pub(crate) fn record(&mut self, mut usage: Usage, price: Option<&Price>) {
+        usage.cost = price.map(|p| p.cost(&usage));
+        self.turn.add(&usage);
+        self.session.add(&usage);
+    }
+
+    /// Multi-line report for `/usage` and the debug log.
+    pub(crate) fn report(&self) -> String {
+        let line = |label: &str, usage: &Usage| {
+            let requests = if usage.requests == 1 { "request" } else { "requests" };
+            format!("{}: {} ({} {})", label, usage.summary(), usage.requests, requests)
+        };
+        let mut report = format!("{}\n{}", line("Last turn", &self.turn), line("Session", &self.session));
+        if self.session.requests > 0 && self.session.cost.is_none() {
+            report.push_str("\nNo price configured for this model; add it to [prices] for a cost estimate.");
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_response_reads_cached_tokens() {
+        let response = json!({"usage": {"prompt_tokens": 1200, "completion_tokens": 30, "total_tokens": 1230,
+            "prompt_tokens_details": {"cached_tokens": 1000}}});
+        let usage = Usage::from_response(&response).unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.cached_tokens), (1200, 30, 1000));
+        assert!(Usage::from_response(&json!({"choices": []})).is_none());
+    }
+
+    #[test]
+    fn test_price_lookup_and_parse() {
+        let prices = BTreeMap::from([
+            ("gpt-4o".to_string(), "2.5, 10, 1.25".to_string()),
+            ("gpt-4o-mini".to_string(), "0.15,0.6".to_string()),
+        ]);
+        let price = price_for(&prices, "GPT-4o-2024-08-06").unwrap();
+        assert_eq!(price, Price { input: 2.5, output: 10.0, cached_input: 1.25 });
+        let mini = price_for(&prices, "gpt-4o-mini").unwrap();
+        assert_eq!(mini.cached_input, 0.15);
+        assert!(price_for(&prices, "claude-sonnet-4").is_none());
+        assert!(Price::parse("1").is_err());
+        assert!(Price::parse("1,x").is_err());
+    }
+
+    #[test]
+    fn test_totals_accumulate_per_turn_and_session() {
+        let price = Price { input: 2.0, output: 8.0, cached_input: 0.5 };
+        let usage = Usage { prompt_tokens: 1_000_000, cached_tokens: 500_000, completion_tokens: 100_000, requests: 1, cost: None };
+        let mut totals = UsageTotals::default();
+        totals.record(usage, Some(&price));
+        totals.start_turn();
+        totals.record(usage, Some(&price));
+        assert_eq!(totals.turn.requests, 1);
+        assert_eq!(totals.session.prompt_tokens, 2_000_000);
+        // 0.5M uncached * $2 + 0.5M cached * $0.5 + 0.1M out * $8 = $2.05 per request
+        assert!((totals.session.cost.unwrap() - 4.1).abs() < 1e-9);
+        assert_eq!(totals.turn.summary(), "1.0M in (500.0k cached) \u{00b7} 100.0k out \u{00b7} $2.0500");
+
+        totals.record(usage, None);
+        assert!(totals.session.cost.is_none());
+        assert!(totals.report().contains("No price configured"));
+    }
+}
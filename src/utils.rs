@@ -68,27 +68,49 @@ pub fn summarize_text(text: &str, num_sentences: usize) -> String {
         .join(" ")
 }
 
-/// Prompts user with y/n/a options (yes/no/always)
-/// Returns (confirmed, always) tuple where:
-/// - confirmed: true if user selected yes or always
-/// - always: true if user selected always (approve all commands for this session)
-pub fn confirm_with_always(prompt: &str) -> (bool, bool) {
+/// An answer to `confirm_with_always`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmAnswer {
+    Yes,
+    No,
+    /// Yes, and for every later request of this kind
+    Always,
+    /// Yes, and for later requests within the offered scope
+    AlwaysScoped,
+}
+
+/// Prompts user with y/n/a options (yes/no/always), plus p (always for `scope`, e.g.
+/// "`cargo test` commands") when a scope is offered.
+/// End of input or a read error counts as no.
+pub fn confirm_with_always(prompt: &str, scope: Option<&str>) -> ConfirmAnswer {
     use std::io::{self, Write};
 
     loop {
-        print!("{} [y/n/a]: ", prompt);
+        if scope.is_some() {
+            print!("{} [y/n/a/p]: ", prompt);
+        } else {
+            print!("{} [y/n/a]: ", prompt);
+        }
         io::stdout().flush().unwrap();
 
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+        if !matches!(io::stdin().read_line(&mut input), Ok(n) if n > 0) {
+            println!();
+            return ConfirmAnswer::No;
+        }
 
         match input.trim().to_lowercase().as_str() {
-            "y" | "yes" => return (true, false),
-            "n" | "no" => return (false, false),
-            "a" | "always" => return (true, true),
-            _ => {
-                println!("Please enter 'y' for yes, 'n' for no, or 'a' for always.");
-            }
+            "y" | "yes" => return ConfirmAnswer::Yes,
+            "n" | "no" => return ConfirmAnswer::No,
+            "a" | "always" => return ConfirmAnswer::Always,
+            "p" if scope.is_some() => return ConfirmAnswer::AlwaysScoped,
+            _ => match scope {
+                Some(scope) => println!(
+                    "Please enter 'y' for yes, 'n' for no, 'a' for always, or 'p' for always for {}.",
+                    scope
+                ),
+                None => println!("Please enter 'y' for yes, 'n' for no, or 'a' for always."),
+            },
         }
     }
 }
@@ -158,7 +180,7 @@ mod tests {
     #[test]
     fn test_get_opt_bool_missing() {
         let args = json!({});
-        assert_eq!(get_opt_bool(&args, "key", true), true);
+        assert!(get_opt_bool(&args, "key", true));
     }
 
     #[test]